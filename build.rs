@@ -0,0 +1,34 @@
+use std::process::Command;
+
+/// Run a command and return its first line of output, if it ran and succeeded
+fn first_line(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    String::from_utf8(output.stdout).ok()?.lines().next().map(|line| line.trim().to_string())
+}
+
+/// Embed what --version-json reports: the commit ring was built from (`unknown` outside a git
+/// checkout, with `-dirty` when there were uncommitted changes), the compiler and the target
+fn main() {
+    let hash = first_line("git", &["rev-parse", "--short=12", "HEAD"]).unwrap_or_else(|| "unknown".to_string());
+    let dirty = first_line("git", &["status", "--porcelain", "--untracked-files=no"]).is_some_and(|line| !line.is_empty());
+    println!("cargo:rustc-env=GIT_HASH={}{}", hash, if dirty { "-dirty" } else { "" });
+
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let rustc_version = first_line(&rustc, &["--version"]).unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=RUSTC_VERSION={}", rustc_version);
+    println!("cargo:rustc-env=TARGET={}", std::env::var("TARGET").unwrap_or_default());
+
+    // A new commit moves HEAD or the branch it points at, staging changes the index, and editing
+    // the source can make the tree dirty. Cargo reruns the script on every build for a path that
+    // doesn't exist, so only existing ones are watched.
+    let branch = first_line("git", &["symbolic-ref", "-q", "HEAD"]).map(|branch| format!(".git/{}", branch));
+    let watched = [Some(".git/HEAD".to_string()), Some(".git/index".to_string()), branch, Some("src".to_string())];
+    for path in watched.into_iter().flatten() {
+        if std::path::Path::new(&path).exists() {
+            println!("cargo:rerun-if-changed={}", path);
+        }
+    }
+}
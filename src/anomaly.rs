@@ -0,0 +1,84 @@
+use crate::scoped::ScopedIp;
+use crate::{icons, HostResult};
+use colored::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Outlier ports listed by name before the rest are summarised as "+N more"
+const MAX_LISTED: usize = 5;
+
+/// A host whose ports disagree: most up and a few not, or the other way round
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Anomaly {
+    pub host: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resolved_addr: Option<ScopedIp>,
+    pub test_type: String,
+    /// The ports that disagree with the majority (with a tie, the ones that aren't up)
+    pub outliers: Vec<u16>,
+    /// e.g. "mostly up but 8080 down"
+    pub message: String,
+}
+
+/// `22, 80 up` or `8080 down, 9090 filtered`, with long lists cut short
+fn describe(results: &[&HostResult]) -> String {
+    let mut by_status: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+    for result in results.iter().take(MAX_LISTED) {
+        let port = result.port.map(|p| p.to_string()).unwrap_or_default();
+        by_status.entry(result.status.as_str()).or_default().push(port);
+    }
+    let mut parts: Vec<String> = by_status
+        .into_iter()
+        .map(|(status, ports)| format!("{} {}", ports.join(", "), status))
+        .collect();
+    if results.len() > MAX_LISTED {
+        parts.push(format!("+{} more", results.len() - MAX_LISTED));
+    }
+    parts.join(", ")
+}
+
+/// Find hosts whose TCP (or SYN) ports came back mixed. Results are grouped by host, address and
+/// test type, so --all-addresses and --ping results never count against each other.
+pub fn detect(results: &[HostResult]) -> Vec<Anomaly> {
+    type Key<'a> = (&'a str, Option<ScopedIp>, &'a str);
+    let mut groups: BTreeMap<Key<'_>, Vec<&HostResult>> = BTreeMap::new();
+    for result in results.iter().filter(|r| r.port.is_some() && (r.test_type == "tcp" || r.test_type == "syn")) {
+        let key = (result.host.as_str(), result.resolved_addr, result.test_type.as_str());
+        groups.entry(key).or_default().push(result);
+    }
+
+    let mut anomalies = Vec::new();
+    for ((host, resolved_addr, test_type), mut group) in groups {
+        group.sort_by_key(|r| r.port);
+        let (up, not_up): (Vec<&HostResult>, Vec<&HostResult>) = group.into_iter().partition(|r| r.status == "up");
+        if up.is_empty() || not_up.is_empty() {
+            continue;
+        }
+
+        let (outliers, message) = if up.len() > not_up.len() {
+            (&not_up, format!("mostly up but {}", describe(&not_up)))
+        } else if not_up.len() > up.len() {
+            (&up, format!("mostly down but {}", describe(&up)))
+        } else {
+            (&not_up, format!("{} but {}", describe(&up), describe(&not_up)))
+        };
+        anomalies.push(Anomaly {
+            host: host.to_string(),
+            resolved_addr,
+            test_type: test_type.to_string(),
+            outliers: outliers.iter().filter_map(|r| r.port).collect(),
+            message,
+        });
+    }
+    anomalies
+}
+
+pub fn print_anomalies(anomalies: &[Anomaly]) {
+    for anomaly in anomalies {
+        let host = match anomaly.resolved_addr {
+            Some(addr) => format!("{} @ {}", anomaly.host, addr),
+            None => anomaly.host.clone(),
+        };
+        println!("{}{}: {}", icons::lead("🔎"), host.blue(), anomaly.message.yellow());
+    }
+}
@@ -0,0 +1,256 @@
+use std::io;
+use std::net::Ipv4Addr;
+use std::time::Duration;
+
+/// What the ARP requests for one address turned up
+#[derive(Debug, Default)]
+pub struct Outcome {
+    /// Round-trip time of each request, `None` where no reply came back in time
+    pub rtts: Vec<Option<Duration>>,
+    /// Every distinct hardware address that answered, in the order first seen. More than one
+    /// means two devices claim the same IP.
+    pub macs: Vec<[u8; 6]>,
+}
+
+/// Whether this platform can send ARP requests at all
+pub const SUPPORTED: bool = cfg!(target_os = "linux");
+
+/// How long to keep listening after the last request, for a second device answering late
+#[cfg(target_os = "linux")]
+const DUPLICATE_WINDOW: Duration = Duration::from_millis(50);
+
+#[cfg(target_os = "linux")]
+const OP_REQUEST: u16 = 1;
+#[cfg(target_os = "linux")]
+const OP_REPLY: u16 = 2;
+
+/// `aa:bb:cc:dd:ee:ff`
+pub fn format_mac(mac: &[u8; 6]) -> String {
+    mac.iter().map(|b| format!("{:02x}", b)).collect::<Vec<_>>().join(":")
+}
+
+/// A packet socket for ARP frames, with the kernel adding and stripping the Ethernet header
+#[cfg(target_os = "linux")]
+fn arp_socket() -> io::Result<std::os::fd::OwnedFd> {
+    use std::os::fd::FromRawFd;
+
+    let protocol = (libc::ETH_P_ARP as u16).to_be() as libc::c_int;
+    // SAFETY: plain socket(2) call; the returned fd is checked before being owned
+    let fd = unsafe { libc::socket(libc::AF_PACKET, libc::SOCK_DGRAM | libc::SOCK_NONBLOCK, protocol) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: `fd` was just opened and nothing else owns it
+    Ok(unsafe { std::os::fd::OwnedFd::from_raw_fd(fd) })
+}
+
+/// Fail early, with the OS error, when the process isn't allowed to open packet sockets
+#[cfg(target_os = "linux")]
+pub fn check_privileges() -> io::Result<()> {
+    arp_socket().map(drop)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn check_privileges() -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "ARP probes are only supported on Linux"))
+}
+
+/// The local interface `dst` is directly reachable on
+#[cfg(target_os = "linux")]
+struct Interface {
+    index: libc::c_int,
+    mac: [u8; 6],
+    addr: Ipv4Addr,
+}
+
+/// Find the interface the kernel would route `dst` from, and check `dst` is on its subnet:
+/// ARP requests don't cross routers
+#[cfg(target_os = "linux")]
+fn interface_for(dst: Ipv4Addr) -> io::Result<Interface> {
+    use std::ffi::CStr;
+
+    let src = crate::syn::source_addr_for(dst)?;
+    let not_on_link = || io::Error::new(io::ErrorKind::AddrNotAvailable, "not on a directly connected network");
+
+    let mut ifaddrs: *mut libc::ifaddrs = std::ptr::null_mut();
+    // SAFETY: getifaddrs fills in a list that is released below with freeifaddrs
+    if unsafe { libc::getifaddrs(&mut ifaddrs) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut name = None;
+    let mut macs = Vec::new();
+    let mut entry = ifaddrs;
+    while !entry.is_null() {
+        // SAFETY: `entry` points into the list getifaddrs returned, which is still alive
+        let ifa = unsafe { &*entry };
+        entry = ifa.ifa_next;
+        if ifa.ifa_addr.is_null() {
+            continue;
+        }
+        // SAFETY: ifa_name is a NUL-terminated string owned by the list
+        let ifa_name = unsafe { CStr::from_ptr(ifa.ifa_name) }.to_owned();
+        // SAFETY: ifa_addr is non-null, and the family says which sockaddr it really is
+        match i32::from(unsafe { (*ifa.ifa_addr).sa_family }) {
+            libc::AF_INET if !ifa.ifa_netmask.is_null() => {
+                // SAFETY: AF_INET addresses and their netmasks are sockaddr_in
+                let (addr, mask) = unsafe {
+                    let addr = &*(ifa.ifa_addr as *const libc::sockaddr_in);
+                    let mask = &*(ifa.ifa_netmask as *const libc::sockaddr_in);
+                    (u32::from_be(addr.sin_addr.s_addr), u32::from_be(mask.sin_addr.s_addr))
+                };
+                let no_arp = (libc::IFF_NOARP | libc::IFF_LOOPBACK) as libc::c_uint;
+                let on_link = ifa.ifa_flags & no_arp == 0 && addr & mask == u32::from(dst) & mask;
+                if addr == u32::from(src) && on_link {
+                    name = Some(ifa_name);
+                }
+            }
+            libc::AF_PACKET => {
+                // SAFETY: AF_PACKET addresses are sockaddr_ll
+                let ll = unsafe { &*(ifa.ifa_addr as *const libc::sockaddr_ll) };
+                if ll.sll_halen == 6 {
+                    let mut mac = [0u8; 6];
+                    mac.copy_from_slice(&ll.sll_addr[..6]);
+                    macs.push((ifa_name, ll.sll_ifindex, mac));
+                }
+            }
+            _ => {}
+        }
+    }
+    // SAFETY: `ifaddrs` came from getifaddrs and no references into it outlive this point
+    unsafe { libc::freeifaddrs(ifaddrs) };
+
+    let name = name.ok_or_else(not_on_link)?;
+    macs.into_iter()
+        .find(|(ifa_name, _, _)| *ifa_name == name)
+        .map(|(_, index, mac)| Interface { index, mac, addr: src })
+        .ok_or_else(not_on_link)
+}
+
+/// A broadcast who-has request for `dst`, from `iface`
+#[cfg(target_os = "linux")]
+fn request_packet(iface: &Interface, dst: Ipv4Addr) -> [u8; 28] {
+    let mut packet = [0u8; 28];
+    packet[0..2].copy_from_slice(&1u16.to_be_bytes()); // hardware type: Ethernet
+    packet[2..4].copy_from_slice(&(libc::ETH_P_IP as u16).to_be_bytes());
+    packet[4] = 6; // hardware address length
+    packet[5] = 4; // protocol address length
+    packet[6..8].copy_from_slice(&OP_REQUEST.to_be_bytes());
+    packet[8..14].copy_from_slice(&iface.mac);
+    packet[14..18].copy_from_slice(&iface.addr.octets());
+    // Target hardware address stays zero: that's what we're asking for
+    packet[24..28].copy_from_slice(&dst.octets());
+    packet
+}
+
+/// The sender hardware address of an ARP reply from `dst`
+#[cfg(target_os = "linux")]
+fn reply_from(packet: &[u8], dst: Ipv4Addr) -> Option<[u8; 6]> {
+    if packet.len() < 28 || u16::from_be_bytes([packet[6], packet[7]]) != OP_REPLY || packet[14..18] != dst.octets() {
+        return None;
+    }
+    let mut mac = [0u8; 6];
+    mac.copy_from_slice(&packet[8..14]);
+    Some(mac)
+}
+
+/// Read replies until one from `dst` arrives (true) or `deadline` passes (false), adding each
+/// new hardware address to `macs`
+#[cfg(target_os = "linux")]
+async fn recv_reply(
+    socket: &tokio::io::unix::AsyncFd<std::os::fd::OwnedFd>,
+    dst: Ipv4Addr,
+    deadline: tokio::time::Instant,
+    macs: &mut Vec<[u8; 6]>,
+) -> io::Result<bool> {
+    use std::os::fd::AsRawFd;
+
+    let mut buf = [0u8; 64];
+    loop {
+        let mut ready = match tokio::time::timeout_at(deadline, socket.readable()).await {
+            Ok(ready) => ready?,
+            Err(_) => return Ok(false),
+        };
+        let read = ready.try_io(|fd| {
+            // SAFETY: recv writes at most `buf.len()` bytes into `buf`
+            let n = unsafe { libc::recv(fd.as_raw_fd(), buf.as_mut_ptr() as *mut libc::c_void, buf.len(), 0) };
+            if n < 0 {
+                Err(io::Error::last_os_error())
+            } else {
+                Ok(n as usize)
+            }
+        });
+        let n = match read {
+            Ok(read) => read?,
+            Err(_would_block) => continue,
+        };
+        if let Some(mac) = reply_from(&buf[..n], dst) {
+            if !macs.contains(&mac) {
+                macs.push(mac);
+            }
+            return Ok(true);
+        }
+    }
+}
+
+/// Send `count` ARP requests for `dst` one after another, each waiting up to `timeout` for a
+/// reply. Replies are only matched by address, since ARP has no sequence numbers.
+#[cfg(target_os = "linux")]
+pub async fn probe(dst: Ipv4Addr, count: u32, timeout: Duration) -> io::Result<Outcome> {
+    use std::os::fd::AsRawFd;
+    use tokio::io::unix::AsyncFd;
+    use tokio::time::Instant;
+
+    let iface = interface_for(dst)?;
+    let socket = AsyncFd::new(arp_socket()?)?;
+
+    // SAFETY: sockaddr_ll is plain integers, for which all zeroes is a valid value
+    let mut link: libc::sockaddr_ll = unsafe { std::mem::zeroed() };
+    link.sll_family = libc::AF_PACKET as u16;
+    link.sll_protocol = (libc::ETH_P_ARP as u16).to_be();
+    link.sll_ifindex = iface.index;
+    link.sll_halen = 6;
+    link.sll_addr[..6].copy_from_slice(&[0xff; 6]);
+    let link_len = std::mem::size_of::<libc::sockaddr_ll>() as libc::socklen_t;
+
+    // SAFETY: `link` is a valid sockaddr_ll of the given length
+    if unsafe { libc::bind(socket.as_raw_fd(), &link as *const _ as *const libc::sockaddr, link_len) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut outcome = Outcome::default();
+    let packet = request_packet(&iface, dst);
+    for _ in 0..count {
+        // Late answers to the previous request would otherwise be timed against this one
+        while recv_reply(&socket, dst, Instant::now(), &mut outcome.macs).await? {}
+
+        let start = Instant::now();
+        // SAFETY: `packet` and `link` are valid for the lengths passed
+        let sent = unsafe {
+            libc::sendto(
+                socket.as_raw_fd(),
+                packet.as_ptr() as *const libc::c_void,
+                packet.len(),
+                0,
+                &link as *const _ as *const libc::sockaddr,
+                link_len,
+            )
+        };
+        if sent < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let answered = recv_reply(&socket, dst, start + timeout, &mut outcome.macs).await?;
+        outcome.rtts.push(answered.then(|| start.elapsed()));
+    }
+
+    let linger = Instant::now() + DUPLICATE_WINDOW;
+    while recv_reply(&socket, dst, linger, &mut outcome.macs).await? {}
+
+    Ok(outcome)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub async fn probe(_dst: Ipv4Addr, _count: u32, _timeout: Duration) -> io::Result<Outcome> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "ARP probes are only supported on Linux"))
+}
@@ -0,0 +1,413 @@
+use crate::HostResult;
+
+/// Fields --assert can test. Latencies are in milliseconds whatever --unit says; `p50`, `p95`
+/// and `p99` are nearest-rank percentiles of the response times, whatever --status-metric says.
+const NUMBERS: &[&str] = &[
+    "port",
+    "attempts",
+    "successful",
+    "success_rate",
+    "avg_ms",
+    "min_ms",
+    "max_ms",
+    "jitter_ms",
+    "p50",
+    "p95",
+    "p99",
+    "ewma_ms",
+    "dns_ms",
+    "connect_ms",
+    "tls_ms",
+    "ttfb_ms",
+    "http_status",
+    "throughput_mbps",
+    "clock_offset_ms",
+    "hops_estimate",
+    "path_mtu",
+];
+
+/// Fields compared with quoted text, e.g. `status == "up"`
+const TEXTS: &[&str] = &["host", "test_type", "status", "failure_kind", "country"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Cmp {
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+}
+
+impl Cmp {
+    fn holds(self, ordering: std::cmp::Ordering) -> bool {
+        use std::cmp::Ordering::*;
+        match self {
+            Cmp::Lt => ordering == Less,
+            Cmp::Le => ordering != Greater,
+            Cmp::Gt => ordering == Greater,
+            Cmp::Ge => ordering != Less,
+            Cmp::Eq => ordering == Equal,
+            Cmp::Ne => ordering != Equal,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Text(String),
+    Name(String),
+    Cmp(Cmp),
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+}
+
+#[derive(Debug, Clone)]
+enum Operand {
+    Number(f64),
+    Text(String),
+    Field(&'static str),
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Compare(Operand, Cmp, Operand),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+}
+
+/// A parsed --assert expression, which every result has to satisfy
+#[derive(Debug, Clone)]
+pub struct Assertion {
+    source: String,
+    expr: Expr,
+    /// The fields it mentions, in order, for explaining a failure
+    fields: Vec<&'static str>,
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = s.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+        // A '-' right before a digit starts a negative number, e.g. `clock_offset_ms > -5`
+        let negative = c == '-' && chars.clone().nth(1).is_some_and(|d| d.is_ascii_digit() || d == '.');
+        if negative || c.is_ascii_digit() || c == '.' {
+            let mut number = String::new();
+            if negative {
+                number.push('-');
+                chars.next();
+            }
+            while let Some(&c) = chars.peek().filter(|c| c.is_ascii_digit() || **c == '.') {
+                number.push(c);
+                chars.next();
+            }
+            tokens.push(Token::Number(number.parse().map_err(|_| format!("'{}' isn't a number", number))?));
+            continue;
+        }
+        if c.is_ascii_alphabetic() || c == '_' {
+            let mut name = String::new();
+            while let Some(&c) = chars.peek().filter(|c| c.is_ascii_alphanumeric() || **c == '_') {
+                name.push(c);
+                chars.next();
+            }
+            tokens.push(Token::Name(name));
+            continue;
+        }
+        chars.next();
+        let token = match c {
+            '"' | '\'' => {
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some(q) if q == c => break,
+                        Some(q) => text.push(q),
+                        None => return Err(format!("unclosed quote in {}{}", c, text)),
+                    }
+                }
+                Token::Text(text)
+            }
+            '<' | '>' | '=' | '!' if chars.next_if_eq(&'=').is_some() => Token::Cmp(match c {
+                '<' => Cmp::Le,
+                '>' => Cmp::Ge,
+                '=' => Cmp::Eq,
+                _ => Cmp::Ne,
+            }),
+            '<' => Token::Cmp(Cmp::Lt),
+            '>' => Token::Cmp(Cmp::Gt),
+            '!' => Token::Not,
+            '&' if chars.next_if_eq(&'&').is_some() => Token::And,
+            '|' if chars.next_if_eq(&'|').is_some() => Token::Or,
+            '(' => Token::Open,
+            ')' => Token::Close,
+            '=' => return Err("write '==' to test for equality".to_string()),
+            _ => return Err(format!("unexpected '{}'", c)),
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+/// Recursive descent over the tokens: `||` binds loosest, then `&&`, then `!`, then a comparison
+struct Parser {
+    tokens: std::iter::Peekable<std::vec::IntoIter<Token>>,
+    fields: Vec<&'static str>,
+}
+
+impl Parser {
+    fn or(&mut self) -> Result<Expr, String> {
+        let mut expr = self.and()?;
+        while self.tokens.next_if_eq(&Token::Or).is_some() {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut expr = self.not()?;
+        while self.tokens.next_if_eq(&Token::And).is_some() {
+            expr = Expr::And(Box::new(expr), Box::new(self.not()?));
+        }
+        Ok(expr)
+    }
+
+    fn not(&mut self) -> Result<Expr, String> {
+        if self.tokens.next_if_eq(&Token::Not).is_some() {
+            return Ok(Expr::Not(Box::new(self.not()?)));
+        }
+        if self.tokens.next_if_eq(&Token::Open).is_some() {
+            let expr = self.or()?;
+            return match self.tokens.next() {
+                Some(Token::Close) => Ok(expr),
+                _ => Err("unmatched '('".to_string()),
+            };
+        }
+        let left = self.operand()?;
+        let cmp = match self.tokens.next() {
+            Some(Token::Cmp(cmp)) => cmp,
+            _ => return Err("expected a comparison such as `p95 < 100`".to_string()),
+        };
+        let right = self.operand()?;
+        match (is_text(&left), is_text(&right)) {
+            (true, true) if !matches!(cmp, Cmp::Eq | Cmp::Ne) => {
+                Err("text can only be compared with == or !=".to_string())
+            }
+            (l, r) if l != r => Err("can't compare a number with text".to_string()),
+            _ => Ok(Expr::Compare(left, cmp, right)),
+        }
+    }
+
+    fn operand(&mut self) -> Result<Operand, String> {
+        match self.tokens.next() {
+            Some(Token::Number(n)) => Ok(Operand::Number(n)),
+            Some(Token::Text(text)) => Ok(Operand::Text(text)),
+            Some(Token::Name(name)) => {
+                let field = NUMBERS.iter().chain(TEXTS).find(|&&f| f == name).ok_or_else(|| {
+                    format!("unknown field '{}'; expected one of {}, {}", name, NUMBERS.join(", "), TEXTS.join(", "))
+                })?;
+                if !self.fields.contains(field) {
+                    self.fields.push(field);
+                }
+                Ok(Operand::Field(field))
+            }
+            Some(token) => Err(format!("expected a field or a value, found {}", describe(&token))),
+            None => Err("the expression ends too early".to_string()),
+        }
+    }
+}
+
+fn describe(token: &Token) -> &'static str {
+    match token {
+        Token::Cmp(_) => "a comparison",
+        Token::And => "'&&'",
+        Token::Or => "'||'",
+        Token::Not => "'!'",
+        Token::Open => "'('",
+        Token::Close => "')'",
+        Token::Number(_) | Token::Text(_) | Token::Name(_) => "a value",
+    }
+}
+
+fn is_text(operand: &Operand) -> bool {
+    match operand {
+        Operand::Text(_) => true,
+        Operand::Field(field) => TEXTS.contains(field),
+        Operand::Number(_) => false,
+    }
+}
+
+/// Parse `--assert`: comparisons of result fields with numbers (`<`, `<=`, `>`, `>=`, `==`, `!=`)
+/// or quoted text (`==`, `!=`), joined with `&&`, `||`, `!` and parentheses
+pub fn parse(s: &str) -> Result<Assertion, String> {
+    let mut parser = Parser { tokens: tokenize(s)?.into_iter().peekable(), fields: Vec::new() };
+    let expr = parser.or()?;
+    if let Some(token) = parser.tokens.next() {
+        return Err(format!("found {} after a complete expression", describe(&token)));
+    }
+    Ok(Assertion { source: s.trim().to_string(), expr, fields: parser.fields })
+}
+
+enum Value {
+    Number(f64),
+    Text(String),
+}
+
+fn field(result: &HostResult, field: &str) -> Option<Value> {
+    let number = |n: Option<f64>| n.map(Value::Number);
+    let text = |s: Option<&str>| s.map(|s| Value::Text(s.to_string()));
+    match field {
+        "port" => number(result.port.map(f64::from)),
+        "attempts" => number(Some(f64::from(result.attempts))),
+        "successful" => number(Some(f64::from(result.successful))),
+        "success_rate" => number(Some(result.success_rate)),
+        "avg_ms" => number(result.avg_response_time_ms),
+        "min_ms" => number(result.min_response_time_ms),
+        "max_ms" => number(result.max_response_time_ms),
+        "jitter_ms" => number(result.jitter_ms),
+        "p50" => number(crate::percentile(&result.response_times, 50.0)),
+        "p95" => number(crate::percentile(&result.response_times, 95.0)),
+        "p99" => number(crate::percentile(&result.response_times, 99.0)),
+        "ewma_ms" => number(result.ewma_response_time_ms),
+        "dns_ms" => number(result.dns_resolution_ms),
+        "connect_ms" => number(result.connect_ms),
+        "tls_ms" => number(result.tls_ms),
+        "ttfb_ms" => number(result.ttfb_ms),
+        "http_status" => number(result.http_status.map(f64::from)),
+        "throughput_mbps" => number(result.throughput_mbps),
+        "clock_offset_ms" => number(result.clock_offset_ms),
+        "hops_estimate" => number(result.hops_estimate.map(f64::from)),
+        "path_mtu" => number(result.path_mtu.map(f64::from)),
+        "host" => text(Some(&result.host)),
+        "test_type" => text(Some(&result.test_type)),
+        "status" => text(Some(&result.status)),
+        "failure_kind" => text(result.failure_kind.map(|k| k.as_str())),
+        "country" => text(result.country.as_deref()),
+        _ => unreachable!("fields are checked when the assertion is parsed"),
+    }
+}
+
+fn value(result: &HostResult, operand: &Operand) -> Option<Value> {
+    match operand {
+        Operand::Number(n) => Some(Value::Number(*n)),
+        Operand::Text(text) => Some(Value::Text(text.clone())),
+        Operand::Field(name) => field(result, name),
+    }
+}
+
+fn eval(expr: &Expr, result: &HostResult) -> bool {
+    match expr {
+        // A result without the value, such as a down one's avg_ms, fails the comparison
+        Expr::Compare(left, cmp, right) => match (value(result, left), value(result, right)) {
+            (Some(Value::Number(l)), Some(Value::Number(r))) => cmp.holds(l.total_cmp(&r)),
+            (Some(Value::Text(l)), Some(Value::Text(r))) => cmp.holds(l.cmp(&r)),
+            _ => false,
+        },
+        Expr::Not(expr) => !eval(expr, result),
+        Expr::And(l, r) => eval(l, result) && eval(r, result),
+        Expr::Or(l, r) => eval(l, result) || eval(r, result),
+    }
+}
+
+impl Assertion {
+    pub fn holds(&self, result: &HostResult) -> bool {
+        eval(&self.expr, result)
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// The values of the fields it mentions, e.g. `p95 = 140.0, status = "up"`
+    pub fn explain(&self, result: &HostResult) -> String {
+        self.fields
+            .iter()
+            .map(|&name| match field(result, name) {
+                Some(Value::Number(n)) if n.fract() == 0.0 => format!("{} = {}", name, n),
+                Some(Value::Number(n)) => format!("{} = {:.2}", name, n),
+                Some(Value::Text(text)) => format!("{} = \"{}\"", name, text),
+                None => format!("{} = -", name),
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn up(port: u16, avg_ms: f64) -> HostResult {
+        HostResult {
+            host: "db1".to_string(),
+            port: Some(port),
+            status: "up".to_string(),
+            avg_response_time_ms: Some(avg_ms),
+            ..Default::default()
+        }
+    }
+
+    fn holds(expr: &str, result: &HostResult) -> bool {
+        parse(expr).unwrap().holds(result)
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let result = up(443, 50.0);
+        assert!(holds("port == 443 || port == 80 && avg_ms > 100", &result));
+        assert!(!holds("(port == 443 || port == 80) && avg_ms > 100", &result));
+    }
+
+    #[test]
+    fn not_binds_tighter_than_and() {
+        let result = up(443, 50.0);
+        assert!(!holds("!port == 443 && avg_ms < 100", &result));
+        assert!(holds("!(port == 443 && avg_ms > 100)", &result));
+        assert!(holds("!!(status == \"up\")", &result));
+    }
+
+    #[test]
+    fn negative_numbers() {
+        let result = HostResult { clock_offset_ms: Some(-3.0), ..up(123, 1.0) };
+        assert!(holds("clock_offset_ms > -5", &result));
+        assert!(!holds("clock_offset_ms < -.5 && clock_offset_ms > -3", &result));
+    }
+
+    #[test]
+    fn text_and_number_mismatch() {
+        assert_eq!(parse("status > \"up\"").unwrap_err(), "text can only be compared with == or !=");
+        assert_eq!(parse("status == 1").unwrap_err(), "can't compare a number with text");
+        assert_eq!(parse("port == \"443\"").unwrap_err(), "can't compare a number with text");
+        assert!(holds("host != 'db2'", &up(443, 1.0)));
+    }
+
+    #[test]
+    fn single_equals() {
+        assert_eq!(parse("port = 443").unwrap_err(), "write '==' to test for equality");
+        assert!(holds("port == 443", &up(443, 1.0)));
+    }
+
+    #[test]
+    fn missing_field_fails_the_comparison() {
+        let down = HostResult { status: "down".to_string(), ..Default::default() };
+        assert!(!holds("avg_ms < 100", &down));
+        assert!(!holds("avg_ms >= 100", &down));
+        assert!(holds("!(avg_ms < 100)", &down));
+        assert_eq!(parse("avg_ms < 100").unwrap().explain(&down), "avg_ms = -");
+    }
+
+    #[test]
+    fn trailing_tokens() {
+        assert_eq!(parse("port == 443 80").unwrap_err(), "found a value after a complete expression");
+        assert_eq!(parse("port == 443)").unwrap_err(), "found ')' after a complete expression");
+        assert_eq!(parse("(port == 443").unwrap_err(), "unmatched '('");
+        assert_eq!(parse("port ==").unwrap_err(), "the expression ends too early");
+    }
+}
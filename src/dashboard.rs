@@ -0,0 +1,95 @@
+use crate::scoped::ScopedIp;
+use crate::{table, HostResult};
+use colored::*;
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::terminal::{Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{execute, queue};
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+/// Latency changes smaller than this fraction of the last scan's are shown as steady
+const TREND_THRESHOLD: f64 = 0.1;
+
+/// Changes under a millisecond are noise however large they are relative to a fast target
+const TREND_FLOOR_MS: f64 = 1.0;
+
+type CellKey = (String, Option<u16>, String, Option<ScopedIp>);
+
+fn key(result: &HostResult) -> CellKey {
+    (result.host.clone(), result.port, result.test_type.clone(), result.resolved_addr)
+}
+
+/// --dashboard: the alternate screen, redrawn in place with every scan. Dropping it gives the
+/// terminal back.
+pub struct Dashboard {
+    scans: u64,
+    /// Each cell's average latency in the previous scan, for its trend arrow
+    previous: HashMap<CellKey, f64>,
+}
+
+impl Dashboard {
+    pub fn enter() -> io::Result<Self> {
+        execute!(io::stdout(), EnterAlternateScreen, Hide)?;
+        Ok(Dashboard { scans: 0, previous: HashMap::new() })
+    }
+
+    fn trend(&self, result: &HostResult) -> &'static str {
+        let (Some(avg), Some(&previous)) = (result.avg_response_time_ms, self.previous.get(&key(result))) else {
+            return " ";
+        };
+        let change = avg - previous;
+        if change.abs() < TREND_FLOOR_MS || change.abs() < previous * TREND_THRESHOLD {
+            "→"
+        } else if change > 0.0 {
+            "↑"
+        } else {
+            "↓"
+        }
+    }
+
+    fn cell(&self, result: &HostResult) -> String {
+        let latency = match result.avg_response_time_ms {
+            Some(avg) => format!(" {:.1} ms", avg),
+            None => String::new(),
+        };
+        format!("{}{} {}", table::status_icon(result), latency, self.trend(result))
+    }
+
+    /// Replace the screen with this scan's grid
+    pub fn draw(&mut self, results: &[HostResult], timestamp: &str) -> io::Result<()> {
+        self.scans += 1;
+        let up = results.iter().filter(|r| r.status == "up").count();
+        let grid = table::grid(results, |r| self.cell(r));
+
+        let mut stdout = io::stdout().lock();
+        queue!(stdout, MoveTo(0, 0), Clear(ClearType::All))?;
+        writeln!(
+            stdout,
+            "{}  {}  {}",
+            "📡 ring dashboard".bold(),
+            format!("scan {} at {}", self.scans, timestamp).dimmed(),
+            format!("{}/{} up", up, results.len()).green()
+        )?;
+        writeln!(stdout, "{grid}")?;
+        writeln!(stdout, "{}", "↑/↓ slower/faster than the last scan · Ctrl+C to quit".dimmed())?;
+        stdout.flush()?;
+
+        self.previous = results
+            .iter()
+            .filter_map(|r| r.avg_response_time_ms.map(|avg| (key(r), avg)))
+            .collect();
+        Ok(())
+    }
+}
+
+impl Drop for Dashboard {
+    fn drop(&mut self) {
+        restore();
+    }
+}
+
+/// Leave the alternate screen and show the cursor again. Also called on Ctrl+C, which ends the
+/// process without running destructors.
+pub fn restore() {
+    let _ = execute!(io::stdout(), Show, LeaveAlternateScreen);
+}
@@ -0,0 +1,180 @@
+use crate::scoped::ScopedIp;
+use crate::{icons, HostResult, ScanResult};
+use colored::*;
+use serde::Serialize;
+use std::collections::HashMap;
+type TargetKey<'a> = (&'a str, Option<u16>, &'a str, Option<ScopedIp>);
+
+fn key(result: &HostResult) -> TargetKey<'_> {
+    (result.host.as_str(), result.port, result.test_type.as_str(), result.resolved_addr)
+}
+
+/// One target whose status or latency differs from the baseline
+#[derive(Serialize, Debug)]
+pub struct DiffEntry {
+    host: String,
+    port: Option<u16>,
+    test_type: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    resolved_addr: Option<ScopedIp>,
+    old_status: Option<String>, // None when the target wasn't in the baseline
+    new_status: String,
+    old_avg_response_time_ms: Option<f64>,
+    new_avg_response_time_ms: Option<f64>,
+    // Latency changes only
+    #[serde(skip_serializing_if = "Option::is_none")]
+    delta_ms: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    delta_percent: Option<f64>, // None when the baseline average was 0
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    regression: bool, // slower than the baseline by more than --baseline-tolerance
+}
+
+#[derive(Serialize, Debug)]
+pub struct ScanDiff {
+    baseline_timestamp: String,
+    scan_timestamp: String,
+    newly_up: Vec<DiffEntry>,
+    newly_down: Vec<DiffEntry>,
+    changed_latency: Vec<DiffEntry>,
+}
+
+impl ScanDiff {
+    pub fn is_empty(&self) -> bool {
+        self.newly_up.is_empty() && self.newly_down.is_empty() && self.changed_latency.is_empty()
+    }
+
+    pub fn has_regressions(&self) -> bool {
+        self.changed_latency.iter().any(|e| e.regression)
+    }
+}
+
+pub fn load_baseline(path: &str) -> Result<ScanResult, String> {
+    crate::output::load_scan(path, "baseline")
+}
+
+/// Compare `current` against `baseline`, matching targets by (host, port, test_type, resolved_addr).
+/// Latency changes are only reported for targets that are up in both scans and moved by more
+/// than `tolerance_ms`, or that got slower by more than `regression_percent`, which makes them
+/// regressions.
+pub fn compute_diff(
+    baseline: &ScanResult,
+    current: &[HostResult],
+    scan_timestamp: &str,
+    tolerance_ms: f64,
+    regression_percent: Option<f64>,
+) -> ScanDiff {
+    let previous: HashMap<TargetKey, &HostResult> = baseline.results.iter().map(|r| (key(r), r)).collect();
+
+    let mut diff = ScanDiff {
+        baseline_timestamp: baseline.scan_timestamp.clone(),
+        scan_timestamp: scan_timestamp.to_string(),
+        newly_up: vec![],
+        newly_down: vec![],
+        changed_latency: vec![],
+    };
+
+    for result in current {
+        let old = previous.get(&key(result)).copied();
+        let was_up = old.is_some_and(|o| o.status == "up");
+        let is_up = result.status == "up";
+
+        let mut entry = DiffEntry {
+            host: result.host.clone(),
+            port: result.port,
+            test_type: result.test_type.clone(),
+            resolved_addr: result.resolved_addr,
+            old_status: old.map(|o| o.status.clone()),
+            new_status: result.status.clone(),
+            old_avg_response_time_ms: old.and_then(|o| o.avg_response_time_ms),
+            new_avg_response_time_ms: result.avg_response_time_ms,
+            delta_ms: None,
+            delta_percent: None,
+            regression: false,
+        };
+
+        if is_up && !was_up {
+            diff.newly_up.push(entry);
+        } else if was_up && !is_up {
+            diff.newly_down.push(entry);
+        } else if is_up && was_up {
+            if let (Some(old_avg), Some(new_avg)) = (entry.old_avg_response_time_ms, entry.new_avg_response_time_ms) {
+                let delta_ms = new_avg - old_avg;
+                let delta_percent = (old_avg > 0.0).then(|| delta_ms / old_avg * 100.0);
+                entry.regression = regression_percent.zip(delta_percent).is_some_and(|(limit, pct)| pct > limit);
+                if entry.regression || delta_ms.abs() > tolerance_ms {
+                    entry.delta_ms = Some(delta_ms);
+                    entry.delta_percent = delta_percent;
+                    diff.changed_latency.push(entry);
+                }
+            }
+        }
+    }
+
+    diff
+}
+
+fn label(entry: &DiffEntry) -> String {
+    let target = match entry.port {
+        Some(port) => format!("{}:{}", entry.host.blue(), port.to_string().yellow()),
+        None => format!("{} ({})", entry.host.blue(), entry.test_type.to_uppercase()),
+    };
+    match entry.resolved_addr {
+        Some(addr) => format!("{} {}", target, format!("@ {}", addr).dimmed()),
+        None => target,
+    }
+}
+
+pub fn print_diff(diff: &ScanDiff) {
+    println!("\n{}Changes since baseline ({})", icons::lead("🔀"), diff.baseline_timestamp.dimmed());
+    println!("{}", icons::rule().dimmed());
+
+    if diff.is_empty() {
+        println!("{}", "No changes since baseline".green());
+        return;
+    }
+
+    for entry in &diff.newly_up {
+        let detail = match &entry.old_status {
+            Some(old) => format!("(was {})", old),
+            None => "(new target)".to_string(),
+        };
+        println!("{} {} {} {}", icons::pick("⬆️ ", "[UP]"), label(entry), "newly up".green(), detail.dimmed());
+    }
+
+    for entry in &diff.newly_down {
+        let old = entry.old_status.as_deref().unwrap_or("unknown");
+        println!(
+            "{} {} {} {}",
+            icons::pick("⬇️ ", "[DOWN]"),
+            label(entry),
+            format!("went {}", entry.new_status).red(),
+            format!("(was {})", old).dimmed()
+        );
+    }
+
+    for entry in &diff.changed_latency {
+        if let (Some(old_avg), Some(new_avg)) = (entry.old_avg_response_time_ms, entry.new_avg_response_time_ms) {
+            let delta = match entry.delta_percent {
+                Some(pct) => format!("({:+.2}, {:+.0}%)", new_avg - old_avg, pct),
+                None => format!("({:+.2})", new_avg - old_avg),
+            };
+            if entry.regression {
+                println!(
+                    "{} {} latency {:.2} {} {:.2} ms {} {}",
+                    icons::pick("🐌", "[SLOWER]"),
+                    label(entry),
+                    old_avg,
+                    icons::get("arrow"),
+                    new_avg,
+                    delta,
+                    "regression".red()
+                );
+            } else {
+                let marker = icons::pick("⏱️ ", "[LATENCY]");
+                let arrow = icons::get("arrow");
+                println!("{} {} latency {:.2} {} {:.2} ms {}", marker, label(entry), old_avg, arrow, new_avg, delta);
+            }
+        }
+    }
+}
@@ -0,0 +1,125 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Pause before retrying a lookup that failed or came back empty
+const RETRY_DELAY: Duration = Duration::from_millis(250);
+
+/// System resolver lookups with retries, and a short-lived cache shared by every probe so a
+/// host with many ports is looked up once per TTL rather than once per port
+#[derive(Debug)]
+pub struct Resolver {
+    retries: u32,
+    ttl: Duration,
+    cache: Mutex<HashMap<String, (Instant, Vec<IpAddr>)>>,
+    /// PTR names, including the addresses that have none, so a sweep isn't looked up every scan
+    ptr_cache: Mutex<HashMap<IpAddr, (Instant, Option<String>)>>,
+}
+
+impl Resolver {
+    /// `ttl` of zero disables the cache
+    pub fn new(retries: u32, ttl: Duration) -> Self {
+        Resolver { retries, ttl, cache: Mutex::new(HashMap::new()), ptr_cache: Mutex::new(HashMap::new()) }
+    }
+
+    fn cached(&self, host: &str) -> Option<Vec<IpAddr>> {
+        let cache = self.cache.lock().unwrap();
+        cache
+            .get(host)
+            .filter(|(stored, _)| stored.elapsed() < self.ttl)
+            .map(|(_, addrs)| addrs.clone())
+    }
+
+    /// Resolve `host`, retrying up to `retries` times. An empty list means the name exists but
+    /// has no addresses. Only successful lookups are cached.
+    #[tracing::instrument(level = "debug", skip(self))]
+    pub async fn lookup(&self, host: &str) -> io::Result<Vec<IpAddr>> {
+        if let Some(addrs) = self.cached(host) {
+            tracing::trace!(?addrs, "cache hit");
+            return Ok(addrs);
+        }
+
+        let mut attempt = 0;
+        loop {
+            let result = tokio::net::lookup_host((host, 0)).await.map(|addrs| addrs.map(|a| a.ip()).collect::<Vec<_>>());
+            match result {
+                Ok(addrs) if !addrs.is_empty() => {
+                    if !self.ttl.is_zero() {
+                        self.cache.lock().unwrap().insert(host.to_string(), (Instant::now(), addrs.clone()));
+                    }
+                    return Ok(addrs);
+                }
+                _ => {
+                    let reason = match &result {
+                        Ok(_) => "no addresses".to_string(),
+                        Err(e) => e.to_string(),
+                    };
+                    if attempt >= self.retries {
+                        tracing::debug!(%reason, "lookup failed");
+                        return result;
+                    }
+                    attempt += 1;
+                    tracing::debug!(attempt, %reason, "lookup failed; retrying");
+                    tokio::time::sleep(RETRY_DELAY).await;
+                }
+            }
+        }
+    }
+}
+
+impl Resolver {
+    /// The name `ip`'s PTR record gives, through the system resolver. `None` when it has none,
+    /// the lookup failed, or it took longer than `timeout`.
+    #[tracing::instrument(level = "debug", skip(self))]
+    pub async fn reverse(&self, ip: IpAddr, timeout: Duration) -> Option<String> {
+        let cached = self.ptr_cache.lock().unwrap().get(&ip).filter(|(stored, _)| stored.elapsed() < self.ttl).cloned();
+        if let Some((_, name)) = cached {
+            tracing::trace!(?name, "cache hit");
+            return name;
+        }
+
+        let lookup = tokio::task::spawn_blocking(move || name_info(ip));
+        let name = match tokio::time::timeout(timeout, lookup).await {
+            Ok(Ok(name)) => name,
+            // A lookup that is still running may yet succeed, so leave it uncached
+            _ => return None,
+        };
+        tracing::debug!(?name, "PTR lookup done");
+        if !self.ttl.is_zero() {
+            self.ptr_cache.lock().unwrap().insert(ip, (Instant::now(), name.clone()));
+        }
+        name
+    }
+}
+
+/// getnameinfo(3) with NI_NAMEREQD, so an address without a name fails instead of coming back
+/// as itself
+#[cfg(unix)]
+fn name_info(ip: IpAddr) -> Option<String> {
+    let addr = socket2::SockAddr::from(std::net::SocketAddr::new(ip, 0));
+    let mut host = [0 as libc::c_char; libc::NI_MAXHOST as usize];
+    let rc = unsafe {
+        libc::getnameinfo(
+            addr.as_ptr().cast::<libc::sockaddr>(),
+            addr.len(),
+            host.as_mut_ptr(),
+            host.len() as libc::socklen_t,
+            std::ptr::null_mut(),
+            0,
+            libc::NI_NAMEREQD,
+        )
+    };
+    if rc != 0 {
+        return None;
+    }
+    let name = unsafe { std::ffi::CStr::from_ptr(host.as_ptr()) }.to_string_lossy().into_owned();
+    // Some resolvers hand back the name with its root dot
+    Some(name.trim_end_matches('.').to_string())
+}
+
+#[cfg(not(unix))]
+fn name_info(_ip: IpAddr) -> Option<String> {
+    None
+}
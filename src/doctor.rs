@@ -0,0 +1,163 @@
+use crate::{dns, fd_soft_limit, icmp_config, icons};
+use colored::*;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::{Duration, Instant};
+
+/// A name any working resolver can look up, and an endpoint on it that is always up
+const KNOWN_HOST: &str = "example.com";
+const KNOWN_PORT: u16 = 443;
+
+/// Reached by address when DNS is broken, so a network problem can still be told from a DNS one
+const KNOWN_IP: IpAddr = IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1));
+
+/// Below this many open files, scans of a few hundred ports start failing with
+/// too_many_open_files
+const LOW_FD_LIMIT: u64 = 1024;
+
+enum Verdict {
+    Ok,
+    Warn,
+    Fail,
+}
+
+/// The checks' findings, printed as they come
+#[derive(Default)]
+struct Report {
+    problems: usize,
+}
+
+impl Report {
+    fn check(&mut self, verdict: Verdict, what: &str, detail: String, hint: Option<&str>) {
+        let icon = match verdict {
+            Verdict::Ok => icons::get("up"),
+            Verdict::Warn => icons::get("warning"),
+            Verdict::Fail => {
+                self.problems += 1;
+                icons::get("down")
+            }
+        };
+        println!("{} {}: {}", icon, what.bold(), detail);
+        if let Some(hint) = hint {
+            println!("    {} {}", icons::get("detail"), hint.dimmed());
+        }
+    }
+}
+
+/// Try to open an echo socket of each family, the way --ping does
+fn check_icmp(report: &mut Report) {
+    if let Err(e) = surge_ping::Client::new(&icmp_config(IpAddr::V4(Ipv4Addr::LOCALHOST))) {
+        // Whatever stops IPv4 stops IPv6 as well, so there's no point in trying
+        return report.check(
+            Verdict::Fail,
+            "ICMP",
+            format!("can't open echo sockets: {}", e),
+            Some(
+                "--ping will fail for every host: run as root, grant the capability with \
+                 `sudo setcap cap_net_raw+ep $(which ring)`, or on Linux allow unprivileged pings \
+                 with `sudo sysctl net.ipv4.ping_group_range=\"0 2147483647\"`",
+            ),
+        );
+    }
+    match surge_ping::Client::new(&icmp_config(IpAddr::V6(Ipv6Addr::LOCALHOST))) {
+        Ok(_) => report.check(Verdict::Ok, "ICMP", "can open IPv4 and IPv6 echo sockets".to_string(), None),
+        // Many hosts have no IPv6 at all, which --ping to IPv4 targets doesn't mind
+        Err(e) => report.check(
+            Verdict::Warn,
+            "ICMP",
+            format!("can open IPv4 echo sockets, but not IPv6 ones: {}", e),
+            Some("pinging IPv6 targets will fail; IPv4 isn't affected"),
+        ),
+    }
+}
+
+fn check_fd_limit(report: &mut Report) {
+    match fd_soft_limit() {
+        Some(limit) if limit < LOW_FD_LIMIT => report.check(
+            Verdict::Warn,
+            "open file limit",
+            format!("{}, enough for about {} probes at once", limit, limit.saturating_sub(64)),
+            Some("big scans will hit too_many_open_files: raise it with `ulimit -n 65536`, or use --concurrency"),
+        ),
+        Some(limit) => report.check(
+            Verdict::Ok,
+            "open file limit",
+            format!("{}, enough for about {} probes at once", limit, limit.saturating_sub(64)),
+            None,
+        ),
+        None => report.check(Verdict::Ok, "open file limit", "not limited on this platform".to_string(), None),
+    }
+}
+
+/// Look up KNOWN_HOST through the resolver probes use, returning its first address
+async fn check_dns(report: &mut Report, dns_retries: u32) -> Option<IpAddr> {
+    let resolver = dns::Resolver::new(dns_retries, Duration::ZERO);
+    let start = Instant::now();
+    let hint = "hostnames won't resolve: check /etc/resolv.conf (or your OS's DNS settings) and that the \
+                resolver is reachable; IP addresses still work";
+    match resolver.lookup(KNOWN_HOST).await {
+        Ok(addrs) if !addrs.is_empty() => {
+            let ms = start.elapsed().as_secs_f64() * 1000.0;
+            report.check(Verdict::Ok, "DNS", format!("{} resolves to {} ({:.0} ms)", KNOWN_HOST, addrs[0], ms), None);
+            Some(addrs[0])
+        }
+        Ok(_) => {
+            report.check(Verdict::Fail, "DNS", format!("{} resolved to no addresses", KNOWN_HOST), Some(hint));
+            None
+        }
+        Err(e) => {
+            report.check(Verdict::Fail, "DNS", format!("can't resolve {}: {}", KNOWN_HOST, e), Some(hint));
+            None
+        }
+    }
+}
+
+/// Connect to the known endpoint: by name when DNS works, otherwise by address
+async fn check_reach(report: &mut Report, resolved: Option<IpAddr>, timeout: Duration) {
+    let (name, ip) = match resolved {
+        Some(ip) => (KNOWN_HOST.to_string(), ip),
+        None => (KNOWN_IP.to_string(), KNOWN_IP),
+    };
+    let addr = SocketAddr::new(ip, KNOWN_PORT);
+    let start = Instant::now();
+    match tokio::time::timeout(timeout, tokio::net::TcpStream::connect(addr)).await {
+        Ok(Ok(_)) => {
+            let ms = start.elapsed().as_secs_f64() * 1000.0;
+            report.check(Verdict::Ok, "network", format!("connected to {}:{} in {:.0} ms", name, KNOWN_PORT, ms), None);
+        }
+        failed => {
+            let error = match failed {
+                Ok(Err(e)) => e.to_string(),
+                _ => "timed out".to_string(),
+            };
+            report.check(
+                Verdict::Fail,
+                "network",
+                format!("can't connect to {}:{}: {}", name, KNOWN_PORT, error),
+                Some(
+                    "outbound connections look blocked: check the default route, a firewall, or whether \
+                     you need --proxy",
+                ),
+            );
+        }
+    }
+}
+
+/// --doctor: check the environment ring runs in and print what is wrong and how to fix it.
+/// Returns whether every check passed; warnings don't count against that.
+pub async fn run(dns_retries: u32, timeout: Duration) -> bool {
+    println!("{}Checking the environment", icons::lead("🩺"));
+    println!("{}", icons::rule());
+
+    let mut report = Report::default();
+    check_icmp(&mut report);
+    check_fd_limit(&mut report);
+    let resolved = check_dns(&mut report, dns_retries).await;
+    check_reach(&mut report, resolved, timeout).await;
+
+    match report.problems {
+        0 => println!("\n{}", "Everything ring needs is working".green()),
+        1 => println!("\n{}", "1 problem found".red()),
+        n => println!("\n{}", format!("{} problems found", n).red()),
+    }
+    report.problems == 0
+}
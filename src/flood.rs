@@ -0,0 +1,118 @@
+use crate::{icmp_config, icons, percentile, rng};
+use colored::*;
+use rand::Rng;
+use serde::Serialize;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use surge_ping::{Client, PingIdentifier, PingSequence};
+
+/// The most echo requests per second --rate allows, so a typo can't saturate a link
+pub const MAX_RATE: u32 = 10_000;
+
+/// How long each step of the ramp sends for
+const STEP: Duration = Duration::from_secs(3);
+
+/// The ramp, in quarters of --rate
+const STEPS: [u32; 4] = [1, 2, 3, 4];
+
+/// How one step of the ramp went
+#[derive(Serialize, Debug)]
+pub struct Step {
+    rate: u32, // echo requests per second
+    sent: u32,
+    received: u32,
+    loss_percent: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    avg_ms: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    p95_ms: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    added_ms: Option<f64>, // avg_ms over the first step's, i.e. the latency the load added
+}
+
+/// --icmp-flood's load-vs-loss report for one target
+#[derive(Serialize, Debug)]
+pub struct Report {
+    host: String,
+    addr: IpAddr,
+    steps: Vec<Step>,
+}
+
+/// Send echo requests at `rate` per second for one step, without waiting for replies in
+/// between, and collect the round trips of those that were answered in microseconds
+async fn step(client: &Client, addr: IpAddr, rate: u32, timeout: Duration, payload: &Arc<[u8]>) -> (u32, Vec<u128>) {
+    let id = PingIdentifier(rng::with(|rng| rng.gen()));
+    let interval = Duration::from_secs(1) / rate;
+    let sent = (u128::from(rate) * STEP.as_millis() / 1000) as u32;
+    let start = tokio::time::Instant::now();
+
+    let mut echoes = Vec::with_capacity(sent as usize);
+    for seq in 0..sent {
+        tokio::time::sleep_until(start + interval * seq).await;
+        let mut pinger = client.pinger(addr, id).await;
+        pinger.timeout(timeout);
+        let payload = payload.clone();
+        // Even at MAX_RATE a step sends fewer echoes than there are sequence numbers
+        echoes.push(tokio::spawn(async move { pinger.ping(PingSequence(seq as u16), &payload).await }));
+    }
+
+    let mut rtts = Vec::new();
+    for echo in echoes {
+        if let Ok(Ok((_, rtt))) = echo.await {
+            rtts.push(rtt.as_micros());
+        }
+    }
+    (sent, rtts)
+}
+
+/// --icmp-flood: ping `addr` in four steps of STEP each, at a quarter, half, three quarters
+/// and all of `rate`, and report how loss and latency change as the load grows
+pub async fn run(host: &str, addr: IpAddr, rate: u32, timeout: Duration, size: usize) -> Result<Report, String> {
+    let client = Client::new(&icmp_config(addr)).map_err(|e| format!("can't open an echo socket for {}: {}", addr, e))?;
+    let payload: Arc<[u8]> = vec![0; size].into();
+
+    let mut steps: Vec<Step> = Vec::new();
+    let mut rates: Vec<u32> = STEPS.iter().map(|&quarters| (rate * quarters / 4).max(1)).collect();
+    rates.dedup();
+    for rate in rates {
+        let (sent, rtts) = step(&client, addr, rate, timeout, &payload).await;
+        let ms = |us: f64| us / 1000.0;
+        let avg_ms = (!rtts.is_empty()).then(|| ms(rtts.iter().sum::<u128>() as f64 / rtts.len() as f64));
+        let first_avg = steps.first().and_then(|s| s.avg_ms);
+        steps.push(Step {
+            rate,
+            sent,
+            received: rtts.len() as u32,
+            loss_percent: (sent - rtts.len() as u32) as f64 / sent as f64 * 100.0,
+            avg_ms,
+            p95_ms: percentile(&rtts, 95.0).map(ms),
+            added_ms: first_avg.zip(avg_ms).map(|(first, avg)| avg - first),
+        });
+    }
+    Ok(Report { host: host.to_string(), addr, steps })
+}
+
+impl Report {
+    pub fn print(&self) {
+        let name = if self.host == self.addr.to_string() { self.host.clone() } else { format!("{} ({})", self.host, self.addr) };
+        println!("\n{} {}", format!("{}ICMP flood:", icons::lead("🌊")).bold(), name.blue());
+        println!("{}", icons::rule().dimmed());
+        for step in &self.steps {
+            let loss = format!("{:.1}% loss", step.loss_percent);
+            let loss = match step.loss_percent {
+                0.0 => loss.green(),
+                l if l < 5.0 => loss.yellow(),
+                _ => loss.red(),
+            };
+            let latency = match (step.avg_ms, step.p95_ms) {
+                (Some(avg), Some(p95)) => {
+                    let added = step.added_ms.map_or(String::new(), |ms| format!(" ({:+.2})", ms));
+                    format!(", avg {:.2} ms{}, p95 {:.2} ms", avg, added.dimmed(), p95)
+                }
+                _ => String::new(),
+            };
+            println!("{:>7}/s {} {}/{} replies, {}{}", step.rate, icons::get("arrow"), step.received, step.sent, loss, latency);
+        }
+    }
+}
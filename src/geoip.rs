@@ -0,0 +1,67 @@
+use crate::HostResult;
+
+/// One or more MaxMind databases (e.g. GeoLite2-Country and GeoLite2-ASN) consulted together
+#[cfg(feature = "geoip")]
+pub struct GeoIp {
+    readers: Vec<maxminddb::Reader<Vec<u8>>>,
+}
+
+#[cfg(not(feature = "geoip"))]
+pub struct GeoIp;
+
+/// The fields ring uses, present in Country/City and ASN/ISP databases alike
+#[cfg(feature = "geoip")]
+#[derive(serde::Deserialize)]
+struct Record<'a> {
+    #[serde(borrow)]
+    country: Option<maxminddb::geoip2::country::Country<'a>>,
+    autonomous_system_number: Option<u32>,
+    autonomous_system_organization: Option<&'a str>,
+}
+
+impl GeoIp {
+    #[cfg(feature = "geoip")]
+    pub fn open(paths: &[String]) -> Result<Self, String> {
+        let readers = paths
+            .iter()
+            .map(|path| {
+                maxminddb::Reader::open_readfile(path)
+                    .map_err(|e| format!("failed to open GeoIP database '{}': {}", path, e))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(GeoIp { readers })
+    }
+
+    #[cfg(not(feature = "geoip"))]
+    pub fn open(_paths: &[String]) -> Result<Self, String> {
+        Err("--geoip requires ring to be built with the `geoip` feature (cargo install --features geoip)".to_string())
+    }
+
+    /// Fill in `country`, `asn` and `as_org` for every result with a known address. Each
+    /// database fills what it has; lookups that miss leave the fields unset.
+    #[cfg(feature = "geoip")]
+    pub fn annotate(&self, results: &mut [HostResult]) {
+        for result in results {
+            let Some(ip) = result.resolved_addr.map(|addr| addr.ip).or_else(|| result.host.parse().ok()) else {
+                continue;
+            };
+            for reader in &self.readers {
+                let Ok(Some(record)) = reader.lookup::<Record>(ip) else {
+                    continue;
+                };
+                if let Some(code) = record.country.and_then(|c| c.iso_code) {
+                    result.country.get_or_insert_with(|| code.to_string());
+                }
+                if let Some(asn) = record.autonomous_system_number {
+                    result.asn.get_or_insert(asn);
+                }
+                if let Some(org) = record.autonomous_system_organization {
+                    result.as_org.get_or_insert_with(|| org.to_string());
+                }
+            }
+        }
+    }
+
+    #[cfg(not(feature = "geoip"))]
+    pub fn annotate(&self, _results: &mut [HostResult]) {}
+}
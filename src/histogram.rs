@@ -0,0 +1,129 @@
+use crate::icons;
+use colored::*;
+use serde::{Deserialize, Serialize};
+
+/// Default bucket edges in milliseconds: 0-5, 5-10, 10-20, ... 500+
+const DEFAULT_EDGES: [f64; 8] = [0.0, 5.0, 10.0, 20.0, 50.0, 100.0, 200.0, 500.0];
+
+const BAR_WIDTH: usize = 40;
+
+/// How samples are split into buckets
+#[derive(Debug, Clone)]
+pub enum BucketSpec {
+    /// This many equal-width buckets between the smallest and largest sample
+    Count(usize),
+    /// Explicit lower edges; the last bucket is open-ended
+    Edges(Vec<f64>),
+}
+
+impl Default for BucketSpec {
+    fn default() -> Self {
+        BucketSpec::Edges(DEFAULT_EDGES.to_vec())
+    }
+}
+
+/// Parse `--hist-buckets`: a single number is a bucket count, a comma-separated list is bucket edges
+pub fn parse_bucket_spec(s: &str) -> Result<BucketSpec, String> {
+    if !s.contains(',') {
+        return match s.trim().parse::<usize>() {
+            Ok(0) | Err(_) => Err(format!("invalid --hist-buckets '{}': expected a bucket count or comma-separated edges", s)),
+            Ok(n) => Ok(BucketSpec::Count(n)),
+        };
+    }
+
+    let mut edges = s
+        .split(',')
+        .map(|e| e.trim().parse::<f64>().map_err(|_| format!("invalid --hist-buckets edge '{}'", e.trim())))
+        .collect::<Result<Vec<_>, _>>()?;
+    if edges.windows(2).any(|w| w[0] >= w[1]) {
+        return Err(format!("invalid --hist-buckets '{}': edges must be strictly increasing", s));
+    }
+    // Everything below the first edge still needs a home
+    if edges[0] > 0.0 {
+        edges.insert(0, 0.0);
+    }
+    Ok(BucketSpec::Edges(edges))
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Bucket {
+    pub min_ms: f64,
+    pub max_ms: Option<f64>, // None for the open-ended last bucket
+    pub count: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Histogram {
+    pub samples: usize,
+    pub buckets: Vec<Bucket>,
+}
+
+pub fn build(samples: &[u128], spec: &BucketSpec) -> Histogram {
+    let values: Vec<f64> = samples.iter().map(|&s| s as f64).collect();
+
+    let mut buckets: Vec<Bucket> = match spec {
+        BucketSpec::Edges(edges) => edges
+            .iter()
+            .enumerate()
+            .map(|(i, &min_ms)| Bucket { min_ms, max_ms: edges.get(i + 1).copied(), count: 0 })
+            .collect(),
+        BucketSpec::Count(n) => {
+            let lo = values.iter().copied().fold(f64::INFINITY, f64::min);
+            let hi = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+            let (lo, hi) = if values.is_empty() { (0.0, 0.0) } else { (lo, hi) };
+            let width = ((hi - lo) / *n as f64).max(1.0);
+            (0..*n)
+                .map(|i| Bucket {
+                    min_ms: lo + width * i as f64,
+                    max_ms: Some(lo + width * (i + 1) as f64),
+                    count: 0,
+                })
+                .collect()
+        }
+    };
+
+    for value in values {
+        // Each sample lands in the last bucket whose lower edge it reaches
+        let index = buckets
+            .iter()
+            .rposition(|b| value >= b.min_ms)
+            .unwrap_or(0);
+        buckets[index].count += 1;
+    }
+
+    Histogram { samples: samples.len(), buckets }
+}
+
+fn format_edge(ms: f64) -> String {
+    if ms.fract() == 0.0 {
+        format!("{}", ms)
+    } else {
+        format!("{:.1}", ms)
+    }
+}
+
+fn bucket_label(bucket: &Bucket) -> String {
+    match bucket.max_ms {
+        Some(max) => format!("{}-{} ms", format_edge(bucket.min_ms), format_edge(max)),
+        None => format!("{}+ ms", format_edge(bucket.min_ms)),
+    }
+}
+
+pub fn print_histogram(histogram: &Histogram) {
+    println!("\n{}Latency Histogram ({} samples)", icons::lead("📈"), histogram.samples);
+    println!("{}", icons::rule().dimmed());
+
+    if histogram.samples == 0 {
+        println!("{}", "No successful samples".dimmed());
+        return;
+    }
+
+    let labels: Vec<String> = histogram.buckets.iter().map(bucket_label).collect();
+    let label_width = labels.iter().map(|l| l.len()).max().unwrap_or(0);
+    let largest = histogram.buckets.iter().map(|b| b.count).max().unwrap_or(0).max(1);
+
+    for (bucket, label) in histogram.buckets.iter().zip(labels) {
+        let bar = icons::get("bar").repeat(bucket.count * BAR_WIDTH / largest);
+        println!("{:>width$} {}{} {}", label, icons::pick("│", "|"), bar.cyan(), bucket.count, width = label_width);
+    }
+}
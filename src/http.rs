@@ -0,0 +1,182 @@
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::{timeout, Instant};
+use tokio_rustls::rustls::pki_types::ServerName;
+use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+use tokio_rustls::TlsConnector;
+
+/// Where the time went in one HTTP attempt, after the TCP connection was established
+#[derive(Debug, Clone)]
+pub struct HttpTiming {
+    pub tls_ms: Option<f64>,
+    pub ttfb_ms: f64,
+    pub status: Option<u16>,
+    /// --throughput: download rate after the first byte, in megabytes per second
+    pub throughput_mbps: Option<f64>,
+    /// --max-response-bytes: the start of the response body
+    pub body: Option<Vec<u8>>,
+    /// How long that download or body took, so it can be left out of the exchange's latency
+    pub download_time: Duration,
+}
+
+/// What to read of the response after its first byte
+#[derive(Debug, Clone, Copy)]
+pub enum Download {
+    /// --throughput: up to this many bytes, to time them
+    Throughput(usize),
+    /// --max-response-bytes: up to this many bytes of the body, to keep them
+    Body(usize),
+}
+
+fn tls_connector() -> TlsConnector {
+    static CONFIG: OnceLock<Arc<ClientConfig>> = OnceLock::new();
+    let config = CONFIG.get_or_init(|| {
+        let roots = RootCertStore { roots: webpki_roots::TLS_SERVER_ROOTS.to_vec() };
+        Arc::new(ClientConfig::builder().with_root_certificates(roots).with_no_client_auth())
+    });
+    TlsConnector::from(config.clone())
+}
+
+/// --captive-check requests this, which connectivity-check endpoints such as
+/// connectivitycheck.gstatic.com answer with an empty 204
+pub const CAPTIVE_CHECK_PATH: &str = "/generate_204";
+
+fn elapsed_ms(since: Instant) -> f64 {
+    since.elapsed().as_secs_f64() * 1000.0
+}
+
+/// Send `GET path` and wait for the first byte of the response, returning the time to first byte,
+/// the status code if the first chunk contained a status line, and the chunk itself. With
+/// `keep_alive` the server is asked to leave the connection open afterwards.
+async fn request<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    host: &str,
+    path: &str,
+    keep_alive: bool,
+) -> Result<(f64, Option<u16>, Vec<u8>), String> {
+    let request = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: ring/{}\r\nConnection: {}\r\n\r\n",
+        path,
+        host,
+        env!("CARGO_PKG_VERSION"),
+        if keep_alive { "keep-alive" } else { "close" }
+    );
+
+    let start = Instant::now();
+    stream.write_all(request.as_bytes()).await.map_err(|e| format!("http_error: {}", e))?;
+
+    let mut buf = [0u8; 64];
+    let n = stream.read(&mut buf).await.map_err(|e| format!("http_error: {}", e))?;
+    let ttfb = elapsed_ms(start);
+    if n == 0 {
+        return Err("http_error: connection closed before response".to_string());
+    }
+
+    // "HTTP/1.1 200 OK" → 200
+    let status = std::str::from_utf8(&buf[..n])
+        .ok()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|code| code.parse().ok());
+
+    Ok((ttfb, status, buf[..n].to_vec()))
+}
+
+/// Keep reading the response, up to `limit` more bytes or until `budget` runs out, and return
+/// the rate in megabytes per second and the time taken. Whatever arrived before a timeout still
+/// counts.
+async fn download<S: AsyncRead + Unpin>(stream: &mut S, limit: usize, budget: Duration) -> (Option<f64>, Duration) {
+    let start = Instant::now();
+    let deadline = start + budget;
+    let mut buf = vec![0u8; 16 * 1024];
+    let mut received = 0;
+    while received < limit {
+        match tokio::time::timeout_at(deadline, stream.read(&mut buf)).await {
+            Ok(Ok(n)) if n > 0 => received += n,
+            _ => break,
+        }
+    }
+    let took = start.elapsed();
+    let secs = took.as_secs_f64();
+    ((received > 0 && secs > 0.0).then(|| received as f64 / secs / 1e6), took)
+}
+
+/// Keep reading the response after `received`, its first chunk, until the headers have ended and
+/// `limit` bytes of the body have arrived, the server closes, or `budget` runs out. Returns the
+/// body as received (chunked framing included, without decompression) and the time taken.
+async fn read_body<S: AsyncRead + Unpin>(stream: &mut S, mut received: Vec<u8>, limit: usize, budget: Duration) -> (Vec<u8>, Duration) {
+    let start = Instant::now();
+    let deadline = start + budget;
+    let mut buf = vec![0u8; 16 * 1024];
+    let body_start = loop {
+        let headers_end = received.windows(4).position(|w| w == b"\r\n\r\n").map(|i| i + 4);
+        if let Some(end) = headers_end.filter(|&end| received.len() - end >= limit) {
+            break Some(end);
+        }
+        match tokio::time::timeout_at(deadline, stream.read(&mut buf)).await {
+            Ok(Ok(n)) if n > 0 => received.extend_from_slice(&buf[..n]),
+            _ => break headers_end,
+        }
+    };
+    let body = body_start.map_or_else(Vec::new, |end| received[end..].iter().take(limit).copied().collect());
+    (body, start.elapsed())
+}
+
+/// Read what `download` asks for of the response whose first chunk was `first`
+async fn read_rest<S: AsyncRead + Unpin>(
+    stream: &mut S,
+    download: Option<Download>,
+    first: Vec<u8>,
+    budget: Duration,
+) -> (Option<f64>, Option<Vec<u8>>, Duration) {
+    match download {
+        Some(Download::Throughput(limit)) => {
+            let (throughput_mbps, took) = self::download(stream, limit, budget).await;
+            (throughput_mbps, None, took)
+        }
+        Some(Download::Body(limit)) => {
+            let (body, took) = read_body(stream, first, limit, budget).await;
+            (None, Some(body), took)
+        }
+        None => (None, None, Duration::ZERO),
+    }
+}
+
+/// Run one HTTP (or, with `tls`, HTTPS) request for `path` over an already-connected stream. The
+/// stream is only borrowed so the caller can inspect the socket afterwards. With `download`, more
+/// of the response is read afterwards, to measure throughput or to keep the start of the body.
+/// `keep_alive` is for --hold, which keeps the connection open after the exchange.
+pub async fn probe(
+    stream: &mut TcpStream,
+    host: &str,
+    path: &str,
+    tls: bool,
+    timeout_ms: u64,
+    download: Option<Download>,
+    keep_alive: bool,
+) -> Result<HttpTiming, String> {
+    let budget = Duration::from_millis(timeout_ms);
+
+    if !tls {
+        let (ttfb_ms, status, first) = timeout(budget, request(stream, host, path, keep_alive))
+            .await
+            .map_err(|_| "http_timeout".to_string())??;
+        let (throughput_mbps, body, download_time) = read_rest(stream, download, first, budget).await;
+        return Ok(HttpTiming { tls_ms: None, ttfb_ms, status, throughput_mbps, body, download_time });
+    }
+
+    let server_name = ServerName::try_from(host.to_string()).map_err(|e| format!("tls_error: {}", e))?;
+    let start = Instant::now();
+    let mut stream = timeout(budget, tls_connector().connect(server_name, stream))
+        .await
+        .map_err(|_| "tls_timeout".to_string())?
+        .map_err(|e| format!("tls_error: {}", e))?;
+    let tls_ms = elapsed_ms(start);
+
+    let (ttfb_ms, status, first) = timeout(budget, request(&mut stream, host, path, keep_alive))
+        .await
+        .map_err(|_| "http_timeout".to_string())??;
+    let (throughput_mbps, body, download_time) = read_rest(&mut stream, download, first, budget).await;
+    Ok(HttpTiming { tls_ms: Some(tls_ms), ttfb_ms, status, throughput_mbps, body, download_time })
+}
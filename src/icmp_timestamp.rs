@@ -0,0 +1,128 @@
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::net::Ipv4Addr;
+use std::time::Duration;
+
+/// Whether this platform can send timestamp requests at all
+pub const SUPPORTED: bool = cfg!(target_os = "linux");
+
+#[cfg(target_os = "linux")]
+const TIMESTAMP_REQUEST: u8 = 13;
+#[cfg(target_os = "linux")]
+const TIMESTAMP_REPLY: u8 = 14;
+
+const MS_PER_DAY: i64 = 86_400_000;
+
+/// A timestamp reply's clocks, in milliseconds since midnight UT (RFC 792): when we sent the
+/// request, when the host received it and when it sent the reply, plus when the reply got back
+/// to us. A host whose clock isn't UT sets the high bit, and its values mean what it likes.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timestamps {
+    pub originate: u32,
+    pub receive: u32,
+    pub transmit: u32,
+    pub arrival: u32,
+}
+
+/// `later - earlier` across midnight, in whichever direction is shorter
+fn since(later: u32, earlier: u32) -> i64 {
+    let diff = (i64::from(later) - i64::from(earlier)).rem_euclid(MS_PER_DAY);
+    if diff > MS_PER_DAY / 2 {
+        diff - MS_PER_DAY
+    } else {
+        diff
+    }
+}
+
+impl Timestamps {
+    fn is_standard(&self) -> bool {
+        (self.receive | self.transmit) & 0x8000_0000 == 0
+    }
+
+    /// How far the host's clock is ahead of ours, assuming the path takes as long each way
+    pub fn clock_offset_ms(&self) -> Option<f64> {
+        self.is_standard()
+            .then(|| (since(self.receive, self.originate) + since(self.transmit, self.arrival)) as f64 / 2.0)
+    }
+}
+
+/// Our clock as a timestamp field
+#[cfg(target_os = "linux")]
+fn now() -> u32 {
+    use std::time::{SystemTime, UNIX_EPOCH};
+    let ms = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis());
+    (ms % MS_PER_DAY as u128) as u32
+}
+
+/// Fail early, with the OS error, when the process isn't allowed to open raw sockets
+#[cfg(target_os = "linux")]
+pub fn check_privileges() -> io::Result<()> {
+    crate::socket::raw_ipv4_socket(socket2::Protocol::ICMPV4).map(drop)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn check_privileges() -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "ICMP timestamp requests are only supported on Linux"))
+}
+
+#[cfg(target_os = "linux")]
+fn request(id: u16, seq: u16, originate: u32) -> [u8; 20] {
+    let mut message = [0u8; 20];
+    message[0] = TIMESTAMP_REQUEST;
+    message[4..6].copy_from_slice(&id.to_be_bytes());
+    message[6..8].copy_from_slice(&seq.to_be_bytes());
+    message[8..12].copy_from_slice(&originate.to_be_bytes());
+    let checksum = crate::socket::internet_checksum(&[&message]);
+    message[2..4].copy_from_slice(&checksum.to_be_bytes());
+    message
+}
+
+/// Send one timestamp request to `dst` and wait for its reply. `None` means no reply came
+/// before the timeout, which is common: many hosts and firewalls drop these.
+#[cfg(target_os = "linux")]
+pub async fn probe(
+    dst: Ipv4Addr,
+    id: u16,
+    seq: u16,
+    timeout: Duration,
+    dscp: Option<u8>,
+    fwmark: Option<u32>,
+) -> io::Result<Option<(Timestamps, Duration)>> {
+    use socket2::SockAddr;
+    use std::net::SocketAddrV4;
+    use tokio::time::Instant;
+
+    let socket = crate::socket::open_raw_ipv4(socket2::Protocol::ICMPV4, dscp, fwmark)?;
+
+    let originate = now();
+    let start = Instant::now();
+    let deadline = start + timeout;
+    socket.get_ref().send_to(&request(id, seq, originate), &SockAddr::from(SocketAddrV4::new(dst, 0)))?;
+
+    // Wait for the reply answering ours
+    let reply = crate::socket::recv_raw_from(&socket, dst, deadline, 20, |icmp| {
+        let field = |at: usize| u32::from_be_bytes([icmp[at], icmp[at + 1], icmp[at + 2], icmp[at + 3]]);
+        let ours = icmp[0] == TIMESTAMP_REPLY
+            && u16::from_be_bytes([icmp[4], icmp[5]]) == id
+            && u16::from_be_bytes([icmp[6], icmp[7]]) == seq
+            && field(8) == originate;
+        ours.then(|| (field(12), field(16)))
+    })
+    .await?;
+    Ok(reply.map(|(receive, transmit)| {
+        let timestamps = Timestamps { originate, receive, transmit, arrival: now() };
+        (timestamps, start.elapsed())
+    }))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub async fn probe(
+    _dst: Ipv4Addr,
+    _id: u16,
+    _seq: u16,
+    _timeout: Duration,
+    _dscp: Option<u8>,
+    _fwmark: Option<u32>,
+) -> io::Result<Option<(Timestamps, Duration)>> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "ICMP timestamp requests are only supported on Linux"))
+}
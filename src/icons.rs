@@ -0,0 +1,76 @@
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+/// The glyphs --icon can replace: name, the default, and the --ascii stand-in
+const GLYPHS: &[(&str, &str, &str)] = &[
+    ("up", "✅", "[OK]"),
+    ("down", "❌", "[FAIL]"),
+    ("partial", "⚠️", "[WARN]"),
+    ("filtered", "🚫", "[FILTERED]"),
+    ("unreachable", "⛔", "[UNREACHABLE]"),
+    ("unknown", "❓", "[?]"),
+    ("error", "❌", "[ERROR]"),
+    ("warning", "⚠️", "[WARN]"),
+    ("change", "🔔", "[CHANGE]"),
+    ("arrow", "→", "->"),
+    ("detail", "↳", "`-"),
+    ("separator", "·", "|"),
+    ("plus-minus", "±", "+/-"),
+    ("times", "×", "x"),
+    ("rule", "━", "-"),
+    ("bar", "█", "#"),
+    ("empty", "·", "."),
+];
+
+struct Icons {
+    ascii: bool,
+    overrides: HashMap<String, &'static str>,
+}
+
+static ICONS: OnceLock<Icons> = OnceLock::new();
+
+/// Set --ascii and the --icon overrides. Only the first call counts; output before it, such as
+/// errors in the arguments, uses the defaults.
+pub fn init(ascii: bool, overrides: &[(String, String)]) {
+    let overrides = overrides.iter().map(|(name, glyph)| (name.clone(), &*glyph.clone().leak())).collect();
+    let _ = ICONS.set(Icons { ascii, overrides });
+}
+
+/// Whether --ascii is on, for output with its own plain form
+pub fn ascii() -> bool {
+    ICONS.get().is_some_and(|icons| icons.ascii)
+}
+
+/// The glyph called `name`, as --ascii and --icon say
+pub fn get(name: &str) -> &'static str {
+    if let Some(glyph) = ICONS.get().and_then(|icons| icons.overrides.get(name)) {
+        return glyph;
+    }
+    let &(_, emoji, plain) = GLYPHS.iter().find(|(n, ..)| *n == name).expect("every glyph used is in GLYPHS");
+    pick(emoji, plain)
+}
+
+/// A heading's decorative emoji and the space after it, or nothing under --ascii
+pub fn lead(emoji: &str) -> String {
+    if ascii() { String::new() } else { format!("{} ", emoji) }
+}
+
+/// A fixed marker that --icon can't change: `emoji`, or `plain` under --ascii
+pub fn pick(emoji: &'static str, plain: &'static str) -> &'static str {
+    if ascii() { plain } else { emoji }
+}
+
+/// The line under a heading
+pub fn rule() -> String {
+    get("rule").repeat(45)
+}
+
+/// Parse `--icon NAME=GLYPH`
+pub fn parse_override(s: &str) -> Result<(String, String), String> {
+    let names = || GLYPHS.iter().map(|(name, ..)| *name).collect::<Vec<_>>().join(", ");
+    let (name, glyph) = s.split_once('=').ok_or_else(|| format!("expected NAME=GLYPH, where NAME is one of {}", names()))?;
+    if !GLYPHS.iter().any(|(n, ..)| *n == name) {
+        return Err(format!("unknown icon '{}'; expected one of {}", name, names()));
+    }
+    Ok((name.to_string(), glyph.to_string()))
+}
@@ -0,0 +1,41 @@
+use serde::Deserialize;
+
+/// `{"hosts": [...]}`, for inventories that wrap their list
+#[derive(Deserialize)]
+struct Wrapped {
+    hosts: Vec<String>,
+}
+
+/// Read a host list: a JSON array of hosts or `{"hosts": [...]}`, otherwise one host per line
+/// with blank lines and `#` comments skipped. Entries are anything a host argument can be, e.g.
+/// `db1:5432` or `10.0.0.0/28`. An empty list is an error, since there would be nothing to scan.
+fn parse(body: &str) -> Result<Vec<String>, String> {
+    let body = body.trim();
+    let hosts = if body.starts_with('[') {
+        serde_json::from_str::<Vec<String>>(body).map_err(|e| format!("invalid JSON host list: {}", e))?
+    } else if body.starts_with('{') {
+        serde_json::from_str::<Wrapped>(body).map_err(|e| format!("invalid JSON host list: {}", e))?.hosts
+    } else {
+        body.lines()
+            .map(|line| line.split('#').next().unwrap_or_default().trim())
+            .filter(|line| !line.is_empty())
+            .map(str::to_string)
+            .collect()
+    };
+    let hosts: Vec<String> = hosts.into_iter().map(|h| h.trim().to_string()).filter(|h| !h.is_empty()).collect();
+    if hosts.is_empty() {
+        return Err("the host list is empty".to_string());
+    }
+    Ok(hosts)
+}
+
+/// --targets-url: fetch the host list
+pub async fn fetch(client: &reqwest::Client, url: &str) -> Result<Vec<String>, String> {
+    let fail = |e: String| format!("couldn't fetch targets from {}: {}", url, e);
+    let response = client.get(url).send().await.map_err(|e| fail(e.to_string()))?;
+    if !response.status().is_success() {
+        return Err(fail(format!("HTTP {}", response.status())));
+    }
+    let body = response.text().await.map_err(|e| fail(e.to_string()))?;
+    parse(&body).map_err(fail)
+}
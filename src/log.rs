@@ -0,0 +1,114 @@
+use crate::{report_warning, HostResult, ScanResult};
+use chrono::Local;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Append-only NDJSON log of every scan, optionally rotated by size
+#[derive(Debug)]
+pub struct ScanLog {
+    path: PathBuf,
+    rotate_bytes: Option<u64>,
+}
+
+impl ScanLog {
+    pub fn new(path: impl Into<PathBuf>, rotate_mb: Option<u64>) -> Self {
+        ScanLog { path: path.into(), rotate_bytes: rotate_mb.map(|mb| mb * 1024 * 1024) }
+    }
+
+    /// Once the log has reached the size limit, move it aside as `<path>.<timestamp>` so the
+    /// next write starts a fresh file
+    fn rotate_if_needed(&self) -> std::io::Result<()> {
+        let Some(limit) = self.rotate_bytes else {
+            return Ok(());
+        };
+        match std::fs::metadata(&self.path) {
+            Ok(meta) if meta.len() >= limit => {
+                let mut rotated = self.path.clone().into_os_string();
+                rotated.push(format!(".{}", Local::now().format("%Y%m%dT%H%M%S")));
+                std::fs::rename(&self.path, rotated)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Write one record as a single JSON line. Failures are reported but never stop monitoring.
+    pub fn append(&self, record: &impl Serialize) {
+        if let Err(e) = self.rotate_if_needed() {
+            report_warning(&format!("failed to rotate log file '{}': {}", self.path.display(), e));
+        }
+
+        if let Err(e) = append_lines(&self.path, std::iter::once(record)) {
+            report_warning(&format!("failed to write log file '{}': {}", self.path.display(), e));
+        }
+    }
+}
+
+fn append_lines<T: Serialize>(path: &Path, records: impl IntoIterator<Item = T>) -> std::io::Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    for record in records {
+        writeln!(file, "{}", serde_json::to_string(&record)?)?;
+    }
+    Ok(())
+}
+
+/// One --output-dir line: a result, stamped with the scan it came from
+#[derive(Serialize)]
+struct HostRecord<'a> {
+    scan_timestamp: &'a str,
+    scan_epoch: u64,
+    #[serde(flatten)]
+    result: &'a HostResult,
+}
+
+/// --output-dir: an NDJSON file per host, `<dir>/<host>.ndjson`, each scan appending a line per
+/// result. The directory is created when it's missing, including after being removed mid-run.
+#[derive(Debug)]
+pub struct HostLogs {
+    dir: PathBuf,
+}
+
+/// `host` as a file name: anything but letters, digits, `-`, `_` and `.` becomes `_`, so IPv6
+/// addresses, `unix:` paths and zone ids can't leave the directory or trip up a filesystem.
+/// A leading dot is escaped too, which rules out `..` and hidden files.
+fn file_name(host: &str) -> String {
+    let name: String =
+        host.chars().map(|c| if c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.') { c } else { '_' }).collect();
+    match name.strip_prefix('.') {
+        Some(rest) => format!("_{}", rest),
+        None if name.is_empty() => "_".to_string(),
+        None => name,
+    }
+}
+
+impl HostLogs {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        HostLogs { dir: dir.into() }
+    }
+
+    /// Append `scan`'s results to their hosts' files. Failures are reported but never stop
+    /// monitoring, and one host's file failing doesn't keep the others from being written.
+    pub fn append(&self, scan: &ScanResult) {
+        if let Err(e) = std::fs::create_dir_all(&self.dir) {
+            report_warning(&format!("failed to create output directory '{}': {}", self.dir.display(), e));
+            return;
+        }
+
+        let mut by_host: BTreeMap<String, Vec<HostRecord>> = BTreeMap::new();
+        for result in &scan.results {
+            by_host.entry(file_name(&result.host)).or_default().push(HostRecord {
+                scan_timestamp: &scan.scan_timestamp,
+                scan_epoch: scan.scan_epoch,
+                result,
+            });
+        }
+        for (name, records) in by_host {
+            let path = self.dir.join(format!("{}.ndjson", name));
+            if let Err(e) = append_lines(&path, records) {
+                report_warning(&format!("failed to write '{}': {}", path.display(), e));
+            }
+        }
+    }
+}
@@ -1,12 +1,58 @@
+mod anomaly;
+mod arp;
+mod assertion;
+mod dashboard;
+mod diff;
+mod dns;
+mod doctor;
+mod flood;
+mod geoip;
+mod histogram;
+mod http;
+mod icmp_timestamp;
+mod icons;
+mod inventory;
+mod log;
+mod manifest;
+mod output;
+mod proxy;
+mod quic;
+mod resume;
+mod rng;
+mod scoped;
+mod serve;
+mod services;
+mod mdns;
+mod socket;
+mod sqlite;
+mod stability;
+mod stream;
+mod syn;
+mod table;
+mod template;
+mod watch;
+
+use chrono::{DateTime, Local, SecondsFormat, Utc};
 use clap::Parser;
 use colored::*;
+use rand::seq::SliceRandom;
+use rand::Rng;
 use serde::{Deserialize, Serialize};
-use std::net::{IpAddr, ToSocketAddrs};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{IsTerminal, Write};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::process::ExitCode;
 use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::io::AsyncReadExt;
 use tokio::net::TcpStream;
-use tokio::time::timeout;
+use tokio::time::{timeout, timeout_at};
 use futures::future;
-use surge_ping::{Client, Config, IcmpPacket, PingIdentifier, PingSequence};
+use futures::stream::{FuturesUnordered, StreamExt};
+use surge_ping::{Client, Config, IcmpPacket, PingIdentifier, PingSequence, SurgeError, ICMP};
+use scoped::ScopedIp;
 
 /// RING: Rust Internet Network Grapher — Multi-host + Multi-port TCP scanner with ICMP ping
 #[derive(Parser, Debug)]
@@ -22,398 +68,6335 @@ about = "RING: Rust Internet Network Grapher (TCP ping + ICMP ping + multi-host
           ring google.com cloudflare.com --ping     # Multiple hosts with ICMP ping
           ring 192.168.1.1-10 -p 22,80,443 --json  # Scan IP range with JSON output
           ring example.com -p 1000-2000 --once      # Port range scan, run once
-          ring api.example.com --ping -t 5000 -c 5  # Custom timeout and attempt count"
+          ring api.example.com --ping -t 5000 -c 5  # Custom timeout and attempt count
+          ring db:5432 web:80,443 [::1]:22          # Per-host port overrides
+          ring 10.0.0.0/24 2001:db8::/120 -p 22     # CIDR expansion (IPv4 and IPv6)
+          ring 192.168.0-2.1-254 -p 22              # nmap-style octet ranges",
+group(clap::ArgGroup::new("non_tcp").args(["ping", "arp", "quic"]).multiple(true))
 )]
 struct Args {
     /// One or more hostnames or IPs
     hosts: Vec<String>,
 
-    /// Ports to connect to, comma-separated or range (e.g. 80,443,1000-1005)
+    /// Also scan the hosts listed at this URL, fetched at startup: one per line, or a JSON array
+    #[arg(long, value_name = "URL")]
+    targets_url: Option<String>,
+
+    /// With --targets-url, fetch the list again before every scan, keeping the last good one
+    /// when that fails
+    #[arg(long, requires = "targets_url", conflicts_with = "once")]
+    refresh_targets: bool,
+
+    /// Ports to connect to: numbers, ranges or service names, comma-separated (e.g. 80,https,1000-1005)
     #[arg(short, long, default_value = "80")]
     ports: String,
 
-    /// Number of attempts per host+port
+    /// Take every Nth port of each range in -p and per-host port lists, starting from its first
+    /// port (e.g. -p 10000-20000 --port-step 100); single ports are unaffected
+    #[arg(long, value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..))]
+    port_step: u16,
+
+    /// Also scan the N most common TCP ports (up to 100), on top of -p
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..), conflicts_with = "no_tcp")]
+    ports_top: Option<u64>,
+
+    /// Number of attempts per probe (each host+port, and each host's ICMP ping)
     #[arg(short, long, default_value_t = 3)]
     count: u32,
 
-    /// Timeout for each connection attempt in milliseconds (default: 2000)
+    /// Number of TCP attempts per host+port, overriding -c
+    #[arg(long, value_name = "N")]
+    tcp_count: Option<u32>,
+
+    /// Number of ICMP echo requests per host, overriding -c
+    #[arg(long, value_name = "N")]
+    ping_count: Option<u32>,
+
+    /// Throwaway attempts per probe before the counted ones, to get cold caches, ARP and route
+    /// setup out of the way. They don't appear in the results.
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    warmup: u32,
+
+    /// Timeout in milliseconds for each connection attempt and any read after it (default: 2000)
     #[arg(short = 't', long, default_value_t = 2000)]
     timeout: u64,
 
+    /// Timeout for establishing each TCP connection in milliseconds, overriding -t
+    #[arg(long, value_name = "MS")]
+    connect_timeout: Option<u64>,
+
+    /// Timeout for reading a banner or HTTP response after connecting in milliseconds, overriding -t
+    #[arg(long, value_name = "MS")]
+    read_timeout: Option<u64>,
+
+    /// Randomize each attempt's timeout and the pause between scans by up to ±PERCENT, so probes
+    /// of many hosts on one interval don't all time out and retry in lockstep
+    #[arg(long, alias = "probe-timeout-jitter", value_name = "PERCENT", value_parser = parse_jitter_percent)]
+    timeout_jitter: Option<f64>,
+
+    /// Seed every random choice (ICMP identifiers, --timeout-jitter, source ports, SYN sequence
+    /// numbers) so a run can be repeated; without it they differ each run
+    #[arg(long, value_name = "N")]
+    seed: Option<u64>,
+
+    /// Report TCP and SYN results whose every attempt timed out as `filtered` rather than `down`
+    #[arg(long, overrides_with = "timeout_as_down")]
+    timeout_as_filtered: bool,
+
+    /// Report results whose every attempt timed out as `down` (the default; undoes --timeout-as-filtered)
+    #[arg(long, overrides_with = "timeout_as_filtered")]
+    timeout_as_down: bool,
+
+    /// Count a target as up once this many attempts succeed instead of requiring all of them
+    /// (capped at the attempt count)
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    require_count: Option<u32>,
+
+    /// End a probe as up as soon as this many attempts succeed, skipping the rest; `attempts`
+    /// then counts only the ones made
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    stop_after_successes: Option<u32>,
+
+    /// After the -c attempts, keep sampling TCP, SYN and ICMP probes until the 95% confidence
+    /// interval of the mean is within ±PERCENT of it, or --ramp-max attempts have been made
+    #[arg(long, value_name = "PERCENT", value_parser = parse_jitter_percent)]
+    ramp_ci: Option<f64>,
+
+    /// Most attempts a --ramp-ci probe makes
+    #[arg(long, default_value_t = 100, value_name = "N", requires = "ramp_ci")]
+    ramp_max: u32,
+
+    /// Only these failures move on to a probe's next attempt; any other ends it there, with its
+    /// remaining attempts failed. `dns` governs --dns-retries (default: retry everything)
+    #[arg(long, value_name = "CATEGORIES", value_delimiter = ',')]
+    retry_on: Option<Vec<RetryOn>>,
+
+    /// Retry a failed TCP or SYN connect up to N times within the same attempt before counting
+    /// it as failed, for failures --retry-on allows. Retries aren't measurements: only the try
+    /// that settled the attempt is timed.
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    connect_retries: u32,
+
+    /// Allow at most N retries (attempts after a failed one) across the whole scan; once they're
+    /// spent, a failing probe ends with its remaining attempts failed
+    #[arg(long, value_name = "N")]
+    retry_budget: Option<u32>,
+
+    /// Once an attempt succeeds, time out the probe's later attempts at a multiple of its slowest
+    /// round trip so far, never above the configured timeout
+    #[arg(long)]
+    adaptive_timeout: bool,
+
+    /// How many times the slowest round trip --adaptive-timeout waits for
+    #[arg(long, default_value_t = 3.0, value_name = "FACTOR", requires = "adaptive_timeout")]
+    adaptive_multiplier: f64,
+
+    /// Shortest timeout --adaptive-timeout will use, in milliseconds
+    #[arg(long, default_value_t = 100, value_name = "MS", requires = "adaptive_timeout")]
+    adaptive_floor: u64,
+
+    /// Verbose output (per-port detail under --by-host)
+    #[arg(short, long)]
+    verbose: bool,
+
+    /// Print a single aggregate status line per scan instead of the full summary
+    #[arg(long, conflicts_with = "json")]
+    summary_only: bool,
+
+    /// Group the summary by host: one line per host with its open ports
+    #[arg(long)]
+    by_host: bool,
+
+    /// Show the summary as a host × port grid (latency in each cell with -v)
+    #[arg(long, conflicts_with_all = ["by_host", "json", "summary_only"])]
+    table: bool,
+
+    /// Show the summary as a leaderboard of reachable targets, fastest average first
+    #[arg(long, conflicts_with_all = ["by_host", "table", "json", "summary_only"])]
+    compare_hosts: bool,
+
+    /// After the summary, set each TCP port's connect time beside its host's ICMP round trip
+    #[arg(long, requires = "ping", conflicts_with_all = ["json", "yaml", "json_by_host", "summary_only", "format",
+          "dashboard", "silent"])]
+    compare_protocols: bool,
+
+    /// Take over the terminal and redraw a host × port grid in place after every scan, with
+    /// latency and a trend arrow in each cell (continuous mode only)
+    #[arg(long, conflicts_with_all = ["once", "wait_up", "until_change", "json", "yaml", "json_by_host", "summary_only",
+          "silent", "baseline", "by_host", "table", "compare_hosts", "format"])]
+    dashboard: bool,
+
+    /// Print each result with this template instead of the summary, e.g. '{host}:{port} {status}'.
+    /// Placeholders: host, port, test_type, status, success, attempts, success_rate, avg_ms,
+    /// min_ms, max_ms, jitter_ms, addr, error. `{{`/`}}` are literal braces; \t and \n work too
+    #[arg(long, alias = "output-template", value_name = "TEMPLATE", value_parser = template::parse_template,
+          conflicts_with_all = ["by_host", "table", "compare_hosts", "json", "yaml", "summary_only"])]
+    format: Option<template::Template>,
+
     /// Quiet mode (suppress individual result lines)
     #[arg(short, long)]
     quiet: bool,
 
+    /// Print nothing at all, not even errors, and exit with status 6 if any result isn't up.
+    /// --json output is still printed.
+    #[arg(long, conflicts_with = "log_level")]
+    silent: bool,
+
     /// Output results in JSON format
     #[arg(short, long)]
     json: bool,
 
+    /// Output results in YAML format (the same structure as --json)
+    #[arg(long, conflicts_with_all = ["json", "json_by_host", "table", "by_host", "compare_hosts", "summary_only"])]
+    yaml: bool,
+
+    /// JSON output with results nested by host, then test type, then port (implies --json)
+    #[arg(long, conflicts_with_all = ["table", "summary_only"])]
+    json_by_host: bool,
+
+    /// Print each JSON document on a single line instead of pretty-printed (implies --json)
+    #[arg(long, alias = "no-pretty", conflicts_with = "yaml")]
+    json_compact: bool,
+
+    /// With --once and --format or --json-compact, print each result as soon as its probe
+    /// finishes and keep only the first N for the end of the scan, so a huge sweep doesn't hold
+    /// every result in memory (0 keeps none)
+    #[arg(long, value_name = "N", requires = "once",
+          conflicts_with_all = ["json_by_host", "rescan_failures", "silent", "ewma", "probe_label_from_ptr",
+          "histogram", "baseline", "baseline_save", "expect", "alert_command", "resume_file", "log_file", "sqlite",
+          "output", "summary_json_to", "fail_threshold_percent", "rescan_on_total_failure", "output_dir"])]
+    result_limit: Option<usize>,
+
+    /// Emit errors and warnings on stderr as JSON objects (one per line)
+    #[arg(long)]
+    json_errors: bool,
+
     /// Run once instead of continuously
     #[arg(short = 'i', long)]
     once: bool,
 
+    /// Delay between scans in continuous and --wait-up modes, in milliseconds (default: 5000)
+    #[arg(long, default_value_t = 5000, value_name = "MS")]
+    interval: u64,
+
+    /// Double the delay between continuous scans while every target stays up, dropping back to
+    /// --interval as soon as one isn't
+    #[arg(long)]
+    interval_adaptive: bool,
+
+    /// Start continuous scans on a fixed grid: at every multiple of --interval since the Unix
+    /// epoch (:00, :05, :10 … for 5 s), skipping a beat when a scan runs over
+    #[arg(long = "min-interval-drift-correction", alias = "align-interval",
+          conflicts_with_all = ["once", "interval_adaptive", "wait_up", "flaky_detector"])]
+    align_interval: bool,
+
+    /// Longest delay --interval-adaptive backs off to, in milliseconds
+    #[arg(long, default_value_t = 60000, value_name = "MS")]
+    max_interval: u64,
+
+    /// Retry a failed DNS lookup this many times before giving up on the host
+    #[arg(long, default_value_t = 2, value_name = "N")]
+    dns_retries: u32,
+
+    /// Re-probe results that aren't up up to this many more times, keeping the best outcome
+    #[arg(long, alias = "retries-on-partial", default_value_t = 0, value_name = "N")]
+    rescan_failures: u32,
+
+    /// When every result of a scan is down, wait a few seconds and run the whole scan once more
+    /// before reporting, since a local network blip is likelier than every target failing at once
+    #[arg(long)]
+    rescan_on_total_failure: bool,
+
+    /// Reuse successful DNS lookups for this many seconds (0 disables the cache)
+    #[arg(long, default_value_t = 30, value_name = "SECONDS")]
+    dns_cache_ttl: u64,
+
+    /// Keep scanning until every target is up, then exit 0
+    #[arg(long, conflicts_with = "once")]
+    wait_up: bool,
+
+    /// Give up --wait-up after this many milliseconds and exit with status 3
+    #[arg(long, value_name = "MS", requires = "wait_up")]
+    wait_timeout: Option<u64>,
+
+    /// Scan quietly, printing only status changes from the first scan, and exit after the first one
+    #[arg(long, conflicts_with_all = ["once", "wait_up", "baseline", "json_by_host", "summary_only"])]
+    until_change: bool,
+
+    /// With --until-change, keep scanning and printing changes instead of exiting after the first
+    #[arg(long, requires = "until_change")]
+    until_change_continuous: bool,
+
+    /// After the first full summary, print only targets whose status or --rtt-warn/--rtt-crit
+    /// latency band changed since the scan before
+    #[arg(long, conflicts_with_all = ["once", "wait_up", "until_change", "baseline", "dashboard", "summary_only",
+          "flaky_detector", "json", "json_compact", "json_by_host", "yaml"])]
+    only_changed: bool,
+
+    /// With --only-changed, print a `no changes` line after this many quiet scans in a row (0: never)
+    #[arg(long, value_name = "N", default_value_t = 10, requires = "only_changed")]
+    heartbeat: u32,
+
+    /// Run this many scans back to back and report how often each target was up, with the
+    /// spread of its latency, instead of each scan's results
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(2..),
+          conflicts_with_all = ["once", "wait_up", "until_change", "dashboard", "baseline", "json_by_host",
+          "summary_only", "by_host", "table", "compare_hosts", "format"])]
+    flaky_detector: Option<u32>,
+
+    /// Stop after this many seconds of wall-clock time overall and exit with status 5
+    #[arg(long, value_name = "SECONDS", value_parser = clap::value_parser!(u64).range(1..))]
+    max_runtime: Option<u64>,
+
     /// Enable ICMP ping in addition to TCP checks
     #[arg(long)]
     ping: bool,
 
+    /// Check hosts on the local network with ARP requests in addition to TCP checks (Linux, needs root)
+    #[arg(long)]
+    arp: bool,
+
+    /// Also attempt a QUIC handshake with each host on UDP --quic-port, offering HTTP/3
+    /// (requires the `quic` cargo feature)
+    #[arg(long)]
+    quic: bool,
+
+    /// UDP port for --quic handshakes
+    #[arg(long, default_value_t = 443, value_name = "PORT", requires = "quic")]
+    quic_port: u16,
+
+    /// Skip TCP checks entirely and only ping, ARP and/or QUIC (the default -p 80 is ignored)
+    #[arg(long, requires = "non_tcp", conflicts_with_all = ["ports", "expect"])]
+    no_tcp: bool,
+
     /// ICMP ping timeout in milliseconds (default: 1000)
     #[arg(long, default_value_t = 1000)]
     ping_timeout: u64,
-}
 
-#[derive(Serialize, Deserialize, Debug)]
-struct HostResult {
-    host: String,
-    port: Option<u16>,
-    test_type: String, // "tcp" or "icmp"
-    attempts: u32,
-    successful: u32,
-    success_rate: f64,
-    avg_response_time_ms: Option<f64>,
-    response_times: Vec<u128>,
-    status: String, // "up", "down", "partial"
-    error: Option<String>,
-}
+    /// Delay between successive ICMP echo requests to a host in milliseconds (default: 1000)
+    #[arg(long, default_value_t = 1000)]
+    ping_interval: u64,
 
-#[derive(Serialize, Deserialize, Debug)]
-struct ScanResult {
-    scan_timestamp: String,
-    results: Vec<HostResult>,
-}
+    /// Bytes of payload in each ICMP echo request (default: 0)
+    #[arg(long, value_name = "BYTES", default_value_t = 0, value_parser = clap::value_parser!(u16).range(..=65507))]
+    ping_size: u16,
 
-fn parse_ports(s: &str) -> Vec<u16> {
-    s.split(',')
-    .flat_map(|part| {
-        if part.contains('-') {
-            let mut range = part.split('-');
-            if let (Some(start), Some(end)) = (range.next(), range.next()) {
-                if let (Ok(start), Ok(end)) = (start.parse(), end.parse()) {
-                    return (start..=end).collect::<Vec<u16>>();
-                }
-            }
-            vec![]
-        } else {
-            part.parse().ok().into_iter().collect()
-        }
-    })
-    .collect()
-}
+    /// First ICMP sequence number sent to each host. Later scans carry on from where the last
+    /// one stopped, wrapping after 65535 (default: 1)
+    #[arg(long, value_name = "N", default_value_t = 1)]
+    icmp_sequence_start: u16,
 
-async fn tcp_check(host: String, port: u16, count: u32, timeout_ms: u64) -> HostResult {
-    let mut response_times = Vec::new();
-    let mut successful = 0;
-    let mut last_error = None;
+    /// First ICMP identifier to hand out. Pingers running at the same time always get distinct
+    /// identifiers, counting up from this one (default: random for each run)
+    #[arg(long, value_name = "N")]
+    icmp_id: Option<u16>,
 
-    let addr = format!("{}:{}", host, port);
-    let resolved = addr.to_socket_addrs().ok().and_then(|mut iter| iter.next());
+    /// Set the don't-fragment bit on ICMP echo requests, so ones too big for the path fail with
+    /// fragmentation_needed instead of being fragmented (Linux)
+    #[arg(long, requires = "ping")]
+    ping_df: bool,
 
-    if resolved.is_none() {
-        return HostResult {
-            host: host.to_string(),
-            port: Some(port),
-            test_type: "tcp".to_string(),
-            attempts: count,
-            successful: 0,
-            success_rate: 0.0,
-            avg_response_time_ms: None,
-            response_times: vec![],
-            status: "down".to_string(),
-            error: Some("dns_resolution_failed".to_string()),
-        };
-    }
+    /// Send ICMP timestamp requests instead of echo requests, recording the remote host's clock
+    /// and an estimate of its offset from ours (IPv4 only; needs raw sockets)
+    #[arg(long, requires = "ping", conflicts_with_all = ["ping_df", "mtu_discover"])]
+    icmp_timestamp: bool,
 
-    let socket_addr = resolved.unwrap();
-    let timeout_dur = Duration::from_millis(timeout_ms);
+    /// Mark ICMP results losing more than this percentage of echo requests down instead of partial
+    #[arg(long, value_name = "PERCENT")]
+    icmp_loss_threshold: Option<f64>,
 
-    for _ in 1..=count {
-        let start = Instant::now();
-        let result = timeout(timeout_dur, TcpStream::connect(socket_addr)).await;
-        let elapsed = start.elapsed();
+    /// After pinging, find each host's path MTU by searching for the largest echo request
+    /// that gets through with the don't-fragment bit set
+    #[arg(long, requires = "ping")]
+    mtu_discover: bool,
 
-        match result {
-            Ok(Ok(_)) => {
-                successful += 1;
-                response_times.push(elapsed.as_millis());
-            }
-            Ok(Err(e)) => {
-                last_error = Some(format!("connection_error: {}", e));
-            }
-            Err(_) => {
-                last_error = Some("timeout".to_string());
-            }
-        }
-    }
+    /// Color average latencies at or above this many milliseconds yellow
+    #[arg(long, default_value_t = 50.0, value_name = "MS")]
+    rtt_warn: f64,
 
-    let success_rate = successful as f64 / count as f64;
-    let avg_response_time = if !response_times.is_empty() {
-        Some(response_times.iter().sum::<u128>() as f64 / response_times.len() as f64)
-    } else {
-        None
-    };
+    /// Color average latencies at or above this many milliseconds red
+    #[arg(long, default_value_t = 200.0, value_name = "MS")]
+    rtt_crit: f64,
 
-    let status = match success_rate {
-        1.0 => "up",
-        0.0 => "down",
-        _ => "partial",
-    };
+    /// Show latencies in milliseconds, microseconds or seconds. JSON and YAML scan output is
+    /// converted too and gains a `unit` field; the field names keep their `_ms` suffix.
+    #[arg(long, value_enum, default_value_t = LatencyUnit::Ms, value_name = "UNIT")]
+    unit: LatencyUnit,
 
-    HostResult {
-        host: host.to_string(),
-        port: Some(port),
-        test_type: "tcp".to_string(),
-        attempts: count,
-        successful,
-        success_rate,
-        avg_response_time_ms: avg_response_time,
-        response_times,
-        status: status.to_string(),
-        error: if successful == 0 { last_error } else { None },
-    }
-}
+    /// When to use colors: auto (terminals only, honoring NO_COLOR), always or never
+    #[arg(long, value_enum, default_value_t = ColorChoice::Auto)]
+    color: ColorChoice,
 
-async fn icmp_ping(host: String, count: u32, timeout_ms: u64) -> HostResult {
-    let mut response_times = Vec::new();
-    let mut successful = 0;
-    let mut last_error = None;
+    /// Print plain ASCII markers such as [OK] and [FAIL] in place of emoji, arrows and box lines
+    #[arg(long)]
+    ascii: bool,
 
-    // Resolve hostname to IP
-    let ip_addr = match host.parse::<IpAddr>() {
-        Ok(ip) => ip,
-        Err(_) => {
-            // Try to resolve hostname
-            match tokio::net::lookup_host(format!("{}:0", host)).await {
-                Ok(mut addrs) => {
-                    if let Some(addr) = addrs.next() {
-                        addr.ip()
-                    } else {
-                        return HostResult {
-                            host: host.to_string(),
-                            port: None,
-                            test_type: "icmp".to_string(),
-                            attempts: count,
-                            successful: 0,
-                            success_rate: 0.0,
-                            avg_response_time_ms: None,
-                            response_times: vec![],
-                            status: "down".to_string(),
-                            error: Some("dns_resolution_failed".to_string()),
-                        };
-                    }
-                }
-                Err(e) => {
-                    return HostResult {
-                        host: host.to_string(),
-                        port: None,
-                        test_type: "icmp".to_string(),
-                        attempts: count,
-                        successful: 0,
-                        success_rate: 0.0,
-                        avg_response_time_ms: None,
-                        response_times: vec![],
-                        status: "down".to_string(),
-                        error: Some(format!("dns_error: {}", e)),
-                    };
-                }
-            }
-        }
-    };
+    /// Replace one status icon or marker, e.g. `--icon up=+ --icon down=-` (repeatable); NAME is
+    /// up, down, partial, filtered, unreachable, unknown, error, warning, change, arrow, detail,
+    /// separator, plus-minus, times, rule, bar or empty
+    #[arg(long = "icon", value_name = "NAME=GLYPH", value_parser = icons::parse_override)]
+    icons: Vec<(String, String)>,
 
-    // Create ICMP client
-    let config = Config::default();
-    let client = match Client::new(&config) {
-        Ok(client) => client,
-        Err(e) => {
-            return HostResult {
-                host: host.to_string(),
-                port: None,
-                test_type: "icmp".to_string(),
-                attempts: count,
-                successful: 0,
-                success_rate: 0.0,
-                avg_response_time_ms: None,
-                response_times: vec![],
-                status: "down".to_string(),
-                error: Some(format!("icmp_client_error: {} (try running as root/admin)", e)),
-            };
-        }
-    };
+    /// Log ring's own internals (DNS, probe scheduling, timeouts) to stderr at this level
+    #[arg(long, value_enum, value_name = "LEVEL")]
+    log_level: Option<LogLevel>,
 
-    let mut pinger = client.pinger(ip_addr, PingIdentifier(rand::random())).await;
-    pinger.timeout(Duration::from_millis(timeout_ms));
+    /// Show only the average latency instead of min/avg/max in the summary
+    #[arg(long)]
+    no_minmax: bool,
 
-    for i in 1..=count {
-        match pinger.ping(PingSequence(i as u16), &[]).await {
-            Ok((IcmpPacket::V4(_packet), duration)) => {
-                successful += 1;
-                response_times.push(duration.as_millis());
-            }
-            Ok((IcmpPacket::V6(_packet), duration)) => {
-                successful += 1;
-                response_times.push(duration.as_millis());
-            }
-            Err(e) => {
-                last_error = Some(format!("ping_error: {}", e));
-            }
-        }
-    }
+    /// Leave the error message off down and filtered results, or with `lines` leave their lines
+    /// out of the summary altogether; the totals still count them
+    #[arg(long, value_enum, value_name = "WHAT", num_args = 0..=1, require_equals = true, default_missing_value = "detail")]
+    quiet_errors: Option<QuietErrors>,
 
-    let success_rate = successful as f64 / count as f64;
-    let avg_response_time = if !response_times.is_empty() {
-        Some(response_times.iter().sum::<u128>() as f64 / response_times.len() as f64)
-    } else {
-        None
-    };
+    /// Track an exponentially weighted moving average of each target's latency across scans
+    #[arg(long)]
+    ewma: bool,
 
-    let status = match success_rate {
-        1.0 => "up",
-        0.0 => "down",
-        _ => "partial",
-    };
+    /// Weight of the newest scan in the --ewma average, between 0 and 1
+    #[arg(long, default_value_t = 0.3, value_name = "ALPHA", value_parser = parse_ewma_alpha, requires = "ewma")]
+    ewma_alpha: f64,
 
-    HostResult {
-        host: host.to_string(),
-        port: None,
-        test_type: "icmp".to_string(),
-        attempts: count,
-        successful,
-        success_rate,
-        avg_response_time_ms: avg_response_time,
-        response_times,
-        status: status.to_string(),
-        error: if successful == 0 { last_error } else { None },
-    }
-}
+    /// Downgrade results whose average latency (or --status-metric) exceeds this many milliseconds to partial
+    #[arg(long, value_name = "MS")]
+    max_rtt: Option<f64>,
 
-fn print_human_readable(results: &[HostResult]) {
-    println!("\n📊 Summary");
-    println!("{}", "━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━".dimmed());
+    /// Judge --max-rtt by this statistic of each result's response times instead of the average;
+    /// a percentile is also reported as p50_ms, p95_ms or p99_ms
+    #[arg(long, value_enum, default_value_t = StatusMetric::Avg, value_name = "METRIC")]
+    status_metric: StatusMetric,
 
-    for result in results {
-        let status_icon = match result.status.as_str() {
-            "up" => "✅",
-            "down" => "❌",
-            "partial" => "⚠️",
-            _ => "❓",
-        };
+    /// Downgrade results whose jitter exceeds this many milliseconds to partial (needs 3+ successes)
+    #[arg(long, value_name = "MS")]
+    jitter_alert: Option<f64>,
 
-        let host_port = if let Some(port) = result.port {
-            format!("{}:{}", result.host.blue(), port.to_string().yellow())
-        } else {
-            format!("{} (ICMP)", result.host.blue())
-        };
+    /// Fail the run (exit 8) when a result doesn't satisfy this expression, e.g.
+    /// 'p95 < 100 && success_rate >= 0.99'; repeat for more
+    #[arg(long = "assert", value_name = "EXPR", value_parser = assertion::parse)]
+    assertions: Vec<assertion::Assertion>,
 
-        if let Some(avg_time) = result.avg_response_time_ms {
-            println!(
-                "{} {} → {}/{} successful (Avg: {:.2} ms) [{}]",
-                     status_icon,
-                     host_port,
-                     result.successful,
-                     result.attempts,
-                     avg_time,
-                     result.test_type.cyan()
-            );
-        } else {
-            println!(
-                "{} {} → {}/{} successful [{}]{}",
-                status_icon,
-                host_port,
-                result.successful,
-                result.attempts,
-                result.test_type.cyan(),
-                     if let Some(error) = &result.error {
-                         format!(" ({})", error.red())
-                     } else {
-                         String::new()
-                     }
-            );
-        }
-    }
-}
+    /// Fail the run (exit 9) only when more than this percentage of the results are down, so a
+    /// few flapping hosts in a large fleet don't; also replaces --silent's exit 6
+    #[arg(long, value_name = "PERCENT", value_parser = parse_fail_percent)]
+    fail_threshold_percent: Option<f64>,
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args = Args::parse();
-    let ports = parse_ports(&args.ports);
+    /// Mark probe packets with this DSCP value (0-63) for QoS testing
+    #[arg(long, value_parser = clap::value_parser!(u8).range(0..=63))]
+    dscp: Option<u8>,
 
-    if args.hosts.is_empty() {
-        eprintln!("{} You must provide at least one host!", "❌".red());
-        return Ok(());
-    }
+    /// Set SO_MARK on TCP and ICMP probe sockets so they follow `ip rule fwmark` policy routing (Linux only)
+    #[arg(long, value_name = "N", value_parser = parse_fwmark)]
+    fwmark: Option<u32>,
 
-    if !args.ping && ports.is_empty() {
-        eprintln!("{} You must provide at least one port or enable --ping!", "❌".red());
-        return Ok(());
-    }
+    /// Resolve `.local` hosts over multicast DNS (requires the `mdns` cargo feature)
+    #[arg(long)]
+    mdns: bool,
 
-    if !args.json && !args.quiet {
-        println!(
-            "\n{} Hosts: [{}]{}{}",
-            "🔍 Scanning".bold(),
-                 args.hosts.join(", ").green(),
-                 if !ports.is_empty() {
-                     format!(", Ports: [{}]", ports.iter().map(|p| p.to_string()).collect::<Vec<_>>().join(", ").yellow())
-                 } else {
-                     String::new()
-                 },
-                 if args.ping { format!("{}", ", ICMP Ping: enabled".magenta()) } else { String::new() }
-        );
-        println!("{}", "━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━".dimmed());
-    }
+    /// Use UTC instead of local time for summary timestamps
+    #[arg(long)]
+    utc: bool,
 
-    loop {
-        let mut all_results = Vec::new();
+    /// Read the service banner (SSH, SMTP, FTP greeting...) after connecting
+    #[arg(long)]
+    banner: bool,
 
-        // Run TCP checks
-        if !ports.is_empty() {
-            let mut tcp_tasks = vec![];
-            for host in &args.hosts {
-                for &port in &ports {
-                    let host_clone = host.clone();
-                    let task = tcp_check(host_clone, port, args.count, args.timeout);
-                    tcp_tasks.push(task);
-                }
-            }
+    /// Maximum number of banner bytes to read
+    #[arg(long, default_value_t = 256)]
+    banner_bytes: usize,
 
-            let tcp_results = future::join_all(tcp_tasks).await;
-            all_results.extend(tcp_results);
-        }
+    /// How long to wait for a banner in milliseconds (default: 1000)
+    #[arg(long, default_value_t = 1000)]
+    banner_timeout: u64,
 
-        // Run ICMP ping checks
-        if args.ping {
-            let mut ping_tasks = vec![];
-            for host in &args.hosts {
-                let host_clone = host.clone();
-                let task = icmp_ping(host_clone, args.count, args.ping_timeout);
-                ping_tasks.push(task);
-            }
+    /// Read up to this many bytes of the --http response body (or of the --banner, in place of
+    /// --banner-bytes) and record their SHA-256 as content_sha256; continuous mode reports a
+    /// changed hash like a status change
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..), conflicts_with = "throughput")]
+    max_response_bytes: Option<u64>,
 
-            let ping_results = future::join_all(ping_tasks).await;
-            all_results.extend(ping_results);
-        }
+    /// Send an HTTP GET after connecting and record connect / time-to-first-byte timings
+    #[arg(long, conflicts_with = "banner")]
+    http: bool,
 
-        // Output results
-        if args.json {
-            let scan_result = ScanResult {
-                scan_timestamp: SystemTime::now()
-                .duration_since(UNIX_EPOCH)?
-                .as_secs()
-                .to_string(),
-                results: all_results,
-            };
-            println!("{}", serde_json::to_string_pretty(&scan_result)?);
-        } else {
-            print_human_readable(&all_results);
-        }
+    /// Speak HTTPS in --http mode and record the TLS handshake time
+    #[arg(long, requires = "http")]
+    tls: bool,
 
-        if args.once {
-            break;
-        }
+    /// Request /generate_204 in --http mode and report anything but a 204 (a redirect, a login
+    /// page) as captive_portal_detected, for connectivity-check endpoints such as
+    /// connectivitycheck.gstatic.com
+    #[arg(long, requires = "http")]
+    captive_check: bool,
 
-        if !args.json && !args.quiet {
-            println!("\n⏱️  Waiting 5 seconds before next scan...\n");
-        }
-        tokio::time::sleep(Duration::from_secs(5)).await;
-    }
+    /// Use TCP Fast Open for --http probes and record whether the server accepted it (Linux)
+    #[arg(long = "tfo", alias = "tcp-fastopen", requires = "http", conflicts_with = "proxy")]
+    tfo: bool,
 
-    Ok(())
+    /// Route TCP probes through a SOCKS5 or HTTP CONNECT proxy, e.g. socks5://127.0.0.1:1080 or http://proxy:3128 (ICMP is skipped)
+    #[arg(long, value_name = "URL", value_parser = proxy::parse_proxy)]
+    proxy: Option<proxy::Proxy>,
+
+    /// Half-open SYN scan over raw sockets instead of full connects (Linux, root or CAP_NET_RAW)
+    #[arg(long, conflicts_with_all = ["http", "banner", "proxy"])]
+    syn: bool,
+
+    /// Connect once and time -c request/response round trips over the open connection
+    #[arg(long, requires = "probe_send", conflicts_with_all = ["http", "banner", "syn"])]
+    keepalive: bool,
+
+    /// Like --keepalive against an RFC 862 echo service (e.g. -p echo): each round trip sends a
+    /// timestamped line and waits for it to come back unchanged
+    #[arg(long, conflicts_with_all = ["keepalive", "http", "banner", "syn", "throughput", "no_tcp"])]
+    echo: bool,
+
+    /// Between scans, keep each --keepalive or --echo connection open for the next one,
+    /// reconnecting only when the peer has dropped it
+    #[arg(long, conflicts_with = "once")]
+    pool: bool,
+
+    /// Keep each successful TCP connection open this many milliseconds before closing it, like a
+    /// real client would; the hold isn't part of the measured latency. With --http the request
+    /// asks for `Connection: keep-alive`, so the server leaves its end open too
+    #[arg(long, alias = "connect-then-close-delay", value_name = "MS",
+          conflicts_with_all = ["syn", "keepalive", "echo", "no_tcp", "throughput", "max_response_bytes"])]
+    hold: Option<u64>,
+
+    /// After the first successful connect, time a bulk transfer to an echo/discard service (or,
+    /// with --http, a download) and report it as throughput_mbps
+    #[arg(long, conflicts_with_all = ["syn", "keepalive", "no_tcp"])]
+    throughput: bool,
+
+    /// Bytes to send (or, with --http, download) for --throughput (default: 1048576)
+    #[arg(long, value_name = "BYTES", default_value_t = 1 << 20, requires = "throughput",
+          value_parser = clap::value_parser!(u64).range(1..=1 << 30))]
+    payload_size: u64,
+
+    /// Bytes sent for each --keepalive round trip; \r, \n, \t, \\ and \xHH escapes are decoded
+    #[arg(long, value_name = "DATA", requires = "keepalive")]
+    probe_send: Option<String>,
+
+    /// Reply each --keepalive round trip waits for (same escapes); without it any reply counts
+    #[arg(long, value_name = "DATA", requires = "keepalive")]
+    probe_expect: Option<String>,
+
+    /// After each TCP connect, send this file's bytes exactly as they are and wait for a reply;
+    /// a probe whose exchange fails is partial
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["http", "syn", "keepalive", "echo", "banner", "throughput", "no_tcp"])]
+    probe_payload_file: Option<String>,
+
+    /// Bytes, in hex, that the reply to --probe-payload-file has to contain (without a payload,
+    /// the service's greeting); without it any reply counts
+    #[arg(long, value_name = "HEX", conflicts_with_all = ["http", "syn", "keepalive", "echo", "banner", "throughput", "no_tcp"])]
+    expect_bytes: Option<String>,
+
+    /// Probe every address a name resolves to (up to 16), not just the first
+    #[arg(long)]
+    all_addresses: bool,
+
+    /// Probe every address a name resolves to at once and report only the fastest; -v lists
+    /// how each one did
+    #[arg(long, conflicts_with_all = ["all_addresses", "proxy"])]
+    fastest_address: bool,
+
+    /// Annotate results with country and ASN from a MaxMind .mmdb database; repeat to combine
+    /// e.g. GeoLite2-Country and GeoLite2-ASN (requires the `geoip` cargo feature)
+    #[arg(long, value_name = "MMDB")]
+    geoip: Vec<String>,
+
+    /// Look up each IP target's PTR record and show that name in place of the address in human
+    /// and table output; JSON keeps the address as `host` and adds `ptr_name`
+    #[arg(long, conflicts_with = "no_dns")]
+    probe_label_from_ptr: bool,
+
+    /// Label the scan with KEY=VALUE, recorded under `tags` in JSON and after the summary header;
+    /// repeat for more (a repeated key keeps its last value)
+    #[arg(long = "tag", value_name = "KEY=VALUE", value_parser = parse_tag)]
+    tags: Vec<(String, String)>,
+
+    /// Bind each TCP probe to a random source port from the dynamic range and report it
+    #[arg(long)]
+    random_source_port: bool,
+
+    /// Show a histogram of all latency samples (cumulative across scans in continuous mode)
+    #[arg(long)]
+    histogram: bool,
+
+    /// Histogram buckets: a bucket count (e.g. 10) or comma-separated edges in ms (e.g. 5,10,20,50)
+    #[arg(long, value_name = "SPEC", value_parser = histogram::parse_bucket_spec, requires = "histogram")]
+    hist_buckets: Option<histogram::BucketSpec>,
+
+    /// Compare each scan against a previously saved --json scan and print only what changed
+    #[arg(long, value_name = "PATH")]
+    baseline: Option<String>,
+
+    /// Save the scan as JSON to PATH, ready for a later --baseline; may be the --baseline file
+    #[arg(long, value_name = "PATH")]
+    baseline_save: Option<String>,
+
+    /// Print a previously saved --json scan through the output options instead of scanning
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["hosts", "baseline", "baseline_save", "summary_json_to",
+          "output_dir", "dashboard", "serve", "flaky_detector", "until_change", "wait_up"])]
+    replay: Option<String>,
+
+    /// With --replay, print the scan in the Prometheus text format that --serve has under /metrics
+    #[arg(long, requires = "replay", conflicts_with_all = ["json", "yaml", "json_by_host", "summary_only"])]
+    prometheus: bool,
+
+    /// Serve /metrics (or, with --replay, print the scan) in the OpenMetrics format, with
+    /// running counters and the latest response time as an exemplar
+    #[arg(long, conflicts_with = "prometheus")]
+    openmetrics: bool,
+
+    /// Minimum latency change in milliseconds reported by --baseline (default: 50)
+    #[arg(long, default_value_t = 50.0, value_name = "MS")]
+    latency_tolerance: f64,
+
+    /// Report targets whose average latency rose more than this percentage over the baseline as
+    /// regressions, and exit 7
+    #[arg(long, value_name = "PERCENT", requires = "baseline")]
+    baseline_tolerance: Option<f64>,
+
+    /// Report each host once, with the first port that comes up; remaining probes are cancelled
+    #[arg(long)]
+    first_success: bool,
+
+    /// Order of TCP probes, and of their results: every port of one host before the next host
+    /// (hosts), or one port across every host before the next port (ports)
+    #[arg(long, value_enum, value_name = "ORDER", default_value_t = ProbeOrder::Hosts)]
+    probe_order: ProbeOrder,
+
+    /// Probe at most this many hosts at once (default: all of them)
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    concurrency: Option<u32>,
+
+    /// Probe at most this many ports of each host at once (default: all of them), so one host's
+    /// large port range can't crowd out the others
+    #[arg(long, alias = "port-probe-concurrency", value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    port_concurrency: Option<u32>,
+
+    /// Run at most this many TCP probes at once across all hosts, handing each free slot to the
+    /// next host in turn so a host with many ports can't hold up the rest
+    #[arg(
+        long,
+        value_name = "N",
+        value_parser = clap::value_parser!(u32).range(1..),
+        conflicts_with_all = ["concurrency", "port_concurrency", "first_success"]
+    )]
+    max_in_flight: Option<u32>,
+
+    /// Probe TCP ports in batches of N (host, port) pairs, shuffled across all hosts, with
+    /// --batch-delay between one batch finishing and the next starting
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..), conflicts_with = "first_success")]
+    batch_size: Option<u32>,
+
+    /// Pause between --batch-size batches, in milliseconds
+    #[arg(long, default_value_t = 1000, value_name = "MS", requires = "batch_size")]
+    batch_delay: u64,
+
+    /// Run the async runtime on this many worker threads (default: one per CPU core)
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    worker_threads: Option<u32>,
+
+    /// Allow at most this many threads for blocking work, such as system DNS lookups, which
+    /// bounds how many run at once (default: 512)
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    max_blocking_threads: Option<u32>,
+
+    /// Abort before probing anything if any host fails to resolve
+    #[arg(long, conflicts_with = "proxy")]
+    strict_dns: bool,
+
+    /// Never resolve names: every host must be an IP address or CIDR range
+    #[arg(long, conflicts_with_all = ["strict_dns", "mdns"])]
+    no_dns: bool,
+
+    /// Print the expanded list of probes and exit without sending anything
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Check that ring can ping, resolve names, open enough files and reach the internet, print
+    /// what's wrong and how to fix it, and exit
+    #[arg(long, conflicts_with_all = ["hosts", "targets_url", "replay", "dry_run"])]
+    doctor: bool,
+
+    /// Print the version, commit, compiler, target and enabled cargo features as JSON and exit
+    #[arg(long, conflicts_with_all = ["hosts", "targets_url", "replay", "dry_run", "doctor"])]
+    version_json: bool,
+
+    /// Lab stress test: ping each target in steps up to --rate echo requests per second and
+    /// report how loss and latency degrade with the load, then exit. Only private, loopback,
+    /// link-local and documentation addresses, unless --i-know-what-im-doing is given too.
+    #[arg(long, requires = "rate", conflicts_with_all = ["replay", "doctor", "dry_run", "proxy"])]
+    icmp_flood: bool,
+
+    /// The highest rate --icmp-flood reaches, in echo requests per second
+    #[arg(long, value_name = "PER_SECOND", requires = "icmp_flood",
+          value_parser = clap::value_parser!(u32).range(1..=i64::from(flood::MAX_RATE)))]
+    rate: Option<u32>,
+
+    /// Allow CIDR ranges with more than 65536 addresses (IPv4 wider than /16, IPv6 wider than /112)
+    #[arg(long)]
+    force_large_range: bool,
+
+    /// Refuse to run when ranges expand to more than N hosts in total
+    /// [default: 65536, or 16777216 with --force-large-range]
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    max_targets: Option<u64>,
+
+    /// Allow CIDR ranges, and --icmp-flood targets, that reach outside private, loopback,
+    /// link-local and documentation space
+    #[arg(long = "i-know-what-im-doing")]
+    allow_public_ranges: bool,
+
+    /// Save completed probes here as the scan runs; re-running with the same file skips them
+    #[arg(long, value_name = "PATH")]
+    resume_file: Option<String>,
+
+    /// Append every scan to this file as one NDJSON record
+    #[arg(long, value_name = "PATH")]
+    log_file: Option<String>,
+
+    /// Insert every result into this SQLite database, creating its tables on first use
+    /// (requires the `sqlite` cargo feature)
+    #[arg(long, value_name = "PATH")]
+    sqlite: Option<String>,
+
+    /// Also write each scan to this file in --output-format, replacing the previous scan, while
+    /// stdout keeps its own format
+    #[arg(long, value_name = "PATH")]
+    output: Option<String>,
+
+    /// After every scan, write just its totals (counts by status and test type, duration) to
+    /// this file as JSON, replacing the previous scan's
+    #[arg(long, value_name = "PATH")]
+    summary_json_to: Option<String>,
+
+    /// Append each scan's results to one NDJSON file per host, DIR/<host>.ndjson, creating the
+    /// directory and files as needed
+    #[arg(long, value_name = "DIR")]
+    output_dir: Option<String>,
+
+    /// Format of the --output file
+    #[arg(long, value_enum, value_name = "FORMAT", default_value_t = OutputFormat::Json, requires = "output")]
+    output_format: OutputFormat,
+
+    /// Rotate --log-file once it reaches this many megabytes
+    #[arg(long, value_name = "MB", requires = "log_file", value_parser = clap::value_parser!(u64).range(1..))]
+    log_rotate_size: Option<u64>,
+
+    /// POST a JSON payload to this URL whenever a target changes status between scans
+    #[arg(long, value_name = "URL")]
+    webhook: Option<String>,
+
+    /// Run this shell command whenever a target goes down (with --once, for every target that
+    /// is down). Placeholders as in --format, with {host} and {error} shell-quoted, e.g.
+    /// "notify-send 'host down: {host}:{port}'"; they're also in $RING_HOST and $RING_ERROR
+    #[arg(long, value_name = "COMMAND", value_parser = template::parse_command)]
+    alert_command: Option<template::Template>,
+
+    /// While monitoring, serve the latest scan over HTTP: GET /status for JSON, /metrics for Prometheus
+    #[arg(long, value_name = "ADDR:PORT", conflicts_with_all = ["once", "wait_up"])]
+    serve: Option<SocketAddr>,
+
+    /// JSON/TOML manifest of host → ports expected to be open; report any drift from it
+    #[arg(long, value_name = "PATH")]
+    expect: Option<String>,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+/// Failure categories for --retry-on
+#[derive(clap::ValueEnum, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum RetryOn {
+    Dns,
+    Timeout,
+    Refused,
+    Unreachable,
+    /// Anything else: TLS and HTTP errors, ICMP send errors, missing socket paths
+    Other,
+}
+
+impl RetryOn {
+    fn of(kind: Option<FailureKind>) -> Self {
+        match kind {
+            Some(FailureKind::Refused) => RetryOn::Refused,
+            Some(FailureKind::Filtered) => RetryOn::Timeout,
+            Some(FailureKind::Unreachable) => RetryOn::Unreachable,
+            None => RetryOn::Other,
+        }
+    }
+}
+
+/// What --output writes, independently of stdout
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum OutputFormat {
+    Json,
+    JsonByHost,
+    Yaml,
+}
+
+/// --status-metric: what --max-rtt is compared with
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum StatusMetric {
+    Avg,
+    P50,
+    P95,
+    P99,
+}
+
+impl StatusMetric {
+    /// The latency a result is judged by, recording a percentile in the result's field for it
+    fn measure(self, result: &mut HostResult) -> Option<f64> {
+        let (p, field) = match self {
+            StatusMetric::Avg => return result.avg_response_time_ms,
+            StatusMetric::P50 => (50.0, &mut result.p50_ms),
+            StatusMetric::P95 => (95.0, &mut result.p95_ms),
+            StatusMetric::P99 => (99.0, &mut result.p99_ms),
+        };
+        *field = percentile(&result.response_times, p);
+        *field
+    }
+}
+
+/// --unit. Latencies are measured and stored in milliseconds; this only changes how they're shown.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum LatencyUnit {
+    Ms,
+    #[value(alias = "µs")]
+    Us,
+    S,
+}
+
+/// Fields holding a latency in milliseconds, rescaled in --unit output
+const LATENCY_FIELDS: &[&str] = &[
+    "response_times",
+    "avg_response_time_ms",
+    "min_response_time_ms",
+    "max_response_time_ms",
+    "jitter_ms",
+    "p50_ms",
+    "p95_ms",
+    "p99_ms",
+    "ewma_response_time_ms",
+    "rtt_ms",
+    "dns_resolution_ms",
+    "connect_ms",
+    "tls_ms",
+    "ttfb_ms",
+    "clock_offset_ms",
+];
+
+impl LatencyUnit {
+    fn label(self) -> &'static str {
+        match self {
+            LatencyUnit::Ms => "ms",
+            LatencyUnit::Us => "µs",
+            LatencyUnit::S => "s",
+        }
+    }
+
+    fn scale(self, ms: f64) -> f64 {
+        match self {
+            LatencyUnit::Ms => ms,
+            LatencyUnit::Us => ms * 1000.0,
+            LatencyUnit::S => ms / 1000.0,
+        }
+    }
+
+    /// `ms` in this unit with about the resolution `precision` decimals give milliseconds:
+    /// fewer decimals for microseconds, more for seconds
+    fn format(self, ms: f64, precision: usize) -> String {
+        let precision = match self {
+            LatencyUnit::Ms => precision,
+            LatencyUnit::Us => precision.saturating_sub(3),
+            LatencyUnit::S => precision + 2,
+        };
+        format!("{:.*}", precision, self.scale(ms))
+    }
+
+    /// Rescale every latency field in serialized output. Whole milliseconds stay integers in
+    /// microseconds. The `config` object is left alone: its timeouts are settings, not measurements.
+    fn rescale(self, value: &mut serde_json::Value) {
+        use serde_json::Value;
+        let scale = |v: &mut Value| {
+            let Some(ms) = v.as_f64() else {
+                return;
+            };
+            *v = match (self, v.as_u64()) {
+                (LatencyUnit::Us, Some(whole)) => Value::from(whole * 1000),
+                _ => serde_json::Number::from_f64(self.scale(ms)).map_or(Value::Null, Value::Number),
+            };
+        };
+        match value {
+            Value::Object(fields) => {
+                for (name, field) in fields.iter_mut() {
+                    if name == "config" {
+                        continue;
+                    }
+                    if LATENCY_FIELDS.contains(&name.as_str()) {
+                        match field {
+                            Value::Array(items) => items.iter_mut().for_each(scale),
+                            other => scale(other),
+                        }
+                    } else {
+                        self.rescale(field);
+                    }
+                }
+            }
+            Value::Array(items) => items.iter_mut().for_each(|item| self.rescale(item)),
+            _ => {}
+        }
+    }
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum QuietErrors {
+    Detail,
+    Lines,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum ProbeOrder {
+    Hosts,
+    Ports,
+}
+
+/// Every (target, port) TCP probe in --probe-order. Port-major order takes ports in the order
+/// they first appear, so per-host port lists interleave sensibly.
+fn tcp_probe_order(targets: &[Target], order: ProbeOrder) -> Vec<(&Target, u16)> {
+    match order {
+        ProbeOrder::Hosts => targets.iter().flat_map(|t| t.ports.iter().map(move |&port| (t, port))).collect(),
+        ProbeOrder::Ports => {
+            let mut ports: Vec<u16> = Vec::new();
+            for &port in targets.iter().flat_map(|t| &t.ports) {
+                if !ports.contains(&port) {
+                    ports.push(port);
+                }
+            }
+            ports
+                .into_iter()
+                .flat_map(|port| targets.iter().filter(move |t| t.ports.contains(&port)).map(move |t| (t, port)))
+                .collect()
+        }
+    }
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl From<LogLevel> for tracing::Level {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Error => tracing::Level::ERROR,
+            LogLevel::Warn => tracing::Level::WARN,
+            LogLevel::Info => tracing::Level::INFO,
+            LogLevel::Debug => tracing::Level::DEBUG,
+            LogLevel::Trace => tracing::Level::TRACE,
+        }
+    }
+}
+
+/// Send --log-level events to stderr. Only ring's own events are shown; the HTTP and TLS
+/// libraries underneath log at a volume that would drown them out.
+fn init_tracing(level: LogLevel) {
+    use tracing_subscriber::prelude::*;
+
+    let filter = tracing_subscriber::filter::Targets::new().with_target("ring", tracing::Level::from(level));
+    let layer = tracing_subscriber::fmt::layer()
+        .with_writer(std::io::stderr)
+        .with_ansi(std::io::stderr().is_terminal());
+    tracing_subscriber::registry().with(layer).with(filter).init();
+}
+
+impl Args {
+    /// Serialize for --json output: pretty-printed, or on one line with --json-compact
+    fn to_json<T: Serialize>(&self, value: &T) -> serde_json::Result<String> {
+        if self.json_compact {
+            serde_json::to_string(value)
+        } else {
+            serde_json::to_string_pretty(value)
+        }
+    }
+
+    /// A scan for --json / --yaml output, with its latencies in --unit. Other units are labeled
+    /// with a `unit` field so the numbers can't be mistaken for milliseconds.
+    fn scan_output<T: Serialize>(&self, scan: &T) -> serde_json::Result<serde_json::Value> {
+        let mut value = serde_json::to_value(scan)?;
+        if self.unit != LatencyUnit::Ms {
+            self.unit.rescale(&mut value);
+            if let serde_json::Value::Object(fields) = &mut value {
+                let at = fields.keys().position(|k| k == "tool_version").map_or(0, |i| i + 1);
+                fields.shift_insert(at, "unit".to_string(), self.unit.label().into());
+            }
+        }
+        Ok(value)
+    }
+
+    /// --dns-retries, unless --retry-on leaves out `dns`
+    fn effective_dns_retries(&self) -> u32 {
+        match &self.retry_on {
+            Some(retry_on) if !retry_on.contains(&RetryOn::Dns) => 0,
+            _ => self.dns_retries,
+        }
+    }
+
+    /// How many ping, ARP, QUIC and `unix:` probes may run at once: one per host with
+    /// --concurrency, or the --max-in-flight pool
+    fn probe_limit(&self) -> Option<u32> {
+        self.concurrency.or(self.max_in_flight)
+    }
+
+    fn tcp_attempts(&self) -> u32 {
+        self.tcp_count.unwrap_or(self.count)
+    }
+
+    fn ping_attempts(&self) -> u32 {
+        self.ping_count.unwrap_or(self.count)
+    }
+
+    fn connect_timeout_ms(&self) -> u64 {
+        self.connect_timeout.unwrap_or(self.timeout)
+    }
+
+    fn read_timeout_ms(&self) -> u64 {
+        self.read_timeout.unwrap_or(self.timeout)
+    }
+
+    /// Which --rtt-warn / --rtt-crit band an average latency is in, as color_latency colors it:
+    /// 0 without a latency, then 1 (below --rtt-warn) to 3 (--rtt-crit and over)
+    fn latency_band(&self, avg: Option<f64>) -> u8 {
+        match avg {
+            None => 0,
+            Some(ms) if ms >= self.rtt_crit => 3,
+            Some(ms) if ms >= self.rtt_warn => 2,
+            Some(_) => 1,
+        }
+    }
+
+    /// Render an average latency in --unit, colored by the --rtt-warn / --rtt-crit thresholds
+    fn color_latency(&self, ms: f64, precision: usize) -> ColoredString {
+        let text = self.unit.format(ms, precision);
+        if ms >= self.rtt_crit {
+            text.red()
+        } else if ms >= self.rtt_warn {
+            text.yellow()
+        } else {
+            text.green()
+        }
+    }
+}
+
+/// How one address a --fastest-address probe raced did
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct Candidate {
+    addr: ScopedIp,
+    status: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    avg_response_time_ms: Option<f64>,
+}
+
+/// A direct TCP connect whose socket reports a different peer than the address it dialed, as
+/// when a connect() shim such as proxychains sends it somewhere else
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+struct PeerMismatch {
+    dialed: SocketAddr,
+    connected: SocketAddr,
+}
+
+impl PeerMismatch {
+    /// Compare the address `stream` was meant to reach with the peer it says it has. Scope IDs
+    /// and IPv4-mapped IPv6 forms of the same address don't count as a difference.
+    fn check(stream: &TcpStream, dialed: SocketAddr) -> Option<Self> {
+        let connected = stream.peer_addr().ok()?;
+        let same = connected.ip().to_canonical() == dialed.ip().to_canonical() && connected.port() == dialed.port();
+        (!same).then_some(PeerMismatch { dialed, connected })
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+struct HostResult {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    probe_id: Option<u64>, // the probe that produced it, for matching -v and --log-level lines to results
+    host: String,
+    port: Option<u16>,
+    test_type: String, // "tcp", "syn", "icmp", "arp", "uds" or "quic"
+    attempts: u32,
+    successful: u32,
+    success_rate: f64,
+    avg_response_time_ms: Option<f64>,
+    #[serde(default)]
+    min_response_time_ms: Option<f64>,
+    #[serde(default)]
+    max_response_time_ms: Option<f64>,
+    response_times: Vec<u128>,
+    status: String, // "up", "down", "partial", or "filtered" with --timeout-as-filtered
+    error: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    errors: Vec<String>, // every distinct error across attempts, in the order first seen
+    #[serde(default)]
+    jitter_ms: Option<f64>, // mean difference between consecutive samples
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    mean_ci_percent: Option<f64>, // --ramp-ci only: the 95% confidence interval's half-width
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    p50_ms: Option<f64>, // --status-metric p50 only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    p95_ms: Option<f64>, // --status-metric p95 only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    p99_ms: Option<f64>, // --status-metric p99 only
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    failed_assertions: Vec<String>, // --assert expressions this result didn't satisfy
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    attempt_log: Vec<AttemptRecord>, // every attempt in order, failures included
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ewma_response_time_ms: Option<f64>, // --ewma only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    banner: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    dscp: Option<u8>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    source_ports: Vec<u16>, // one per attempt, --random-source-port only
+    #[serde(default, skip_serializing_if = "is_zero")]
+    rescans: u32, // extra passes --rescan-failures ran for this result
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    send_epoch_us: Vec<u64>, // ICMP only, parallel to response_times
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    recv_epoch_us: Vec<u64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    icmp_timestamps: Vec<icmp_timestamp::Timestamps>, // --icmp-timestamp only, parallel to response_times
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    clock_offset_ms: Option<f64>, // how far the host's clock is ahead of ours, averaged over replies
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    path_mtu: Option<u32>, // ICMP with --mtu-discover only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    hops_estimate: Option<u8>, // ICMP over IPv4 only: routers in between, guessed from the reply TTL
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    quic_version: Option<u32>, // QUIC only, once a handshake has completed
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    mac_addresses: Vec<String>, // ARP only; more than one means duplicate addresses
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    proxy: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    resolved_addr: Option<ScopedIp>, // --all-addresses only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    peer_mismatch: Option<PeerMismatch>, // direct TCP connects that reached another peer
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    addresses: Vec<String>, // every address the name resolved to, e.g. "AAAA 2001:db8::1"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ptr_name: Option<String>, // --probe-label-from-ptr only, for IP targets with a PTR record
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    candidates: Vec<Candidate>, // --fastest-address only, fastest first
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    dns_resolution_ms: Option<f64>, // None for literal IPs and proxied probes
+    // Timing breakdown averaged over successful attempts, --http only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    connect_ms: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pool_connection: Option<String>, // --pool only: "new", "reused", "reconnected" or "dropped"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tls_ms: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ttfb_ms: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    http_status: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tfo_used: Option<bool>, // --tfo only: whether any attempt's SYN data was accepted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    throughput_mbps: Option<f64>, // --throughput only, in megabytes per second
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    content_sha256: Option<String>, // --max-response-bytes only: of the banner or HTTP body
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    failure_kind: Option<FailureKind>, // TCP/SYN results that never connected
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    country: Option<String>, // ISO code, --geoip only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    asn: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    as_org: Option<String>,
+}
+
+fn is_zero(n: &u32) -> bool {
+    *n == 0
+}
+
+/// One attempt of a probe, so `attempt_log` shows the order of successes and failures that the
+/// totals hide
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct AttemptRecord {
+    seq: u32, // 1-based; --warmup attempts aren't logged
+    success: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    rtt_ms: Option<u128>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    retries: u32, // --connect-retries made before this outcome
+}
+
+impl AttemptRecord {
+    fn success(seq: u32, rtt_ms: u128) -> Self {
+        AttemptRecord { seq, success: true, rtt_ms: Some(rtt_ms), error: None, retries: 0 }
+    }
+
+    fn failure(seq: u32, error: &str) -> Self {
+        AttemptRecord { seq, success: false, rtt_ms: None, error: Some(error.to_string()), retries: 0 }
+    }
+}
+
+/// What a failed TCP probe says about the path to the port
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum FailureKind {
+    /// The host answered with a RST: nothing listening
+    Refused,
+    /// No answer at all, typically a firewall dropping packets
+    Filtered,
+    /// A router reported the host or network unreachable
+    Unreachable,
+}
+
+impl FailureKind {
+    fn from_io(e: &std::io::Error) -> Option<Self> {
+        use std::io::ErrorKind::*;
+        match e.kind() {
+            ConnectionRefused => Some(FailureKind::Refused),
+            TimedOut => Some(FailureKind::Filtered),
+            HostUnreachable | NetworkUnreachable => Some(FailureKind::Unreachable),
+            _ => None,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            FailureKind::Refused => "refused",
+            FailureKind::Filtered => "filtered",
+            FailureKind::Unreachable => "unreachable",
+        }
+    }
+
+    fn icon(self) -> &'static str {
+        match self {
+            FailureKind::Refused => icons::get("down"),
+            FailureKind::Filtered => icons::get("filtered"),
+            FailureKind::Unreachable => icons::get("unreachable"),
+        }
+    }
+}
+
+/// A connect that didn't produce a stream: the error as results report it, and what kind of
+/// failure it was when that can be told
+#[derive(Debug)]
+struct ConnectError {
+    message: String,
+    kind: Option<FailureKind>,
+}
+
+impl From<String> for ConnectError {
+    fn from(message: String) -> Self {
+        ConnectError { message, kind: None }
+    }
+}
+
+impl HostResult {
+    /// A result for a probe that could not be attempted at all (e.g. DNS failure)
+    fn failure(host: &str, port: Option<u16>, test_type: &str, attempts: u32, error: String) -> Self {
+        HostResult {
+            host: host.to_string(),
+            port,
+            test_type: test_type.to_string(),
+            attempts,
+            status: "down".to_string(),
+            error: Some(error),
+            ..Default::default()
+        }
+    }
+
+    /// Fill in `errors` from the attempt log. `error` alone only keeps the last failure, and only
+    /// when nothing succeeded, which hides a probe that timed out once and was refused the next time.
+    fn collect_errors(&mut self) {
+        let logged = self.attempt_log.iter().filter_map(|a| a.error.as_ref());
+        let mut errors: Vec<String> = Vec::new();
+        for error in logged.chain(&self.error) {
+            if !errors.contains(error) {
+                errors.push(error.clone());
+            }
+        }
+        self.errors = errors;
+    }
+
+    /// The name human output gives the target: its PTR name with --probe-label-from-ptr,
+    /// otherwise `host` as given
+    fn label_host(&self) -> &str {
+        self.ptr_name.as_deref().unwrap_or(&self.host)
+    }
+
+    /// The address human output adds after the label, unless the label already is it: the one
+    /// probed, or the target's own address when a PTR name stands in for it
+    fn label_addr(&self) -> Option<String> {
+        match self.resolved_addr {
+            Some(addr) if addr.to_string() != self.host => Some(addr.to_string()),
+            _ => self.ptr_name.is_some().then(|| self.host.clone()),
+        }
+    }
+}
+
+/// ICMP sequence numbers for each address pinged, kept across scans so a capture of a long run
+/// sees one increasing sequence per host rather than 1..=count over and over
+#[derive(Debug)]
+struct IcmpSequences {
+    start: u16,
+    next: Mutex<HashMap<ScopedIp, u16>>,
+}
+
+impl IcmpSequences {
+    fn new(start: u16) -> Self {
+        IcmpSequences { start, next: Mutex::new(HashMap::new()) }
+    }
+
+    /// The sequence number for the next echo request to `addr`
+    fn next(&self, addr: ScopedIp) -> u16 {
+        let mut next = self.next.lock().unwrap();
+        let seq = next.entry(addr).or_insert(self.start);
+        let current = *seq;
+        *seq = seq.wrapping_add(1);
+        current
+    }
+}
+
+#[derive(Debug, Default)]
+struct IdState {
+    next: u16,
+    in_use: HashSet<u16>,
+    /// The identifier each address last had, which it gets again when it is free
+    by_addr: HashMap<ScopedIp, u16>,
+}
+
+/// ICMP identifiers for the pingers running at any moment. Replies are matched to pingers by
+/// identifier and sequence, so two pingers sharing an identifier could take each other's
+/// replies; handing each its own rules that out. An address keeps its identifier from one scan
+/// to the next while nothing else holds it, so a capture shows one identifier per host.
+#[derive(Debug)]
+struct IcmpIdentifiers {
+    state: Mutex<IdState>,
+}
+
+impl IcmpIdentifiers {
+    fn new(start: u16) -> Self {
+        IcmpIdentifiers { state: Mutex::new(IdState { next: start, ..Default::default() }) }
+    }
+
+    /// An identifier no other running pinger has, held until the lease is dropped
+    fn lease(self: &Arc<Self>, addr: ScopedIp) -> IcmpIdLease {
+        let mut state = self.state.lock().unwrap();
+        let id = match state.by_addr.get(&addr) {
+            Some(&id) if !state.in_use.contains(&id) => id,
+            // With every identifier taken (65536 pingers at once) sharing is unavoidable
+            _ if state.in_use.len() > usize::from(u16::MAX) => state.next,
+            _ => {
+                while state.in_use.contains(&state.next) {
+                    state.next = state.next.wrapping_add(1);
+                }
+                let id = state.next;
+                state.next = id.wrapping_add(1);
+                id
+            }
+        };
+        state.in_use.insert(id);
+        state.by_addr.insert(addr, id);
+        IcmpIdLease { id, owner: Arc::clone(self) }
+    }
+}
+
+struct IcmpIdLease {
+    id: u16,
+    owner: Arc<IcmpIdentifiers>,
+}
+
+impl Drop for IcmpIdLease {
+    fn drop(&mut self) {
+        self.owner.state.lock().unwrap().in_use.remove(&self.id);
+    }
+}
+
+/// --retry-budget: how many more attempts after a failure the current scan may make, shared
+/// by every probe so widespread failure can't multiply the scan's duration
+#[derive(Debug)]
+struct RetryBudget {
+    limit: u32,
+    remaining: AtomicU32,
+    /// Whether a probe wanted a retry after the budget was spent
+    refused: AtomicBool,
+}
+
+impl RetryBudget {
+    fn new(limit: u32) -> Self {
+        RetryBudget { limit, remaining: AtomicU32::new(limit), refused: AtomicBool::new(false) }
+    }
+
+    /// Spend one retry, or return false if there are none left
+    fn take(&self) -> bool {
+        let taken = self.remaining.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1)).is_ok();
+        if !taken {
+            self.refused.store(true, Ordering::Relaxed);
+        }
+        taken
+    }
+
+    /// Refill the budget for the next scan
+    fn reset(&self) {
+        self.remaining.store(self.limit, Ordering::Relaxed);
+        self.refused.store(false, Ordering::Relaxed);
+    }
+}
+
+/// --adaptive-timeout: attempt timeouts that shrink to fit the round trips a probe has seen
+#[derive(Debug, Clone, Copy)]
+struct AdaptiveTimeout {
+    multiplier: f64,
+    floor: Duration,
+}
+
+impl AdaptiveTimeout {
+    /// How long the next attempt may take: `configured` until something succeeds, then the
+    /// multiple of `slowest`, kept between the floor and `configured`
+    fn next(&self, configured: Duration, slowest: Option<Duration>) -> Duration {
+        match slowest {
+            Some(rtt) => rtt.mul_f64(self.multiplier).max(self.floor).min(configured),
+            None => configured,
+        }
+    }
+}
+
+/// Per-probe settings derived from the command line
+#[derive(Debug, Clone)]
+struct ProbeOptions {
+    /// When set, read up to this many bytes from the server after connecting
+    banner_bytes: Option<usize>,
+    banner_timeout_ms: u64,
+    /// Bound on the HTTP exchange after connecting
+    read_timeout_ms: u64,
+    ping_interval_ms: u64,
+    ping_size: usize,
+    ping_df: bool,
+    icmp_loss_threshold: Option<f64>,
+    mtu_discover: bool,
+    icmp_timestamp: bool,
+    dscp: Option<u8>,
+    fwmark: Option<u32>,
+    mdns: bool,
+    http: bool,
+    tls: bool,
+    captive_check: bool,
+    tfo: bool,
+    random_source_port: bool,
+    proxy: Option<proxy::Proxy>,
+    all_addresses: bool,
+    fastest_address: bool,
+    /// --keepalive or --echo: what each round trip exchanges
+    keepalive: Option<Exchange>,
+    pool: Option<Arc<ConnectionPool>>,
+    /// --probe-payload-file and --expect-bytes: what to send after each connect, and what the
+    /// reply has to contain
+    payload: Option<(Vec<u8>, Option<Vec<u8>>)>,
+    resolver: Arc<dns::Resolver>,
+    icmp_sequences: Arc<IcmpSequences>,
+    icmp_ids: Arc<IcmpIdentifiers>,
+    retry_budget: Option<Arc<RetryBudget>>,
+    /// Report the address each result was probed at: with --all-addresses or --fastest-address,
+    /// and for --geoip
+    record_addr: bool,
+    adaptive_timeout: Option<AdaptiveTimeout>,
+    timeout_jitter: Option<f64>,
+    timeout_as_filtered: bool,
+    require_count: Option<u32>,
+    stop_after_successes: Option<u32>,
+    /// --ramp-ci: the interval to sample down to, in percent of the mean, and --ramp-max
+    ramp: Option<(f64, u32)>,
+    retry_on: Option<Vec<RetryOn>>,
+    connect_retries: u32,
+    warmup: u32,
+    syn: bool,
+    /// --hold: how long to keep a successful connection open
+    hold: Option<Duration>,
+    /// --throughput: how many bytes to transfer
+    throughput: Option<usize>,
+    /// --max-response-bytes: hash the banner or HTTP body, reading up to `content_bytes` of the
+    /// latter
+    content_hash: bool,
+    content_bytes: Option<usize>,
+}
+
+impl ProbeOptions {
+    fn from_args(args: &Args) -> Self {
+        ProbeOptions {
+            banner_bytes: args.banner.then_some(args.max_response_bytes.map_or(args.banner_bytes, |n| n as usize)),
+            // An explicit --read-timeout covers banners too; otherwise they keep their own default
+            banner_timeout_ms: args.read_timeout.unwrap_or(args.banner_timeout),
+            read_timeout_ms: args.read_timeout_ms(),
+            ping_interval_ms: args.ping_interval,
+            ping_size: usize::from(args.ping_size),
+            ping_df: args.ping_df,
+            icmp_loss_threshold: args.icmp_loss_threshold,
+            mtu_discover: args.mtu_discover,
+            icmp_timestamp: args.icmp_timestamp,
+            dscp: args.dscp,
+            fwmark: args.fwmark,
+            mdns: args.mdns,
+            http: args.http,
+            tls: args.tls,
+            captive_check: args.captive_check,
+            tfo: args.tfo,
+            random_source_port: args.random_source_port,
+            proxy: args.proxy.clone(),
+            all_addresses: args.all_addresses,
+            fastest_address: args.fastest_address,
+            keepalive: match (args.keepalive, &args.probe_send) {
+                (true, Some(send)) => Some(Exchange::Request(unescape(send), args.probe_expect.as_deref().map(unescape))),
+                _ => args.echo.then_some(Exchange::Echo),
+            },
+            pool: args.pool.then(|| Arc::new(ConnectionPool::default())),
+            // Set by main, which reads the file and reports a bad one
+            payload: None,
+            resolver: Arc::new(dns::Resolver::new(args.effective_dns_retries(), Duration::from_secs(args.dns_cache_ttl))),
+            icmp_sequences: Arc::new(IcmpSequences::new(args.icmp_sequence_start)),
+            icmp_ids: Arc::new(IcmpIdentifiers::new(args.icmp_id.unwrap_or_else(|| rng::with(|rng| rng.gen())))),
+            retry_budget: args.retry_budget.map(|n| Arc::new(RetryBudget::new(n))),
+            record_addr: args.all_addresses || args.fastest_address || !args.geoip.is_empty(),
+            syn: args.syn,
+            hold: args.hold.map(Duration::from_millis),
+            throughput: args.throughput.then_some(args.payload_size as usize),
+            content_hash: args.max_response_bytes.is_some(),
+            content_bytes: args.max_response_bytes.filter(|_| args.http).map(|n| n as usize),
+            timeout_as_filtered: args.timeout_as_filtered,
+            timeout_jitter: args.timeout_jitter,
+            require_count: args.require_count,
+            stop_after_successes: args.stop_after_successes,
+            ramp: args.ramp_ci.map(|ci| (ci, args.ramp_max)),
+            retry_on: args.retry_on.clone(),
+            connect_retries: args.connect_retries,
+            warmup: args.warmup,
+            adaptive_timeout: args.adaptive_timeout.then_some(AdaptiveTimeout {
+                multiplier: args.adaptive_multiplier,
+                floor: Duration::from_millis(args.adaptive_floor),
+            }),
+        }
+    }
+
+    /// Keep the addresses a name resolved to that should be probed: the first, or with
+    /// --all-addresses or --fastest-address every distinct one up to MAX_ADDRESSES_PER_HOST
+    fn pick_addresses(&self, mut addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+        let mut seen = HashSet::new();
+        addrs.retain(|a| seen.insert(a.ip()));
+        addrs.truncate(if self.all_addresses || self.fastest_address { MAX_ADDRESSES_PER_HOST } else { 1 });
+        addrs
+    }
+
+    /// Successful attempts out of `count` that make a probe up: all of them unless
+    /// --require-count or --stop-after-successes say fewer will do
+    fn required_successes(&self, count: u32) -> u32 {
+        [self.require_count, self.stop_after_successes]
+            .into_iter()
+            .flatten()
+            .min()
+            .map_or(count, |n| n.min(count))
+    }
+
+    /// Whether a probe has had the successes --stop-after-successes asks for
+    fn enough_successes(&self, successful: u32) -> bool {
+        self.stop_after_successes.is_some_and(|n| successful >= n)
+    }
+
+    /// Attempts a probe of `count` may make: with --ramp-ci, up to --ramp-max
+    fn max_attempts(&self, count: u32) -> u32 {
+        self.ramp.map_or(count, |(_, max)| max.max(count))
+    }
+
+    /// --ramp-ci: whether the samples so far pin the mean down closely enough. A probe without a
+    /// single success has nothing to pin down, so more attempts wouldn't help.
+    fn precise_enough(&self, response_times: &[u128]) -> bool {
+        self.ramp.is_some_and(|(target, _)| {
+            response_times.is_empty() || mean_ci_percent(response_times).is_some_and(|ci| ci <= target)
+        })
+    }
+
+    /// Status for a probe given how many of its attempts succeeded and how many timed out
+    fn status(&self, successful: u32, timeouts: u32, count: u32) -> &'static str {
+        match successful {
+            0 if self.timeout_as_filtered && timeouts == count => "filtered",
+            0 => "down",
+            n if n >= self.required_successes(count) => "up",
+            _ => "partial",
+        }
+    }
+
+    /// Whether a failed attempt of this kind moves on to the next attempt, per --retry-on, and
+    /// --retry-budget has a retry to spend on it
+    fn retries(&self, kind: Option<FailureKind>) -> bool {
+        self.retry_on.as_ref().is_none_or(|retry_on| retry_on.contains(&RetryOn::of(kind)))
+            && self.spend_retry()
+    }
+
+    /// Make one attempt's connect, trying again up to --connect-retries times while `failure`
+    /// says it failed in a way worth retrying. Returns the outcome of the last try, when that
+    /// try started, and how many retries came before it.
+    async fn with_connect_retries<T, Fut>(
+        &self,
+        attempt: u32,
+        mut try_once: impl FnMut() -> Fut,
+        failure: impl Fn(&T) -> Option<Option<FailureKind>>,
+    ) -> (T, Instant, u32)
+    where
+        Fut: Future<Output = T>,
+    {
+        let mut retries = 0;
+        loop {
+            let start = Instant::now();
+            let outcome = try_once().await;
+            match failure(&outcome) {
+                Some(kind) if retries < self.connect_retries && self.retries(kind) => {
+                    retries += 1;
+                    tracing::debug!(attempt, retries, "connect failed; retrying");
+                }
+                _ => return (outcome, start, retries),
+            }
+        }
+    }
+
+    /// Take a retry from --retry-budget, if there is one
+    fn spend_retry(&self) -> bool {
+        self.retry_budget.as_ref().is_none_or(|budget| budget.take())
+    }
+
+    /// Whether this scan had failures that went unretried because --retry-budget ran out
+    fn retry_budget_refused(&self) -> bool {
+        self.retry_budget.as_ref().is_some_and(|budget| budget.refused.load(Ordering::Relaxed))
+    }
+
+    /// An ICMP probe's status and error: down when nothing came back or --icmp-loss-threshold
+    /// was crossed, otherwise up or partial like any other probe
+    fn icmp_status(&self, successful: u32, attempts: u32, last_error: Option<String>) -> (&'static str, Option<String>) {
+        let loss_percent = (1.0 - successful as f64 / attempts as f64) * 100.0;
+        let too_lossy = self.icmp_loss_threshold.is_some_and(|limit| loss_percent > limit);
+        match successful {
+            0 => ("down", last_error),
+            _ if too_lossy => ("down", Some("packet_loss_exceeded".to_string())),
+            n if n >= self.required_successes(attempts) => ("up", None),
+            _ => ("partial", None),
+        }
+    }
+
+    /// What --http requests
+    fn http_path(&self) -> &'static str {
+        if self.captive_check {
+            http::CAPTIVE_CHECK_PATH
+        } else {
+            "/"
+        }
+    }
+
+    /// Whether `attempt` (counting from 1, warm-ups first) is a --warmup throwaway
+    fn is_warmup(&self, attempt: u32) -> bool {
+        attempt <= self.warmup
+    }
+
+    /// Timeout for the next attempt of a probe whose slowest successful attempt so far took `slowest`
+    fn attempt_timeout(&self, configured_ms: u64, slowest: Option<Duration>) -> Duration {
+        let configured = Duration::from_millis(configured_ms);
+        let timeout = match &self.adaptive_timeout {
+            Some(adaptive) => adaptive.next(configured, slowest),
+            None => configured,
+        };
+        jittered(timeout, self.timeout_jitter)
+    }
+}
+
+/// Version of the `--json` output format. Bump whenever a field is removed, renamed or changes meaning.
+const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Debug)]
+struct ScanResult {
+    #[serde(default)] // 0 for output written before versioning
+    schema_version: u32,
+    scan_timestamp: String, // RFC 3339
+    #[serde(default)]
+    scan_epoch: u64,
+    #[serde(default)] // empty for output written before it was recorded
+    tool_version: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    config: Option<ScanConfig>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    tags: BTreeMap<String, String>, // --tag
+    results: Vec<HostResult>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    results_dropped: Option<u64>, // with --result-limit, how many were printed but not kept
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    drift: Option<manifest::DriftReport>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    histogram: Option<histogram::Histogram>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    anomalies: Vec<anomaly::Anomaly>, // hosts whose ports came back mixed
+}
+
+/// The settings a scan ran with, so saved output says how it was produced. Only what shapes the
+/// probes is recorded, not how results are displayed, and never secrets: the proxy appears
+/// without credentials, and --webhook and --probe-send not at all.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct ScanConfig {
+    hosts: Vec<String>,
+    /// -p as given (empty with --no-tcp)
+    ports: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    port_step: Option<u16>, // only when above 1
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ports_top: Option<u64>,
+    tcp_count: u32,
+    ping_count: u32,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    warmup: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    hold_ms: Option<u64>,
+    connect_timeout_ms: u64,
+    read_timeout_ms: u64,
+    ping_timeout_ms: u64,
+    interval_ms: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    max_interval_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    require_count: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    stop_after_successes: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    retry_on: Option<Vec<RetryOn>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    retry_budget: Option<u32>,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    connect_retries: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    timeout_jitter: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    dscp: Option<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    fwmark: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    proxy: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    seed: Option<u64>,
+    /// Probe-affecting switches that were on, by their long names (e.g. `ping`, `tls`)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    flags: Vec<String>,
+}
+
+impl ScanConfig {
+    fn from_args(args: &Args) -> Self {
+        let flags = [
+            (args.ping, "ping"),
+            (args.arp, "arp"),
+            (args.quic, "quic"),
+            (args.no_tcp, "no-tcp"),
+            (args.syn, "syn"),
+            (args.http, "http"),
+            (args.tls, "tls"),
+            (args.captive_check, "captive-check"),
+            (args.tfo, "tfo"),
+            (args.keepalive, "keepalive"),
+            (args.echo, "echo"),
+            (args.pool, "pool"),
+            (args.throughput, "throughput"),
+            (args.banner, "banner"),
+            (args.probe_payload_file.is_some(), "probe-payload-file"),
+            (args.expect_bytes.is_some(), "expect-bytes"),
+            (args.ping_df, "ping-df"),
+            (args.icmp_timestamp, "icmp-timestamp"),
+            (args.mtu_discover, "mtu-discover"),
+            (args.mdns, "mdns"),
+            (args.all_addresses, "all-addresses"),
+            (args.fastest_address, "fastest-address"),
+            (args.probe_label_from_ptr, "probe-label-from-ptr"),
+            (args.random_source_port, "random-source-port"),
+            (args.adaptive_timeout, "adaptive-timeout"),
+            (args.timeout_as_filtered, "timeout-as-filtered"),
+            (args.first_success, "first-success"),
+            (args.strict_dns, "strict-dns"),
+            (args.no_dns, "no-dns"),
+        ];
+        ScanConfig {
+            hosts: args.hosts.clone(),
+            ports: if args.no_tcp { String::new() } else { args.ports.clone() },
+            port_step: (args.port_step > 1).then_some(args.port_step),
+            ports_top: args.ports_top,
+            tcp_count: args.tcp_attempts(),
+            ping_count: args.ping_attempts(),
+            warmup: args.warmup,
+            hold_ms: args.hold,
+            connect_timeout_ms: args.connect_timeout_ms(),
+            read_timeout_ms: args.read_timeout_ms(),
+            ping_timeout_ms: args.ping_timeout,
+            interval_ms: args.interval,
+            max_interval_ms: args.interval_adaptive.then_some(args.max_interval),
+            require_count: args.require_count,
+            stop_after_successes: args.stop_after_successes,
+            retry_on: args.retry_on.clone(),
+            retry_budget: args.retry_budget,
+            connect_retries: args.connect_retries,
+            timeout_jitter: args.timeout_jitter,
+            dscp: args.dscp,
+            fwmark: args.fwmark,
+            proxy: args.proxy.as_ref().map(|p| p.display_url()),
+            seed: args.seed,
+            flags: flags.iter().filter(|(on, _)| *on).map(|(_, name)| name.to_string()).collect(),
+        }
+    }
+}
+
+/// A host's results in --json-by-host output
+#[derive(Serialize, Default)]
+struct HostGroup<'a> {
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    tcp: BTreeMap<u16, &'a HostResult>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    syn: BTreeMap<u16, &'a HostResult>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    icmp: Option<&'a HostResult>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    arp: Option<&'a HostResult>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    uds: Option<&'a HostResult>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    quic: Option<&'a HostResult>,
+}
+
+/// ScanResult reshaped for --json-by-host: `hosts` maps each host (as `host @ addr` when
+/// --all-addresses split it) to its results, instead of the flat `results` array
+#[derive(Serialize)]
+struct GroupedScanResult<'a> {
+    schema_version: u32,
+    scan_timestamp: &'a str,
+    scan_epoch: u64,
+    tool_version: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    config: Option<&'a ScanConfig>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    tags: &'a BTreeMap<String, String>,
+    hosts: BTreeMap<String, HostGroup<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    drift: Option<&'a manifest::DriftReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    histogram: Option<&'a histogram::Histogram>,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    anomalies: &'a [anomaly::Anomaly],
+}
+
+impl<'a> GroupedScanResult<'a> {
+    fn new(scan: &'a ScanResult) -> Self {
+        let mut hosts: BTreeMap<String, HostGroup> = BTreeMap::new();
+        for result in &scan.results {
+            let key = match result.resolved_addr.filter(|addr| addr.to_string() != result.host) {
+                Some(addr) => format!("{} @ {}", result.host, addr),
+                None => result.host.clone(),
+            };
+            let group = hosts.entry(key).or_default();
+            match (result.test_type.as_str(), result.port) {
+                ("icmp", _) => group.icmp = Some(result),
+                ("arp", _) => group.arp = Some(result),
+                ("uds", _) => group.uds = Some(result),
+                ("quic", _) => group.quic = Some(result),
+                ("syn", Some(port)) => {
+                    group.syn.insert(port, result);
+                }
+                (_, Some(port)) => {
+                    group.tcp.insert(port, result);
+                }
+                (_, None) => {}
+            }
+        }
+
+        GroupedScanResult {
+            schema_version: scan.schema_version,
+            scan_timestamp: &scan.scan_timestamp,
+            scan_epoch: scan.scan_epoch,
+            tool_version: &scan.tool_version,
+            config: scan.config.as_ref(),
+            tags: &scan.tags,
+            hosts,
+            drift: scan.drift.as_ref(),
+            histogram: scan.histogram.as_ref(),
+            anomalies: &scan.anomalies,
+        }
+    }
+}
+
+/// Exit status when the scan found ports that don't match the --expect manifest
+const EXIT_DRIFT: u8 = 2;
+
+/// Exit status when --wait-up gave up before every target came up
+const EXIT_WAIT_TIMEOUT: u8 = 3;
+
+/// Exit status when a result exceeded --max-rtt or --jitter-alert
+const EXIT_THRESHOLD: u8 = 4;
+
+/// Exit status when --max-runtime ran out
+const EXIT_MAX_RUNTIME: u8 = 5;
+
+/// Exit status under --silent when a result wasn't up and nothing more specific applies
+const EXIT_NOT_UP: u8 = 6;
+
+/// Exit status when a target's latency regressed past --baseline-tolerance
+const EXIT_REGRESSION: u8 = 7;
+
+/// Exit status when a result didn't satisfy an --assert expression
+const EXIT_ASSERTION: u8 = 8;
+
+/// Exit status when more than --fail-threshold-percent of the results were down
+const EXIT_FLEET_DOWN: u8 = 9;
+
+/// How long probes still in flight when --max-runtime runs out get to finish
+const MAX_RUNTIME_GRACE: Duration = Duration::from_secs(2);
+
+/// Upper bound on how many addresses of one name --all-addresses probes
+const MAX_ADDRESSES_PER_HOST: usize = 16;
+
+/// Jitter needs a few samples before it means anything
+const MIN_JITTER_SAMPLES: usize = 3;
+
+/// Two-sided 95% critical values of Student's t for 1 to 30 degrees of freedom; past that the
+/// normal distribution's 1.96 is close enough
+const T_95: [f64; 30] = [
+    12.706, 4.303, 3.182, 2.776, 2.571, 2.447, 2.365, 2.306, 2.262, 2.228, 2.201, 2.179, 2.160, 2.145, 2.131,
+    2.120, 2.110, 2.101, 2.093, 2.086, 2.080, 2.074, 2.069, 2.064, 2.060, 2.056, 2.052, 2.048, 2.045, 2.042,
+];
+
+/// Decode the `\r`, `\n`, `\t`, `\\` and `\xHH` escapes in --probe-send / --probe-expect.
+/// Anything else after a backslash is kept as written.
+fn unescape(s: &str) -> Vec<u8> {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let decoded = match (bytes[i], bytes.get(i + 1)) {
+            (b'\\', Some(b'r')) => Some((b'\r', 2)),
+            (b'\\', Some(b'n')) => Some((b'\n', 2)),
+            (b'\\', Some(b't')) => Some((b'\t', 2)),
+            (b'\\', Some(b'\\')) => Some((b'\\', 2)),
+            (b'\\', Some(b'x')) => s
+                .get(i + 2..i + 4)
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                .map(|byte| (byte, 4)),
+            _ => None,
+        };
+        match decoded {
+            Some((byte, len)) => {
+                out.push(byte);
+                i += len;
+            }
+            None => {
+                out.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    out
+}
+
+/// --expect-bytes: hex digits, two per byte, optionally separated by spaces or colons
+fn parse_hex(s: &str) -> Result<Vec<u8>, String> {
+    let digits: String = s.chars().filter(|c| !c.is_whitespace() && *c != ':').collect();
+    let digits = digits.strip_prefix("0x").unwrap_or(&digits);
+    if digits.is_empty() {
+        return Err("no bytes given".to_string());
+    }
+    if let Some(c) = digits.chars().find(|c| !c.is_ascii_hexdigit()) {
+        return Err(format!("'{}' isn't a hex digit", c));
+    }
+    if !digits.len().is_multiple_of(2) {
+        return Err(format!("'{}' has an odd number of hex digits", s));
+    }
+    Ok((0..digits.len()).step_by(2).map(|i| u8::from_str_radix(&digits[i..i + 2], 16).unwrap_or_default()).collect())
+}
+
+/// Parse a --tag: a non-empty key of letters, digits, `_`, `-` or `.`, then `=` and any value
+fn parse_tag(s: &str) -> Result<(String, String), String> {
+    let (key, value) = s.split_once('=').ok_or_else(|| format!("'{}' is not KEY=VALUE", s))?;
+    if key.is_empty() {
+        return Err(format!("'{}': empty key", s));
+    }
+    if let Some(c) = key.chars().find(|c| !(c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))) {
+        return Err(format!("'{}': '{}' isn't allowed in a key", s, c));
+    }
+    Ok((key.to_string(), value.to_string()))
+}
+
+/// --tag labels as `env=prod team=payments`, for human output
+fn format_tags(tags: &[(String, String)]) -> String {
+    let tags: BTreeMap<&str, &str> = tags.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+    tags.iter().map(|(k, v)| format!("{}={}", k, v)).collect::<Vec<_>>().join(" ")
+}
+
+fn parse_jitter_percent(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(percent) if percent > 0.0 && percent < 100.0 => Ok(percent),
+        _ => Err(format!("'{}' is not a percentage in (0, 100)", s)),
+    }
+}
+
+fn parse_fail_percent(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(percent) if (0.0..100.0).contains(&percent) => Ok(percent),
+        _ => Err(format!("'{}' is not a percentage in [0, 100)", s)),
+    }
+}
+
+/// A firewall mark, in decimal or `0x` hex as `ip rule` writes it
+fn parse_fwmark(s: &str) -> Result<u32, String> {
+    let parsed = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+    parsed.map_err(|_| format!("'{}' is not a 32-bit mark (decimal or 0x hex)", s))
+}
+
+/// `d` scaled by a random factor within ±`percent`, for --timeout-jitter
+fn jittered(d: Duration, percent: Option<f64>) -> Duration {
+    match percent {
+        Some(percent) => d.mul_f64(1.0 + rng::with(|rng| rng.gen_range(-percent..=percent)) / 100.0),
+        None => d,
+    }
+}
+
+fn parse_ewma_alpha(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(alpha) if alpha > 0.0 && alpha <= 1.0 => Ok(alpha),
+        _ => Err(format!("'{}' is not a number in (0, 1]", s)),
+    }
+}
+
+fn parse_port(token: &str) -> Result<u16, String> {
+    match token.parse::<u32>() {
+        Ok(0) => Err(format!("'{}': port 0 is not a valid port", token)),
+        Ok(n) if n > u16::MAX as u32 => Err(format!("'{}': out of range (1-65535)", token)),
+        Ok(n) => Ok(n as u16),
+        Err(_) if token.chars().all(|c| c.is_ascii_digit()) => Err(format!("'{}': out of range (1-65535)", token)),
+        Err(_) => services::port_for_name(token).ok_or_else(|| format!("'{}': not a number or known service name", token)),
+    }
+}
+
+fn parse_ports(s: &str, step: u16) -> Result<Vec<u16>, String> {
+    if s.trim().is_empty() {
+        return Err("empty port specification".to_string());
+    }
+
+    let mut ports = Vec::new();
+    let mut errors = Vec::new();
+
+    for part in s.split(',').map(str::trim) {
+        if part.is_empty() {
+            errors.push("empty entry (stray comma?)".to_string());
+            continue;
+        }
+
+        // Service names such as `ftp-data` contain a dash but aren't ranges
+        if let Some(port) = services::port_for_name(part) {
+            ports.push(port);
+        } else if let Some((start, end)) = part.split_once('-') {
+            match (parse_port(start.trim()), parse_port(end.trim())) {
+                (Ok(start), Ok(end)) if start > end => {
+                    errors.push(format!("'{}': reversed range ({} > {})", part, start, end));
+                }
+                (Ok(start), Ok(end)) => ports.extend((start..=end).step_by(usize::from(step))),
+                (start, end) => {
+                    errors.extend(start.err());
+                    errors.extend(end.err());
+                }
+            }
+        } else {
+            match parse_port(part) {
+                Ok(port) => ports.push(port),
+                Err(e) => errors.push(e),
+            }
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(format!("invalid port specification: {}", errors.join("; ")));
+    }
+
+    ports.sort_unstable();
+    ports.dedup();
+    Ok(ports)
+}
+
+/// `22, 80, 443, 8000-8010`: the ports in order, each once, with runs of three or more
+/// consecutive ports written as ranges
+fn format_port_ranges(ports: &[u16]) -> String {
+    let mut ports = ports.to_vec();
+    ports.sort_unstable();
+    ports.dedup();
+    let mut runs: Vec<(u16, u16)> = Vec::new();
+    for port in ports {
+        match runs.last_mut() {
+            Some((_, end)) if u32::from(*end) + 1 == u32::from(port) => *end = port,
+            _ => runs.push((port, port)),
+        }
+    }
+    runs.iter()
+        .map(|&(start, end)| match end - start {
+            0 => start.to_string(),
+            1 => format!("{}, {}", start, end),
+            _ => format!("{}-{}", start, end),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// A host to probe along with the TCP ports to check on it
+#[derive(Debug, Clone)]
+struct Target {
+    host: String,
+    ports: Vec<u16>,
+}
+
+/// Scheme marking a Unix domain socket target, e.g. `unix:/var/run/docker.sock`
+const UDS_SCHEME: &str = "unix:";
+
+/// The socket path of a `unix:` target
+fn uds_path(host: &str) -> Option<&str> {
+    host.strip_prefix(UDS_SCHEME)
+}
+
+impl Target {
+    /// A `unix:` target names a socket path, not a host: it has no ports and is never pinged
+    fn is_uds(&self) -> bool {
+        uds_path(&self.host).is_some()
+    }
+}
+
+/// Parse `host`, `host:ports`, `[v6addr]:ports` or `unix:/path`, falling back to `default_ports`
+/// for bare hosts. Ranges in `ports` are sampled every `port_step` ports.
+fn parse_target(spec: &str, default_ports: &[u16], port_step: u16) -> Result<Target, String> {
+    if let Some(path) = uds_path(spec) {
+        if path.is_empty() {
+            return Err(format!("'{}': empty socket path", spec));
+        }
+        return Ok(Target { host: spec.to_string(), ports: Vec::new() });
+    }
+
+    let (host, port_spec) = if let Some(rest) = spec.strip_prefix('[') {
+        let (host, after) = rest
+            .split_once(']')
+            .ok_or_else(|| format!("'{}': missing closing ']'", spec))?;
+        match after {
+            "" => (host, None),
+            _ => match after.strip_prefix(':') {
+                Some(ports) => (host, Some(ports)),
+                None => return Err(format!("'{}': unexpected '{}' after ']'", spec, after)),
+            },
+        }
+    } else {
+        match spec.split_once(':') {
+            // A single colon separates host and ports; more than one is a bare IPv6 literal
+            Some((host, ports)) if !ports.contains(':') => (host, Some(ports)),
+            _ => (spec, None),
+        }
+    };
+
+    if host.is_empty() {
+        return Err(format!("'{}': empty host", spec));
+    }
+    // Catch a bad zone (`fe80::1%nosuchif`) now rather than as a lookup failure mid-scan
+    if host.contains('%') {
+        host.parse::<ScopedIp>()?;
+    }
+
+    let ports = match port_spec {
+        Some(port_spec) => parse_ports(port_spec, port_step).map_err(|e| format!("'{}': {}", spec, e))?,
+        None => default_ports.to_vec(),
+    };
+
+    Ok(Target { host: host.to_string(), ports })
+}
+
+/// Largest prefix that may be expanded without --force-large-range (a /16 or /112)
+const MAX_RANGE_ADDRESSES: u128 = 1 << 16;
+
+/// Hard limit on expansion even with --force-large-range
+const MAX_FORCED_RANGE_ADDRESSES: u128 = 1 << 24;
+
+/// Address blocks that never route across the internet. A range inside one of these can be
+/// scanned without --i-know-what-im-doing.
+const NON_PUBLIC_V4: &[(u32, u32)] = &[
+    (0x0000_0000, 8),  // 0.0.0.0/8
+    (0x0a00_0000, 8),  // 10.0.0.0/8
+    (0x6440_0000, 10), // 100.64.0.0/10, carrier-grade NAT
+    (0x7f00_0000, 8),  // 127.0.0.0/8
+    (0xa9fe_0000, 16), // 169.254.0.0/16
+    (0xac10_0000, 12), // 172.16.0.0/12
+    (0xc000_0200, 24), // 192.0.2.0/24
+    (0xc0a8_0000, 16), // 192.168.0.0/16
+    (0xc612_0000, 15), // 198.18.0.0/15, benchmarking
+    (0xc633_6400, 24), // 198.51.100.0/24
+    (0xcb00_7100, 24), // 203.0.113.0/24
+];
+
+const NON_PUBLIC_V6: &[(u128, u32)] = &[
+    (1, 128),                // ::1
+    (0xfc00 << 112, 7),      // fc00::/7, unique local
+    (0xfe80 << 112, 10),     // fe80::/10, link-local
+    (0x2001_0db8 << 96, 32), // 2001:db8::/32, documentation
+];
+
+/// Whether `addr/prefix` reaches any address outside the blocks above
+fn is_public_range(addr: IpAddr, prefix: u32) -> bool {
+    match addr {
+        IpAddr::V4(v4) => !NON_PUBLIC_V4.iter().any(|&(block, block_prefix)| {
+            prefix >= block_prefix && (u32::from(v4) ^ block) >> (32 - block_prefix) == 0
+        }),
+        IpAddr::V6(v6) => !NON_PUBLIC_V6.iter().any(|&(block, block_prefix)| {
+            prefix >= block_prefix && (block_prefix == 128 || (u128::from(v6) ^ block) >> (128 - block_prefix) == 0)
+        }),
+    }
+}
+
+/// Expand a CIDR block (`10.0.0.0/24`, `2001:db8::/120`) into its host addresses.
+/// Returns `Ok(None)` when `host` isn't CIDR notation.
+fn expand_cidr(host: &str, force_large: bool, allow_public: bool) -> Result<Option<Vec<String>>, String> {
+    let Some((addr, prefix)) = host.split_once('/') else {
+        return Ok(None);
+    };
+
+    let addr: IpAddr = addr
+        .parse()
+        .map_err(|_| format!("'{}': '{}' is not an IP address", host, addr))?;
+    let prefix: u32 = prefix
+        .parse()
+        .map_err(|_| format!("'{}': prefix length '{}' is not a number", host, prefix))?;
+    let width = if addr.is_ipv4() { 32 } else { 128 };
+    if prefix > width {
+        return Err(format!("'{}': prefix length must be between 0 and {}", host, width));
+    }
+
+    let host_bits = width - prefix;
+    let size: u128 = if host_bits >= 128 { u128::MAX } else { 1 << host_bits };
+    if size > MAX_FORCED_RANGE_ADDRESSES {
+        return Err(format!("'{}': range is too large to scan ({} host bits)", host, host_bits));
+    }
+    if size > MAX_RANGE_ADDRESSES && !force_large {
+        return Err(format!(
+            "'{}': range contains {} addresses; pass --force-large-range to scan more than {}",
+            host, size, MAX_RANGE_ADDRESSES
+        ));
+    }
+    if !allow_public && is_public_range(addr, prefix) {
+        return Err(format!(
+            "'{}': range of {} addresses reaches public address space; pass --i-know-what-im-doing to scan it",
+            host, size
+        ));
+    }
+
+    let addresses = match addr {
+        IpAddr::V4(v4) => {
+            let mask = if host_bits == 32 { 0 } else { u32::MAX << host_bits };
+            let network = u32::from(v4) & mask;
+            let last = network | !mask;
+            // Skip the network and broadcast addresses unless the block is a /31 or /32
+            let (first, last) = if host_bits >= 2 { (network + 1, last - 1) } else { (network, last) };
+            (first..=last).map(|a| std::net::Ipv4Addr::from(a).to_string()).collect()
+        }
+        IpAddr::V6(v6) => {
+            let mask = if host_bits == 128 { 0 } else { u128::MAX << host_bits };
+            let network = u128::from(v6) & mask;
+            (0..size).map(|offset| std::net::Ipv6Addr::from(network + offset).to_string()).collect()
+        }
+    };
+
+    Ok(Some(addresses))
+}
+
+/// Parse one octet of an nmap-style spec: a comma list of values and ranges, where a range may
+/// leave either end open (`-10`, `200-`, `-`)
+fn parse_octet(spec: &str, octet: &str) -> Result<Vec<u8>, String> {
+    let value = |s: &str, default: u8| -> Result<u8, String> {
+        if s.is_empty() {
+            return Ok(default);
+        }
+        let n: u32 = s
+            .parse()
+            .map_err(|_| format!("'{}': '{}' in octet '{}' is not a number", spec, s, octet))?;
+        u8::try_from(n).map_err(|_| format!("'{}': octet value '{}' in '{}' exceeds 255", spec, s, octet))
+    };
+
+    let mut values = Vec::new();
+    for part in octet.split(',') {
+        if part.is_empty() {
+            return Err(format!("'{}': empty entry in octet '{}' (stray comma?)", spec, octet));
+        }
+        match part.split_once('-') {
+            Some((start, end)) => {
+                let (start, end) = (value(start, 0)?, value(end, 255)?);
+                if start > end {
+                    return Err(format!("'{}': reversed octet range '{}' ({} > {})", spec, part, start, end));
+                }
+                values.extend(start..=end);
+            }
+            None => values.push(value(part, 0)?),
+        }
+    }
+    values.sort_unstable();
+    values.dedup();
+    Ok(values)
+}
+
+/// Expand an nmap-style IPv4 target (`192.168.0-2.1-254`, `10.0.0.1,5,9`) into its addresses.
+/// Returns `Ok(None)` when `host` isn't one. Unlike a CIDR block every address in the ranges is
+/// kept, .0 and .255 included, as nmap does.
+fn expand_octets(host: &str, force_large: bool, allow_public: bool) -> Result<Option<Vec<String>>, String> {
+    let octets: Vec<&str> = host.split('.').collect();
+    // Hostnames may contain dashes but never commas, so a comma marks a spec even when the
+    // rest of it is malformed
+    let is_spec = octets.len() == 4
+        && (host.contains(',')
+            || (host.contains('-') && host.chars().all(|c| c.is_ascii_digit() || matches!(c, '.' | '-'))));
+    if !is_spec {
+        return Ok(None);
+    }
+    if let Some(empty) = octets.iter().position(|o| o.is_empty()) {
+        return Err(format!("'{}': octet {} is empty", host, empty + 1));
+    }
+
+    let octets = octets
+        .iter()
+        .map(|octet| parse_octet(host, octet))
+        .collect::<Result<Vec<_>, _>>()?;
+    let size: u128 = octets.iter().map(|values| values.len() as u128).product();
+    if size > MAX_FORCED_RANGE_ADDRESSES {
+        return Err(format!("'{}': range is too large to scan ({} addresses)", host, size));
+    }
+    if size > MAX_RANGE_ADDRESSES && !force_large {
+        return Err(format!(
+            "'{}': range contains {} addresses; pass --force-large-range to scan more than {}",
+            host, size, MAX_RANGE_ADDRESSES
+        ));
+    }
+
+    let mut addresses = Vec::with_capacity(size as usize);
+    for &a in &octets[0] {
+        for &b in &octets[1] {
+            for &c in &octets[2] {
+                for &d in &octets[3] {
+                    let addr = std::net::Ipv4Addr::new(a, b, c, d);
+                    if !allow_public && is_public_range(IpAddr::V4(addr), 32) {
+                        return Err(format!(
+                            "'{}': range reaches public address {}; pass --i-know-what-im-doing to scan it",
+                            host, addr
+                        ));
+                    }
+                    addresses.push(addr.to_string());
+                }
+            }
+        }
+    }
+
+    Ok(Some(addresses))
+}
+
+/// Replace any CIDR or nmap-style range targets with one target per host address, keeping their ports
+fn expand_hosts(targets: Vec<Target>, args: &Args) -> Result<Vec<Target>, String> {
+    let max_targets = args.max_targets.unwrap_or(if args.force_large_range {
+        MAX_FORCED_RANGE_ADDRESSES as u64
+    } else {
+        MAX_RANGE_ADDRESSES as u64
+    });
+    let mut expanded: Vec<Target> = Vec::with_capacity(targets.len());
+    let mut index: HashMap<String, usize> = HashMap::new();
+
+    for target in targets {
+        // Socket paths contain slashes too, but aren't ranges
+        let cidr = if target.is_uds() {
+            None
+        } else {
+            match expand_cidr(&target.host, args.force_large_range, args.allow_public_ranges)? {
+                Some(addresses) => Some(addresses),
+                None => expand_octets(&target.host, args.force_large_range, args.allow_public_ranges)?,
+            }
+        };
+        let hosts = match cidr {
+            Some(addresses) => addresses,
+            None => vec![target.host],
+        };
+        for host in hosts {
+            // A host named more than once (or covered by overlapping ranges) is probed once with all its ports
+            match index.get(&host) {
+                Some(&i) => {
+                    let existing = &mut expanded[i];
+                    existing.ports.extend(&target.ports);
+                    existing.ports.sort_unstable();
+                    existing.ports.dedup();
+                }
+                None => {
+                    index.insert(host.clone(), expanded.len());
+                    expanded.push(Target { host, ports: target.ports.clone() });
+                }
+            }
+        }
+    }
+
+    if expanded.len() as u64 > max_targets {
+        return Err(format!(
+            "the hosts expand to {} targets, more than the limit of {}; raise --max-targets to scan them all",
+            expanded.len(),
+            max_targets
+        ));
+    }
+    Ok(expanded)
+}
+
+/// Render raw banner bytes as a printable string, escaping anything non-printable
+fn escape_banner(bytes: &[u8]) -> String {
+    let trimmed = match bytes.iter().rposition(|b| !b.is_ascii_whitespace()) {
+        Some(end) => &bytes[..=end],
+        None => &[],
+    };
+
+    let mut out = String::with_capacity(trimmed.len());
+    for &b in trimmed {
+        match b {
+            b'\r' => out.push_str("\\r"),
+            b'\n' => out.push_str("\\n"),
+            b'\t' => out.push_str("\\t"),
+            b'\\' => out.push_str("\\\\"),
+            0x20..=0x7e => out.push(b as char),
+            _ => out.push_str(&format!("\\x{:02x}", b)),
+        }
+    }
+    out
+}
+
+/// --max-response-bytes: the SHA-256 of a banner or response body, in hex
+fn content_sha256(content: &[u8]) -> String {
+    let digest = ring_crypto::digest::digest(&ring_crypto::digest::SHA256, content);
+    digest.as_ref().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Read whatever the server sends on connect, up to `max_bytes`. With `whole` it keeps reading
+/// past the first line, until `max_bytes`, the end of the stream or the timeout.
+async fn read_banner(stream: &mut TcpStream, max_bytes: usize, timeout_ms: u64, whole: bool) -> Vec<u8> {
+    let mut buf = vec![0u8; max_bytes];
+    let mut filled = 0;
+    let deadline = tokio::time::Instant::now() + Duration::from_millis(timeout_ms);
+
+    while filled < max_bytes {
+        match tokio::time::timeout_at(deadline, stream.read(&mut buf[filled..])).await {
+            Ok(Ok(0)) | Ok(Err(_)) | Err(_) => break,
+            Ok(Ok(n)) => {
+                filled += n;
+                // Most greetings are a single line; don't wait out the timeout once we have one
+                if !whole && buf[..filled].contains(&b'\n') {
+                    break;
+                }
+            }
+        }
+    }
+    buf.truncate(filled);
+    buf
+}
+
+/// Send `size` bytes and time how long the far side takes to consume them: a discard service
+/// closes its end once it has read up to our FIN, an echo service once it has sent everything
+/// back. Echoed bytes are read and dropped as they arrive so the transfer never stalls on a full
+/// receive window. Returns megabytes per second, or `None` if the transfer failed or took longer
+/// than `timeout_ms`.
+async fn measure_throughput(stream: &mut TcpStream, size: usize, timeout_ms: u64) -> Option<f64> {
+    use tokio::io::AsyncWriteExt;
+
+    let start = Instant::now();
+    let (mut reader, mut writer) = stream.split();
+    let send = async {
+        let chunk = [0u8; 16 * 1024];
+        let mut remaining = size;
+        while remaining > 0 {
+            let n = remaining.min(chunk.len());
+            writer.write_all(&chunk[..n]).await?;
+            remaining -= n;
+        }
+        writer.shutdown().await
+    };
+    let drain = async {
+        let mut buf = [0u8; 16 * 1024];
+        while reader.read(&mut buf).await? > 0 {}
+        Ok(())
+    };
+
+    match timeout(Duration::from_millis(timeout_ms), future::try_join(send, drain)).await {
+        Ok(Ok(_)) => Some(size as f64 / start.elapsed().as_secs_f64() / 1e6),
+        Ok(Err(e)) => {
+            tracing::debug!(error = %e, "throughput transfer failed");
+            None
+        }
+        Err(_) => {
+            tracing::debug!(timeout_ms, "throughput transfer timed out");
+            None
+        }
+    }
+}
+
+/// Whether an I/O error means the process (EMFILE) or system (ENFILE) ran out of file descriptors
+fn is_fd_exhaustion(e: &std::io::Error) -> bool {
+    #[cfg(unix)]
+    {
+        matches!(e.raw_os_error(), Some(libc::EMFILE) | Some(libc::ENFILE))
+    }
+    #[cfg(not(unix))]
+    {
+        // WSAEMFILE
+        e.raw_os_error() == Some(10024)
+    }
+}
+
+/// Set from --json-errors: diagnostics on stderr are emitted as JSON objects instead of text
+static JSON_ERRORS: AtomicBool = AtomicBool::new(false);
+
+/// Set from --silent: diagnostics aren't printed at all
+static SILENT: AtomicBool = AtomicBool::new(false);
+
+/// Print a fatal error to stderr
+fn report_error(message: &str) {
+    if SILENT.load(Ordering::Relaxed) {
+        return;
+    }
+    if JSON_ERRORS.load(Ordering::Relaxed) {
+        eprintln!("{}", serde_json::json!({ "level": "error", "message": message }));
+    } else {
+        eprintln!("{} {}", icons::get("error").red(), message);
+    }
+}
+
+/// Print a non-fatal warning to stderr
+fn report_warning(message: &str) {
+    if SILENT.load(Ordering::Relaxed) {
+        return;
+    }
+    if JSON_ERRORS.load(Ordering::Relaxed) {
+        eprintln!("{}", serde_json::json!({ "level": "warning", "message": message }));
+    } else {
+        eprintln!("{} {}", icons::get("warning").yellow(), message);
+    }
+}
+
+static FD_EXHAUSTION_WARNED: AtomicBool = AtomicBool::new(false);
+
+fn warn_fd_exhaustion_once() {
+    if !FD_EXHAUSTION_WARNED.swap(true, Ordering::Relaxed) {
+        report_warning(
+            "Ran out of file descriptors (too many open files). Scan fewer hosts/ports per run or raise the limit with `ulimit -n`.",
+        );
+    }
+}
+
+/// The soft limit on open file descriptors for this process, if it can be determined
+#[cfg(unix)]
+fn fd_soft_limit() -> Option<u64> {
+    let mut limit = libc::rlimit { rlim_cur: 0, rlim_max: 0 };
+    // SAFETY: getrlimit only writes into the struct we pass it
+    let rc = unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) };
+    #[allow(clippy::unnecessary_cast)] // rlim_t isn't u64 on every platform
+    (rc == 0).then_some(limit.rlim_cur as u64)
+}
+
+#[cfg(not(unix))]
+fn fd_soft_limit() -> Option<u64> {
+    None
+}
+
+/// How a TCP probe reaches its target
+enum Route<'a> {
+    Direct(SocketAddr),
+    Proxied(&'a proxy::Proxy),
+}
+
+/// Open one probe connection, describing failures the way results report them
+async fn connect(
+    route: &Route<'_>,
+    host: &str,
+    port: u16,
+    source_port: Option<u16>,
+    opts: &ProbeOptions,
+) -> Result<TcpStream, ConnectError> {
+    match route {
+        Route::Proxied(proxy) => proxy::connect(proxy, host, port, source_port, opts).await,
+        Route::Direct(addr) => socket::connect_tcp(*addr, source_port, opts).await.map_err(|e| {
+            if is_fd_exhaustion(&e) {
+                warn_fd_exhaustion_once();
+                format!("too_many_open_files: {}", e).into()
+            } else {
+                ConnectError { message: format!("connection_error: {}", e), kind: FailureKind::from_io(&e) }
+            }
+        }),
+    }
+}
+
+/// Probe `host:port`, once per resolved address with --all-addresses and otherwise just the first
+#[tracing::instrument(level = "debug", skip(count, timeout_ms, opts))]
+async fn tcp_check(host: String, port: u16, count: u32, timeout_ms: u64, opts: &ProbeOptions) -> Vec<HostResult> {
+    // Through a proxy the far side resolves the name, so only resolve locally when connecting directly
+    let resolve_start = Instant::now();
+    let dns_ms = || Some(resolve_start.elapsed().as_secs_f64() * 1000.0);
+    let (routes, dns_resolution_ms, addresses) = if let Some(proxy) = &opts.proxy {
+        (vec![Route::Proxied(proxy)], None, Vec::new())
+    } else if let Ok(ip) = host.parse::<ScopedIp>() {
+        (vec![Route::Direct(ip.socket_addr(port))], None, Vec::new())
+    } else if opts.mdns && mdns::is_mdns_host(&host) {
+        match mdns::resolve(&host, timeout_ms).await {
+            Some(ip) => (vec![Route::Direct(SocketAddr::new(ip, port))], dns_ms(), address_records(&[ip])),
+            None => {
+                let failure = HostResult::failure(&host, Some(port), "tcp", count, "mdns_resolution_failed".to_string());
+                return vec![HostResult { dns_resolution_ms: dns_ms(), ..failure }];
+            }
+        }
+    } else {
+        let ips = opts.resolver.lookup(&host).await.unwrap_or_default();
+        let addresses = address_records(&ips);
+        let addrs = opts.pick_addresses(ips.into_iter().map(|ip| SocketAddr::new(ip, port)).collect());
+        if addrs.is_empty() {
+            tracing::debug!("no addresses; giving up");
+            let failure = HostResult::failure(&host, Some(port), "tcp", count, "dns_resolution_failed".to_string());
+            return vec![HostResult { dns_resolution_ms: dns_ms(), ..failure }];
+        }
+        tracing::debug!(?addrs, "resolved");
+        (addrs.into_iter().map(Route::Direct).collect(), dns_ms(), addresses)
+    };
+
+    let host = &host;
+    let probes = routes.iter().map(|route| async move {
+        match route {
+            // SYN probes are IPv4 only; anything else falls back to a full connect
+            Route::Direct(SocketAddr::V4(addr)) if opts.syn => {
+                syn_probe(host, *addr, count, timeout_ms, dns_resolution_ms, opts).await
+            }
+            _ if opts.keepalive.is_some() => {
+                keepalive_probe(host, port, route, count, timeout_ms, dns_resolution_ms, opts).await
+            }
+            _ => tcp_probe(host, port, route, count, timeout_ms, dns_resolution_ms, opts).await,
+        }
+    });
+    let mut results = future::join_all(probes).await;
+    if opts.fastest_address {
+        results = keep_fastest(results);
+    }
+    for result in &mut results {
+        result.addresses = addresses.clone();
+    }
+    results
+}
+
+async fn syn_probe(
+    host: &str,
+    addr: SocketAddrV4,
+    count: u32,
+    timeout_ms: u64,
+    dns_resolution_ms: Option<f64>,
+    opts: &ProbeOptions,
+) -> HostResult {
+    let mut response_times = Vec::new();
+    let mut successful = 0;
+    let mut last_error = None;
+    let mut last_failure_kind = None;
+    let mut slowest = None;
+    let mut timeouts = 0;
+    let mut attempt_log = Vec::new();
+    let max_attempts = opts.max_attempts(count);
+    let mut attempts = count;
+
+    for attempt in 1..=opts.warmup + max_attempts {
+        let attempt_timeout = opts.attempt_timeout(timeout_ms, slowest);
+        let (outcome, _, retries) = opts
+            .with_connect_retries(
+                attempt,
+                || syn::probe(*addr.ip(), addr.port(), attempt_timeout, opts.dscp, opts.fwmark),
+                |outcome| match outcome {
+                    Ok((syn::PortState::Open, _)) => None,
+                    Ok((syn::PortState::Closed, _)) => Some(Some(FailureKind::Refused)),
+                    Ok((syn::PortState::Filtered, _)) => Some(Some(FailureKind::Filtered)),
+                    Err(_) => Some(None),
+                },
+            )
+            .await;
+        if opts.is_warmup(attempt) {
+            continue;
+        }
+        let seq = attempt - opts.warmup;
+        // --ramp-ci: attempts past -c count towards the probe
+        attempts = attempts.max(seq);
+        match outcome {
+            Ok((syn::PortState::Open, rtt)) => {
+                successful += 1;
+                response_times.push(rtt.as_millis());
+                attempt_log.push(AttemptRecord::success(seq, rtt.as_millis()));
+                slowest = slowest.max(Some(rtt));
+            }
+            Ok((syn::PortState::Closed, _)) => {
+                attempt_log.push(AttemptRecord::failure(seq, "port_closed"));
+                last_error = Some("port_closed".to_string());
+                last_failure_kind = Some(FailureKind::Refused);
+            }
+            Ok((syn::PortState::Filtered, _)) => {
+                timeouts += 1;
+                attempt_log.push(AttemptRecord::failure(seq, "filtered"));
+                last_error = Some("filtered".to_string());
+                last_failure_kind = Some(FailureKind::Filtered);
+            }
+            Err(e) => {
+                let error = format!("syn_error: {}", e);
+                attempt_log.push(AttemptRecord::failure(seq, &error));
+                last_error = Some(error);
+                last_failure_kind = None;
+            }
+        }
+
+        if let Some(record) = attempt_log.last_mut() {
+            record.retries = retries;
+        }
+
+        // --retry-on/--retry-budget: a failure that won't be retried ends the probe and fails its
+        // remaining attempts
+        if seq < max_attempts && attempt_log.last().is_some_and(|a| !a.success) && !opts.retries(last_failure_kind) {
+            tracing::debug!(attempt, "not retrying");
+            attempts = count.max(seq);
+            if last_failure_kind == Some(FailureKind::Filtered) {
+                timeouts += attempts - seq;
+            }
+            break;
+        }
+
+        // --stop-after-successes: that many successes make the probe up, so skip the rest
+        if opts.enough_successes(successful) {
+            tracing::debug!(attempt, "enough successes");
+            attempts = seq;
+            break;
+        }
+        // --ramp-ci: past the -c attempts, sample until the mean is pinned down
+        if seq >= count && opts.precise_enough(&response_times) {
+            tracing::debug!(attempt, "confidence interval reached");
+            attempts = seq;
+            break;
+        }
+    }
+
+    let success_rate = successful as f64 / attempts as f64;
+    let status = opts.status(successful, timeouts, attempts);
+
+    HostResult {
+        host: host.to_string(),
+        port: Some(addr.port()),
+        test_type: "syn".to_string(),
+        attempts,
+        successful,
+        success_rate,
+        avg_response_time_ms: mean(&response_times.iter().map(|&t| t as f64).collect::<Vec<_>>()),
+        min_response_time_ms: response_times.iter().min().map(|&t| t as f64),
+        max_response_time_ms: response_times.iter().max().map(|&t| t as f64),
+        jitter_ms: jitter(&response_times),
+        mean_ci_percent: opts.ramp.and(mean_ci_percent(&response_times)),
+        response_times,
+        attempt_log,
+        status: status.to_string(),
+        error: if successful == 0 { last_error } else { None },
+        dscp: opts.dscp,
+        resolved_addr: opts.record_addr.then_some(IpAddr::V4(*addr.ip()).into()),
+        dns_resolution_ms,
+        failure_kind: if successful == 0 { last_failure_kind } else { None },
+        ..Default::default()
+    }
+}
+
+async fn tcp_probe(
+    host: &str,
+    port: u16,
+    route: &Route<'_>,
+    count: u32,
+    timeout_ms: u64,
+    dns_resolution_ms: Option<f64>,
+    opts: &ProbeOptions,
+) -> HostResult {
+    let mut response_times = Vec::new();
+    let mut successful = 0;
+    let mut last_error = None;
+    let mut last_failure_kind = None;
+    let mut banner = None;
+    let (mut connect_times, mut tls_times, mut ttfb_times) = (Vec::new(), Vec::new(), Vec::new());
+    let mut http_status = None;
+    let mut tfo_used = None;
+    let mut throughput_mbps = None;
+    let mut source_ports = Vec::new();
+    let mut slowest = None;
+    let mut timeouts = 0;
+    let mut attempt_log = Vec::new();
+    let max_attempts = opts.max_attempts(count);
+    let mut attempts = count;
+    let mut captive = false;
+    let mut payload_error = None;
+    let mut peer_mismatch = None;
+    let mut content_hash = None;
+
+    for attempt in 1..=opts.warmup + max_attempts {
+        let source_port = opts.random_source_port.then(socket::random_source_port);
+        let attempt_timeout = opts.attempt_timeout(timeout_ms, slowest);
+        let (result, start, retries) = opts
+            .with_connect_retries(
+                attempt,
+                || timeout(attempt_timeout, connect(route, host, port, source_port, opts)),
+                |result| match result {
+                    Ok(Ok(_)) => None,
+                    Ok(Err(e)) => Some(e.kind),
+                    Err(_) => Some(Some(FailureKind::Filtered)),
+                },
+            )
+            .await;
+        let elapsed = start.elapsed();
+
+        if opts.is_warmup(attempt) {
+            // Go through the whole exchange, so the server's caches are warm as well
+            if let (Ok(Ok(mut stream)), true) = (result, opts.http) {
+                let _ = http::probe(&mut stream, host, opts.http_path(), opts.tls, opts.read_timeout_ms, None, false).await;
+            }
+            tracing::trace!(attempt, ?elapsed, "warm-up done");
+            continue;
+        }
+        let seq = attempt - opts.warmup;
+        // --ramp-ci: attempts past -c count towards the probe
+        attempts = attempts.max(seq);
+        source_ports.extend(source_port);
+        if let (Ok(Ok(stream)), Route::Direct(addr)) = (&result, route) {
+            if let Some(mismatch) = PeerMismatch::check(stream, *addr) {
+                tracing::debug!(attempt, connected = %mismatch.connected, "peer isn't the address dialed");
+                peer_mismatch = Some(mismatch);
+            }
+        }
+
+        match result {
+            Ok(Ok(mut stream)) if opts.http => {
+                // Only the first successful attempt downloads
+                let download = match (opts.throughput, opts.content_bytes) {
+                    (Some(size), _) if throughput_mbps.is_none() => Some(http::Download::Throughput(size)),
+                    (_, Some(limit)) if content_hash.is_none() => Some(http::Download::Body(limit)),
+                    _ => None,
+                };
+                match http::probe(&mut stream, host, opts.http_path(), opts.tls, opts.read_timeout_ms, download, opts.hold.is_some()).await {
+                    Ok(timing) => {
+                        // Something other than the endpoint answered: a portal redirecting or serving its login page
+                        captive |= opts.captive_check && timing.status != Some(204);
+                        if opts.tfo {
+                            tfo_used = socket::fastopen_used(&stream).map(|used| used || tfo_used == Some(true));
+                        }
+                        successful += 1;
+                        // The headline latency covers the whole exchange; the breakdown says where it went
+                        let rtt = (start.elapsed() - timing.download_time).as_millis();
+                        response_times.push(rtt);
+                        attempt_log.push(AttemptRecord::success(seq, rtt));
+                        slowest = slowest.max(Some(elapsed));
+                        connect_times.push(elapsed.as_secs_f64() * 1000.0);
+                        tls_times.extend(timing.tls_ms);
+                        ttfb_times.push(timing.ttfb_ms);
+                        http_status = timing.status.or(http_status);
+                        throughput_mbps = throughput_mbps.or(timing.throughput_mbps);
+                        content_hash = content_hash.or(timing.body.as_deref().map(content_sha256));
+                        if let Some(hold) = opts.hold {
+                            tokio::time::sleep(hold).await;
+                        }
+                    }
+                    Err(e) => {
+                        tracing::debug!(attempt, error = %e, "HTTP exchange failed");
+                        attempt_log.push(AttemptRecord::failure(seq, &e));
+                        last_error = Some(e);
+                        last_failure_kind = None;
+                    }
+                }
+            }
+            Ok(Ok(mut stream)) => {
+                tracing::trace!(attempt, ?elapsed, "connected");
+                successful += 1;
+                response_times.push(elapsed.as_millis());
+                attempt_log.push(AttemptRecord::success(seq, elapsed.as_millis()));
+                slowest = slowest.max(Some(elapsed));
+
+                if let (Some(max_bytes), None) = (opts.banner_bytes, &banner) {
+                    let raw = read_banner(&mut stream, max_bytes, opts.banner_timeout_ms, opts.content_hash).await;
+                    // The hash covers the bytes as sent, before they're trimmed and escaped for display
+                    content_hash = (opts.content_hash && !raw.is_empty()).then(|| content_sha256(&raw));
+                    banner = Some(escape_banner(&raw)).filter(|b| !b.is_empty());
+                }
+                // The port is open either way; a wrong answer only makes the probe partial
+                if let Some((send, expect)) = &opts.payload {
+                    let exchanged = timeout(
+                        Duration::from_millis(opts.read_timeout_ms),
+                        round_trip(&mut stream, "payload", send, expect.as_deref(), false),
+                    )
+                    .await;
+                    if let Some(e) = match exchanged {
+                        Ok(Ok(())) => None,
+                        Ok(Err(e)) => Some(e),
+                        Err(_) => Some("payload_timeout".to_string()),
+                    } {
+                        tracing::debug!(attempt, error = %e, "payload exchange failed");
+                        payload_error = Some(e);
+                    }
+                }
+                if let (Some(size), None) = (opts.throughput, throughput_mbps) {
+                    throughput_mbps = measure_throughput(&mut stream, size, opts.read_timeout_ms).await;
+                }
+                if let Some(hold) = opts.hold {
+                    tokio::time::sleep(hold).await;
+                }
+            }
+            Ok(Err(e)) => {
+                tracing::debug!(attempt, error = %e.message, "connect failed");
+                attempt_log.push(AttemptRecord::failure(seq, &e.message));
+                last_error = Some(e.message);
+                last_failure_kind = e.kind;
+            }
+            Err(_) => {
+                tracing::debug!(attempt, ?attempt_timeout, "connect timed out");
+                timeouts += 1;
+                attempt_log.push(AttemptRecord::failure(seq, "timeout"));
+                last_error = Some("timeout".to_string());
+                last_failure_kind = Some(FailureKind::Filtered);
+            }
+        }
+
+        if let Some(record) = attempt_log.last_mut() {
+            record.retries = retries;
+        }
+
+        // --retry-on/--retry-budget: a failure that won't be retried ends the probe and fails its
+        // remaining attempts
+        if seq < max_attempts && attempt_log.last().is_some_and(|a| !a.success) && !opts.retries(last_failure_kind) {
+            tracing::debug!(attempt, "not retrying");
+            attempts = count.max(seq);
+            if last_failure_kind == Some(FailureKind::Filtered) {
+                timeouts += attempts - seq;
+            }
+            break;
+        }
+
+        // --stop-after-successes: that many successes make the probe up, so skip the rest
+        if opts.enough_successes(successful) {
+            tracing::debug!(attempt, "enough successes");
+            attempts = seq;
+            break;
+        }
+        // --ramp-ci: past the -c attempts, sample until the mean is pinned down
+        if seq >= count && opts.precise_enough(&response_times) {
+            tracing::debug!(attempt, "confidence interval reached");
+            attempts = seq;
+            break;
+        }
+    }
+
+    let success_rate = successful as f64 / attempts as f64;
+    let avg_response_time = if !response_times.is_empty() {
+        Some(response_times.iter().sum::<u128>() as f64 / response_times.len() as f64)
+    } else {
+        None
+    };
+    let min_response_time = response_times.iter().min().map(|&t| t as f64);
+    let max_response_time = response_times.iter().max().map(|&t| t as f64);
+
+    // Behind a portal the request got an answer, just not from the endpoint: never fully up
+    let (status, error) = match opts.status(successful, timeouts, attempts) {
+        _ if captive => ("partial", Some("captive_portal_detected".to_string())),
+        _ if payload_error.is_some() => ("partial", payload_error),
+        status => (status, if successful == 0 { last_error } else { None }),
+    };
+
+    HostResult {
+        host: host.to_string(),
+        port: Some(port),
+        test_type: "tcp".to_string(),
+        attempts,
+        successful,
+        success_rate,
+        avg_response_time_ms: avg_response_time,
+        min_response_time_ms: min_response_time,
+        max_response_time_ms: max_response_time,
+        jitter_ms: jitter(&response_times),
+        mean_ci_percent: opts.ramp.and(mean_ci_percent(&response_times)),
+        response_times,
+        attempt_log,
+        status: status.to_string(),
+        error,
+        banner,
+        dscp: opts.dscp,
+        source_ports,
+        proxy: opts.proxy.as_ref().map(|p| p.display_url()),
+        resolved_addr: match route {
+            Route::Direct(addr) if opts.record_addr => Some(ScopedIp::from(*addr)),
+            _ => None,
+        },
+        peer_mismatch,
+        dns_resolution_ms,
+        connect_ms: mean(&connect_times),
+        tls_ms: mean(&tls_times),
+        ttfb_ms: mean(&ttfb_times),
+        http_status,
+        tfo_used,
+        throughput_mbps,
+        content_sha256: content_hash,
+        failure_kind: if successful == 0 { last_failure_kind } else { None },
+        ..Default::default()
+    }
+}
+
+/// What a --keepalive or --echo round trip sends and waits for
+#[derive(Debug, Clone)]
+enum Exchange {
+    /// --probe-send, answered by --probe-expect (or, without it, by any reply)
+    Request(Vec<u8>, Option<Vec<u8>>),
+    /// RFC 862 echo: a line naming the attempt and when it was sent, which has to come back as is
+    Echo,
+}
+
+impl Exchange {
+    /// Prefix of this exchange's error names: `keepalive_timeout`, `echo_mismatch`, …
+    fn name(&self) -> &'static str {
+        match self {
+            Exchange::Request(..) => "keepalive",
+            Exchange::Echo => "echo",
+        }
+    }
+
+    /// `ring echo 3 1760000000123456\n`: unique per attempt, so a late reply to an earlier
+    /// round trip can't be mistaken for this one's
+    fn echo_payload(attempt: u32) -> Vec<u8> {
+        let sent_us = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_micros()).unwrap_or_default();
+        format!("ring echo {} {}\n", attempt, sent_us).into_bytes()
+    }
+}
+
+/// A --pool connection: the target it reaches, and the address it was made to unless proxied
+type PoolKey = (String, u16, Option<SocketAddr>);
+
+/// --pool: the connections --keepalive and --echo probes left open for the next scan. A
+/// connection that broke is remembered as `None`, so the one replacing it counts as a reconnect.
+#[derive(Debug, Default)]
+struct ConnectionPool {
+    streams: Mutex<HashMap<PoolKey, Option<TcpStream>>>,
+}
+
+/// What a --pool probe found waiting for it
+#[derive(Debug)]
+enum Pooled {
+    /// Nothing: the target's first scan
+    Empty,
+    /// A connection still open from the last scan
+    Reused(TcpStream),
+    /// The last scan's connection, which has since been closed, reset or broken
+    Dropped,
+}
+
+impl ConnectionPool {
+    /// Take `key`'s connection, if it is still usable: the peer hasn't closed it or said
+    /// anything unasked since, which would be mistaken for the next reply
+    fn take(&self, key: &PoolKey) -> Pooled {
+        let stream = match self.streams.lock().unwrap().remove(key) {
+            None => return Pooled::Empty,
+            Some(None) => return Pooled::Dropped,
+            Some(Some(stream)) => stream,
+        };
+        match stream.try_read(&mut [0u8; 1]) {
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => Pooled::Reused(stream),
+            _ => Pooled::Dropped,
+        }
+    }
+
+    /// Leave `stream` open for the next scan, or with `None` note that the connection was lost
+    fn put(&self, key: PoolKey, stream: Option<TcpStream>) {
+        self.streams.lock().unwrap().insert(key, stream);
+    }
+}
+
+/// Send one round trip's request and wait for its reply: `expect` when given, otherwise any
+/// bytes. With `exact` the reply has to be `expect` itself, and a byte that differs fails at once.
+async fn round_trip(stream: &mut TcpStream, name: &str, send: &[u8], expect: Option<&[u8]>, exact: bool) -> Result<(), String> {
+    use tokio::io::AsyncWriteExt;
+
+    stream.write_all(send).await.map_err(|e| format!("{}_error: {}", name, e))?;
+
+    let mut reply = Vec::new();
+    let mut buf = [0u8; 1024];
+    loop {
+        let n = stream.read(&mut buf).await.map_err(|e| format!("{}_error: {}", name, e))?;
+        if n == 0 {
+            return Err(match expect {
+                Some(_) if !reply.is_empty() => format!("{}_mismatch: {}", name, escape_banner(&reply[..reply.len().min(256)])),
+                _ => format!("{}_error: connection closed", name),
+            });
+        }
+        reply.extend_from_slice(&buf[..n]);
+        match expect {
+            None => return Ok(()),
+            Some(expect) if exact && !expect.starts_with(&reply) && !reply.starts_with(expect) => {
+                return Err(format!("{}_mismatch: {}", name, escape_banner(&reply[..reply.len().min(256)])));
+            }
+            Some(expect) if reply.windows(expect.len()).any(|w| w == expect) => return Ok(()),
+            // Replies are small; a peer that keeps talking without the expected bytes is wrong
+            Some(_) if reply.len() > 64 * 1024 => {
+                return Err(format!("unexpected_response: {}", escape_banner(&reply[..256])));
+            }
+            Some(_) => {}
+        }
+    }
+}
+
+/// --keepalive and --echo: connect once, then time `count` request/response round trips over
+/// the same connection. The samples are application-level RTTs; the connect itself is reported
+/// as `connect_ms`. Once a round trip fails the connection is in an unknown state, so the
+/// remaining attempts are counted as failed too, though `attempt_log` only has those made.
+/// With --pool a connection that comes through unbroken is kept for the next scan, which
+/// skips the connect (and so has no `connect_ms`) unless the peer dropped it meanwhile.
+async fn keepalive_probe(
+    host: &str,
+    port: u16,
+    route: &Route<'_>,
+    count: u32,
+    timeout_ms: u64,
+    dns_resolution_ms: Option<f64>,
+    opts: &ProbeOptions,
+) -> HostResult {
+    let Some(exchange) = &opts.keepalive else {
+        unreachable!("keepalive_probe without --keepalive or --echo");
+    };
+    let pool_key = (host.to_string(), port, match route {
+        Route::Direct(addr) => Some(*addr),
+        Route::Proxied(_) => None,
+    });
+    let pooled = opts.pool.as_ref().map(|pool| pool.take(&pool_key));
+    let mut source_port = None;
+
+    let mut connect_ms = None;
+    let (connected, pool_connection) = match pooled {
+        Some(Pooled::Reused(stream)) => (Ok(stream), Some("reused")),
+        pooled => {
+            source_port = opts.random_source_port.then(socket::random_source_port);
+            let start = Instant::now();
+            let connected = match timeout(Duration::from_millis(timeout_ms), connect(route, host, port, source_port, opts)).await {
+                Ok(Ok(stream)) => {
+                    connect_ms = Some(start.elapsed().as_secs_f64() * 1000.0);
+                    Ok(stream)
+                }
+                Ok(Err(e)) => Err((e.message, e.kind)),
+                Err(_) => Err(("timeout".to_string(), Some(FailureKind::Filtered))),
+            };
+            let pool_connection = match (pooled, connected.is_ok()) {
+                (Some(Pooled::Empty), true) => Some("new"),
+                (Some(Pooled::Dropped), true) => Some("reconnected"),
+                (Some(Pooled::Dropped), false) => Some("dropped"),
+                _ => None,
+            };
+            (connected, pool_connection)
+        }
+    };
+
+    let mut response_times = Vec::new();
+    let mut attempt_log = Vec::new();
+    let mut last_error = None;
+    let mut failure_kind = None;
+    let max_attempts = opts.max_attempts(count);
+    let mut attempts = count;
+    match connected {
+        Ok(mut stream) => {
+            for attempt in 1..=opts.warmup + max_attempts {
+                let payload;
+                let (send, expect, exact) = match exchange {
+                    Exchange::Request(send, expect) => (send.as_slice(), expect.as_deref(), false),
+                    Exchange::Echo => {
+                        payload = Exchange::echo_payload(attempt);
+                        (payload.as_slice(), Some(payload.as_slice()), true)
+                    }
+                };
+                let start = Instant::now();
+                let exchanged = timeout(
+                    Duration::from_millis(opts.read_timeout_ms),
+                    round_trip(&mut stream, exchange.name(), send, expect, exact),
+                )
+                .await;
+                let outcome = match exchanged {
+                    Ok(Ok(())) => Ok(start.elapsed().as_millis()),
+                    Ok(Err(e)) => Err(e),
+                    Err(_) => Err(format!("{}_timeout", exchange.name())),
+                };
+                let seq = attempt.saturating_sub(opts.warmup);
+                attempts = attempts.max(seq);
+                match outcome {
+                    Ok(_) if opts.is_warmup(attempt) => {}
+                    Ok(rtt) => {
+                        response_times.push(rtt);
+                        attempt_log.push(AttemptRecord::success(seq, rtt));
+                    }
+                    // A failed warm-up leaves the connection as unusable as a failed attempt would
+                    Err(e) => {
+                        if !opts.is_warmup(attempt) {
+                            attempt_log.push(AttemptRecord::failure(seq, &e));
+                        }
+                        last_error = Some(e);
+                        break;
+                    }
+                }
+                if opts.is_warmup(attempt) {
+                    continue;
+                }
+
+                // --stop-after-successes and --ramp-ci, as for a connect per attempt
+                if opts.enough_successes(response_times.len() as u32) {
+                    tracing::debug!(attempt, "enough successes");
+                    attempts = seq;
+                    break;
+                }
+                if seq >= count && opts.precise_enough(&response_times) {
+                    tracing::debug!(attempt, "confidence interval reached");
+                    attempts = seq;
+                    break;
+                }
+            }
+            if let Some(pool) = &opts.pool {
+                pool.put(pool_key, last_error.is_none().then_some(stream));
+            }
+        }
+        Err((error, kind)) => {
+            last_error = Some(error);
+            failure_kind = kind;
+            // Keep remembering the lost connection, so the next one still counts as a reconnect
+            if let (Some(pool), Some("dropped")) = (&opts.pool, pool_connection) {
+                pool.put(pool_key, None);
+            }
+        }
+    }
+
+    let successful = response_times.len() as u32;
+    let success_rate = successful as f64 / attempts as f64;
+    // The single connect timing out means every attempt did
+    let timeouts = if connect_ms.is_none() && failure_kind == Some(FailureKind::Filtered) { attempts } else { 0 };
+    let status = opts.status(successful, timeouts, attempts);
+
+    HostResult {
+        host: host.to_string(),
+        port: Some(port),
+        test_type: "tcp".to_string(),
+        attempts,
+        successful,
+        success_rate,
+        avg_response_time_ms: mean(&response_times.iter().map(|&t| t as f64).collect::<Vec<_>>()),
+        min_response_time_ms: response_times.iter().min().map(|&t| t as f64),
+        max_response_time_ms: response_times.iter().max().map(|&t| t as f64),
+        jitter_ms: jitter(&response_times),
+        mean_ci_percent: opts.ramp.and(mean_ci_percent(&response_times)),
+        response_times,
+        attempt_log,
+        status: status.to_string(),
+        error: if successful == 0 { last_error } else { None },
+        dscp: opts.dscp,
+        source_ports: source_port.into_iter().collect(),
+        proxy: opts.proxy.as_ref().map(|p| p.display_url()),
+        resolved_addr: match route {
+            Route::Direct(addr) if opts.record_addr => Some(ScopedIp::from(*addr)),
+            _ => None,
+        },
+        dns_resolution_ms,
+        connect_ms,
+        pool_connection: pool_connection.map(str::to_string),
+        failure_kind,
+        ..Default::default()
+    }
+}
+
+/// Mean absolute difference between consecutive samples (the RFC 3550 definition, unsmoothed)
+fn jitter(samples: &[u128]) -> Option<f64> {
+    if samples.len() < 2 {
+        return None;
+    }
+    let total: u128 = samples.windows(2).map(|w| w[0].abs_diff(w[1])).sum();
+    Some(total as f64 / (samples.len() - 1) as f64)
+}
+
+/// Half-width of the 95% confidence interval of the samples' mean, in percent of the mean. None
+/// until there are two samples; identical samples give 0, even if they're all 0 ms.
+fn mean_ci_percent(samples: &[u128]) -> Option<f64> {
+    if samples.len() < 2 {
+        return None;
+    }
+    let n = samples.len() as f64;
+    let mean = samples.iter().sum::<u128>() as f64 / n;
+    let variance = samples.iter().map(|&t| (t as f64 - mean).powi(2)).sum::<f64>() / (n - 1.0);
+    let half_width = T_95.get(samples.len() - 2).copied().unwrap_or(1.96) * (variance / n).sqrt();
+    match half_width {
+        0.0 => Some(0.0),
+        _ if mean == 0.0 => None,
+        _ => Some(half_width / mean * 100.0),
+    }
+}
+
+/// Nearest-rank percentile: the smallest sample at least `p` percent of the samples are at or below
+fn percentile(samples: &[u128], p: f64) -> Option<f64> {
+    if samples.is_empty() {
+        return None;
+    }
+    let mut sorted = samples.to_vec();
+    sorted.sort_unstable();
+    let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+    Some(sorted[rank.clamp(1, sorted.len()) - 1] as f64)
+}
+
+/// Mark results that breach --max-rtt or --jitter-alert as `partial`, noting which threshold was
+/// exceeded. `down` results are left alone. Returns whether any threshold was exceeded.
+fn apply_thresholds(results: &mut [HostResult], args: &Args) -> bool {
+    let mut exceeded = false;
+
+    for result in results.iter_mut().filter(|r| r.status != "down") {
+        // With --status-metric p95, one slow outlier in a thousand attempts doesn't count
+        let latency = args.status_metric.measure(result);
+        let mut reasons = Vec::new();
+        if let (Some(max_rtt), Some(latency)) = (args.max_rtt, latency) {
+            if latency > max_rtt {
+                reasons.push("rtt_threshold_exceeded");
+            }
+        }
+        if let (Some(limit), Some(jitter)) = (args.jitter_alert, result.jitter_ms) {
+            if result.successful as usize >= MIN_JITTER_SAMPLES && jitter > limit {
+                reasons.push("jitter_threshold_exceeded");
+            }
+        }
+
+        if !reasons.is_empty() {
+            result.status = "partial".to_string();
+            result.error = Some(reasons.join(", "));
+            exceeded = true;
+        }
+    }
+
+    exceeded
+}
+
+/// Check every result against the --assert expressions, recording and reporting the ones it
+/// doesn't satisfy. Returns whether any result failed one.
+fn check_assertions(results: &mut [HostResult], assertions: &[assertion::Assertion]) -> bool {
+    let mut failed = false;
+
+    for result in results.iter_mut() {
+        let target = match result.port {
+            Some(port) if result.host.contains(':') => format!("[{}]:{}", result.host, port),
+            Some(port) => format!("{}:{}", result.host, port),
+            None => result.host.clone(),
+        };
+        let unmet: Vec<&assertion::Assertion> = assertions.iter().filter(|a| !a.holds(result)).collect();
+        for assertion in &unmet {
+            report_warning(&format!(
+                "{} [{}] failed --assert '{}' ({})",
+                target,
+                result.test_type,
+                assertion.source(),
+                assertion.explain(result)
+            ));
+        }
+        failed |= !unmet.is_empty();
+        result.failed_assertions = unmet.iter().map(|a| a.source().to_string()).collect();
+    }
+
+    failed
+}
+
+/// --fail-threshold-percent: whether more than `limit` percent of the results are down (or
+/// filtered), reporting it when they are. Partial results still answered, so they don't count.
+fn fleet_down(results: &[HostResult], limit: f64) -> bool {
+    let down = results.iter().filter(|r| r.status == "down" || r.status == "filtered").count();
+    let percent = match results.len() {
+        0 => 0.0,
+        n => down as f64 / n as f64 * 100.0,
+    };
+    if percent <= limit {
+        return false;
+    }
+    report_warning(&format!(
+        "{} of {} results are down ({:.1}%), more than --fail-threshold-percent {}",
+        down,
+        results.len(),
+        percent,
+        limit
+    ));
+    true
+}
+
+/// --fastest-address: of the results for every address of one name, keep the one that did best,
+/// an up result with the lowest average if there is one, recording how each address did
+fn keep_fastest(mut results: Vec<HostResult>) -> Vec<HostResult> {
+    if results.len() < 2 {
+        return results;
+    }
+    let rank = |r: &HostResult| match r.status.as_str() {
+        "up" => 0,
+        "partial" => 1,
+        _ => 2,
+    };
+    // Stable, so ties keep the resolver's order
+    results.sort_by(|a, b| {
+        rank(a).cmp(&rank(b)).then_with(|| match (a.avg_response_time_ms, b.avg_response_time_ms) {
+            (Some(x), Some(y)) => x.total_cmp(&y),
+            (x, y) => y.is_some().cmp(&x.is_some()),
+        })
+    });
+    let candidates = results
+        .iter()
+        .filter_map(|r| {
+            r.resolved_addr.map(|addr| Candidate {
+                addr,
+                status: r.status.clone(),
+                avg_response_time_ms: r.avg_response_time_ms,
+            })
+        })
+        .collect();
+    results.truncate(1);
+    results[0].candidates = candidates;
+    results
+}
+
+/// `A 192.0.2.1` / `AAAA 2001:db8::1` for each distinct address a name resolved to, in the
+/// resolver's order, whether or not it was probed
+fn address_records(ips: &[IpAddr]) -> Vec<String> {
+    let mut records: Vec<String> = Vec::new();
+    for ip in ips {
+        let record = match ip {
+            IpAddr::V4(v4) => format!("A {}", v4),
+            IpAddr::V6(v6) => format!("AAAA {}", v6),
+        };
+        if !records.contains(&record) {
+            records.push(record);
+        }
+    }
+    records
+}
+
+fn mean(values: &[f64]) -> Option<f64> {
+    if values.is_empty() {
+        None
+    } else {
+        Some(values.iter().sum::<f64>() / values.len() as f64)
+    }
+}
+
+#[cfg(unix)]
+async fn uds_connect(path: &str) -> std::io::Result<()> {
+    tokio::net::UnixStream::connect(path).await.map(drop)
+}
+
+#[cfg(not(unix))]
+async fn uds_connect(_path: &str) -> std::io::Result<()> {
+    Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "Unix domain sockets are only supported on Unix"))
+}
+
+/// Connect to the socket behind a `unix:` target `count` times, timed and classified like a TCP connect
+#[tracing::instrument(level = "debug", skip(count, timeout_ms, opts))]
+async fn uds_check(host: String, count: u32, timeout_ms: u64, opts: &ProbeOptions) -> HostResult {
+    let path = uds_path(&host).unwrap_or_default();
+    let mut response_times = Vec::new();
+    let mut successful = 0;
+    let mut last_error = None;
+    let mut last_failure_kind = None;
+    let mut slowest = None;
+    let mut timeouts = 0;
+    let mut attempt_log = Vec::new();
+    let mut attempts = count;
+
+    for attempt in 1..=opts.warmup + count {
+        let attempt_timeout = opts.attempt_timeout(timeout_ms, slowest);
+        let start = Instant::now();
+        let result = timeout(attempt_timeout, uds_connect(path)).await;
+        let elapsed = start.elapsed();
+        if opts.is_warmup(attempt) {
+            continue;
+        }
+        let seq = attempt - opts.warmup;
+
+        match result {
+            Ok(Ok(())) => {
+                tracing::trace!(attempt, ?elapsed, "connected");
+                successful += 1;
+                response_times.push(elapsed.as_millis());
+                attempt_log.push(AttemptRecord::success(seq, elapsed.as_millis()));
+                slowest = slowest.max(Some(elapsed));
+            }
+            Ok(Err(e)) => {
+                tracing::debug!(attempt, error = %e, "connect failed");
+                // Nobody listening on the socket is a refusal, just as with TCP
+                let error = format!("connection_error: {}", e);
+                attempt_log.push(AttemptRecord::failure(seq, &error));
+                last_failure_kind = FailureKind::from_io(&e);
+                last_error = Some(error);
+            }
+            Err(_) => {
+                tracing::debug!(attempt, ?attempt_timeout, "connect timed out");
+                timeouts += 1;
+                attempt_log.push(AttemptRecord::failure(seq, "timeout"));
+                last_error = Some("timeout".to_string());
+                last_failure_kind = Some(FailureKind::Filtered);
+            }
+        }
+
+        // --retry-on/--retry-budget: a failure that won't be retried ends the probe and fails its
+        // remaining attempts
+        if seq < count && attempt_log.last().is_some_and(|a| !a.success) && !opts.retries(last_failure_kind) {
+            tracing::debug!(attempt, "not retrying");
+            if last_failure_kind == Some(FailureKind::Filtered) {
+                timeouts += count - seq;
+            }
+            break;
+        }
+
+        // --stop-after-successes: that many successes make the probe up, so skip the rest
+        if opts.enough_successes(successful) {
+            tracing::debug!(attempt, "enough successes");
+            attempts = seq;
+            break;
+        }
+    }
+
+    let success_rate = successful as f64 / attempts as f64;
+    let avg_response_time = if !response_times.is_empty() {
+        Some(response_times.iter().sum::<u128>() as f64 / response_times.len() as f64)
+    } else {
+        None
+    };
+    let min_response_time = response_times.iter().min().map(|&t| t as f64);
+    let max_response_time = response_times.iter().max().map(|&t| t as f64);
+
+    HostResult {
+        port: None,
+        test_type: "uds".to_string(),
+        attempts,
+        successful,
+        success_rate,
+        avg_response_time_ms: avg_response_time,
+        min_response_time_ms: min_response_time,
+        max_response_time_ms: max_response_time,
+        jitter_ms: jitter(&response_times),
+        response_times,
+        attempt_log,
+        status: opts.status(successful, timeouts, attempts).to_string(),
+        error: if successful == 0 { last_error } else { None },
+        failure_kind: if successful == 0 { last_failure_kind } else { None },
+        host,
+        ..Default::default()
+    }
+}
+
+/// Attempt QUIC handshakes with `host:port`, once per resolved address with --all-addresses and
+/// otherwise just the first
+#[tracing::instrument(level = "debug", skip(count, timeout_ms, opts))]
+async fn quic_check(host: String, port: u16, count: u32, timeout_ms: u64, opts: &ProbeOptions) -> Vec<HostResult> {
+    let resolve_start = Instant::now();
+    let dns_ms = || Some(resolve_start.elapsed().as_secs_f64() * 1000.0);
+    let dns_failure = |error: String| {
+        vec![HostResult {
+            dns_resolution_ms: dns_ms(),
+            ..HostResult::failure(&host, Some(port), "quic", count, error)
+        }]
+    };
+    let (addrs, dns_resolution_ms, addresses) = if let Ok(ip) = host.parse::<ScopedIp>() {
+        (vec![ip.socket_addr(port)], None, Vec::new())
+    } else if opts.mdns && mdns::is_mdns_host(&host) {
+        match mdns::resolve(&host, timeout_ms).await {
+            Some(ip) => (vec![SocketAddr::new(ip, port)], dns_ms(), address_records(&[ip])),
+            None => return dns_failure("mdns_resolution_failed".to_string()),
+        }
+    } else {
+        let ips = opts.resolver.lookup(&host).await.unwrap_or_default();
+        let addresses = address_records(&ips);
+        let addrs = opts.pick_addresses(ips.into_iter().map(|ip| SocketAddr::new(ip, port)).collect());
+        if addrs.is_empty() {
+            return dns_failure("dns_resolution_failed".to_string());
+        }
+        (addrs, dns_ms(), addresses)
+    };
+
+    // The certificate is checked against the name, or the bare address for literal IPs
+    let server_name = match host.parse::<ScopedIp>() {
+        Ok(ip) => ip.ip.to_string(),
+        Err(_) => host.clone(),
+    };
+    let probes = addrs
+        .iter()
+        .map(|&addr| quic_probe(&host, &server_name, addr, count, timeout_ms, dns_resolution_ms, opts));
+    let mut results = future::join_all(probes).await;
+    if opts.fastest_address {
+        results = keep_fastest(results);
+    }
+    for result in &mut results {
+        result.addresses = addresses.clone();
+    }
+    results
+}
+
+async fn quic_probe(
+    host: &str,
+    server_name: &str,
+    addr: SocketAddr,
+    count: u32,
+    timeout_ms: u64,
+    dns_resolution_ms: Option<f64>,
+    opts: &ProbeOptions,
+) -> HostResult {
+    let mut response_times = Vec::new();
+    let mut successful = 0;
+    let mut last_error = None;
+    let mut slowest = None;
+    let mut timeouts = 0;
+    let mut attempt_log = Vec::new();
+    let mut attempts = count;
+    let mut version = None;
+    // A server that answered, even just to refuse the handshake, makes the port reachable
+    let mut answered = false;
+
+    for attempt in 1..=opts.warmup + count {
+        let attempt_timeout = opts.attempt_timeout(timeout_ms, slowest);
+        let start = Instant::now();
+        let result = timeout(attempt_timeout, quic::handshake(addr, server_name)).await;
+        let elapsed = start.elapsed();
+        if opts.is_warmup(attempt) {
+            continue;
+        }
+        let seq = attempt - opts.warmup;
+
+        let failure_kind = match result {
+            Ok(Ok(negotiated)) => {
+                tracing::trace!(attempt, ?elapsed, negotiated, "handshake completed");
+                version = Some(negotiated);
+                successful += 1;
+                answered = true;
+                response_times.push(elapsed.as_millis());
+                attempt_log.push(AttemptRecord::success(seq, elapsed.as_millis()));
+                slowest = slowest.max(Some(elapsed));
+                // --stop-after-successes: that many successes make the probe up, so skip the rest
+                if opts.enough_successes(successful) {
+                    tracing::debug!(attempt, "enough successes");
+                    attempts = seq;
+                    break;
+                }
+                continue;
+            }
+            Ok(Err(quic::Failure::Rejected(error))) => {
+                tracing::debug!(attempt, %error, "handshake rejected");
+                answered = true;
+                attempt_log.push(AttemptRecord::failure(seq, &error));
+                last_error = Some(error);
+                None
+            }
+            Ok(Err(quic::Failure::Local(error))) => {
+                tracing::debug!(attempt, %error, "endpoint setup failed");
+                attempt_log.push(AttemptRecord::failure(seq, &error));
+                last_error = Some(error);
+                None
+            }
+            Ok(Err(quic::Failure::NoResponse)) | Err(_) => {
+                tracing::debug!(attempt, ?attempt_timeout, "handshake timed out");
+                timeouts += 1;
+                attempt_log.push(AttemptRecord::failure(seq, "timeout"));
+                last_error = Some("timeout".to_string());
+                Some(FailureKind::Filtered)
+            }
+        };
+
+        // --retry-on/--retry-budget: a failure that won't be retried ends the probe and fails its
+        // remaining attempts
+        if seq < count && !opts.retries(failure_kind) {
+            tracing::debug!(attempt, "not retrying");
+            if failure_kind == Some(FailureKind::Filtered) {
+                timeouts += count - seq;
+            }
+            break;
+        }
+    }
+
+    let success_rate = successful as f64 / attempts as f64;
+    let min_response_time = response_times.iter().min().map(|&t| t as f64);
+    let max_response_time = response_times.iter().max().map(|&t| t as f64);
+    let status = match opts.status(successful, timeouts, attempts) {
+        // The UDP port is open; it just didn't complete a handshake
+        "down" if answered => "partial",
+        status => status,
+    };
+
+    HostResult {
+        host: host.to_string(),
+        port: Some(addr.port()),
+        test_type: "quic".to_string(),
+        attempts,
+        successful,
+        success_rate,
+        avg_response_time_ms: mean(&response_times.iter().map(|&t| t as f64).collect::<Vec<_>>()),
+        min_response_time_ms: min_response_time,
+        max_response_time_ms: max_response_time,
+        jitter_ms: jitter(&response_times),
+        response_times,
+        attempt_log,
+        status: status.to_string(),
+        error: if successful == 0 { last_error } else { None },
+        quic_version: version,
+        resolved_addr: opts.record_addr.then_some(addr.into()),
+        dns_resolution_ms,
+        ..Default::default()
+    }
+}
+
+/// Ping `host`, once per resolved address with --all-addresses and otherwise just the first
+#[tracing::instrument(level = "debug", skip(count, timeout_ms, opts))]
+async fn icmp_ping(host: String, count: u32, timeout_ms: u64, opts: &ProbeOptions) -> Vec<HostResult> {
+    // Resolve hostname to IP
+    let resolve_start = Instant::now();
+    let dns_ms = || Some(resolve_start.elapsed().as_secs_f64() * 1000.0);
+    let dns_failure = |error: String| {
+        vec![HostResult {
+            dns_resolution_ms: dns_ms(),
+            ..HostResult::failure(&host, None, "icmp", count, error)
+        }]
+    };
+    let (ip_addrs, dns_resolution_ms, addresses): (Vec<ScopedIp>, _, _) = match host.parse::<ScopedIp>() {
+        Ok(ip) => (vec![ip], None, Vec::new()),
+        Err(_) if opts.mdns && mdns::is_mdns_host(&host) => match mdns::resolve(&host, timeout_ms).await {
+            Some(ip) => (vec![ip.into()], dns_ms(), address_records(&[ip])),
+            None => return dns_failure("mdns_resolution_failed".to_string()),
+        },
+        Err(_) => {
+            // Try to resolve hostname
+            match opts.resolver.lookup(&host).await {
+                Ok(mut ips) => {
+                    let addresses = address_records(&ips);
+                    // On an IPv6-only (or IPv4-only) host, ping an address of the family it can
+                    // reach rather than whichever came first
+                    if !ips.iter().all(|ip| ip.is_ipv4() == ips[0].is_ipv4()) {
+                        ips.sort_by_key(|&ip| !socket::has_route(ip));
+                    }
+                    let addrs = opts.pick_addresses(ips.into_iter().map(|ip| SocketAddr::new(ip, 0)).collect());
+                    if addrs.is_empty() {
+                        return dns_failure("dns_resolution_failed".to_string());
+                    }
+                    (addrs.into_iter().map(ScopedIp::from).collect(), dns_ms(), addresses)
+                }
+                Err(e) => {
+                    return dns_failure(format!("dns_error: {}", e));
+                }
+            }
+        }
+    };
+
+    let host = &host;
+    let pings = ip_addrs.iter().map(|&ip| async move {
+        match ip.ip {
+            IpAddr::V4(v4) if opts.icmp_timestamp => timestamp_ping(host, v4, count, timeout_ms, dns_resolution_ms, opts).await,
+            IpAddr::V6(_) if opts.icmp_timestamp => {
+                let failure = HostResult::failure(host, None, "icmp", count, "icmp_timestamp_ipv4_only".to_string());
+                HostResult { resolved_addr: opts.record_addr.then_some(ip), dns_resolution_ms, ..failure }
+            }
+            _ => ping_addr(host, ip, count, timeout_ms, dns_resolution_ms, opts).await,
+        }
+    });
+    let mut results = future::join_all(pings).await;
+    if opts.fastest_address {
+        results = keep_fastest(results);
+    }
+    for result in &mut results {
+        result.addresses = addresses.clone();
+    }
+    results
+}
+
+/// Settings for an ICMP echo client of `ip`'s family
+fn icmp_config(ip: IpAddr) -> Config {
+    match ip {
+        IpAddr::V4(_) => Config::default(),
+        IpAddr::V6(_) => Config::builder().kind(ICMP::V6).build(),
+    }
+}
+
+async fn ping_addr(
+    host: &str,
+    ip_addr: ScopedIp,
+    count: u32,
+    timeout_ms: u64,
+    dns_resolution_ms: Option<f64>,
+    opts: &ProbeOptions,
+) -> HostResult {
+    let mut response_times = Vec::new();
+    let (mut send_epoch_us, mut recv_epoch_us) = (Vec::new(), Vec::new());
+    let mut successful = 0;
+    let mut last_error = None;
+
+    let config = icmp_config(ip_addr.ip);
+    let client = match Client::new(&config) {
+        Ok(client) => client,
+        Err(e) => {
+            let hint = match ip_addr.ip {
+                _ if !socket::is_family_unsupported(&e) => "try running as root/admin",
+                IpAddr::V4(_) => "this host has no IPv4",
+                IpAddr::V6(_) => "this host has no IPv6",
+            };
+            return HostResult::failure(host, None, "icmp", count, format!("icmp_client_error: {} ({})", e, hint));
+        }
+    };
+
+    if let Some(dscp) = opts.dscp {
+        // The option has to match the socket's family, not the target's
+        if let Err(e) = socket::set_icmp_dscp(&client, matches!(config.kind, ICMP::V4), dscp) {
+            return HostResult::failure(host, None, "icmp", count, format!("dscp_error: {}", e));
+        }
+    }
+    if let Some(mark) = opts.fwmark {
+        if let Err(e) = socket::set_icmp_mark(&client, mark) {
+            return HostResult::failure(host, None, "icmp", count, format!("fwmark_error: {}", e));
+        }
+    }
+    if opts.ping_df {
+        if let Err(e) = socket::set_icmp_dont_fragment(&client, matches!(config.kind, ICMP::V4)) {
+            return HostResult::failure(host, None, "icmp", count, format!("df_error: {}", e));
+        }
+    }
+    let payload = vec![0; opts.ping_size];
+    let mut attempt_log = Vec::new();
+    let max_attempts = opts.max_attempts(count);
+    let mut attempts = count;
+    let mut last_failure_kind = None;
+
+    let icmp_id = opts.icmp_ids.lease(ip_addr);
+    let mut pinger = client.pinger(ip_addr.ip, PingIdentifier(icmp_id.id)).await;
+    tracing::debug!(%ip_addr, icmp_id = icmp_id.id, "pinging");
+    if ip_addr.scope_id != 0 {
+        pinger.scope_id(ip_addr.scope_id);
+    }
+    let mut slowest = None;
+    // The highest TTL seen, i.e. the reply that took the shortest way back
+    let mut reply_ttl = None;
+
+    // Pace sends like ping(8): one echo request every interval, measured from the first send
+    let interval = Duration::from_millis(opts.ping_interval_ms);
+    let first_send = tokio::time::Instant::now();
+
+    // Warm-up echoes are paced and numbered like the rest, just not counted
+    for i in 1..=opts.warmup + max_attempts {
+        if i > 1 {
+            tokio::time::sleep_until(first_send + interval * (i - 1)).await;
+        }
+
+        pinger.timeout(opts.attempt_timeout(timeout_ms, slowest));
+        let sent = epoch_us();
+        let seq = opts.icmp_sequences.next(ip_addr);
+        let reply = pinger.ping(PingSequence(seq), &payload).await;
+        if opts.is_warmup(i) {
+            tracing::trace!(%ip_addr, seq, ok = reply.is_ok(), "warm-up echo");
+            continue;
+        }
+        let attempt = i - opts.warmup;
+        // --ramp-ci, as for TCP
+        attempts = attempts.max(attempt);
+        match reply {
+            Ok((IcmpPacket::V4(packet), duration)) => {
+                successful += 1;
+                reply_ttl = reply_ttl.max(packet.get_ttl());
+                response_times.push(duration.as_millis());
+                attempt_log.push(AttemptRecord::success(attempt, duration.as_millis()));
+                send_epoch_us.push(sent);
+                recv_epoch_us.push(epoch_us());
+                slowest = slowest.max(Some(duration));
+            }
+            Ok((IcmpPacket::V6(_packet), duration)) => {
+                successful += 1;
+                response_times.push(duration.as_millis());
+                attempt_log.push(AttemptRecord::success(attempt, duration.as_millis()));
+                send_epoch_us.push(sent);
+                recv_epoch_us.push(epoch_us());
+                slowest = slowest.max(Some(duration));
+            }
+            // With the don't-fragment bit set the kernel refuses packets over the known path MTU
+            Err(SurgeError::IOError(e)) if socket::is_message_too_big(&e) => {
+                tracing::debug!(%ip_addr, seq, error = %e, "echo request too big for the path");
+                let size = opts.ping_size as u32 + echo_headers(ip_addr.ip);
+                let error = format!("fragmentation_needed: {} byte packet exceeds the path MTU", size);
+                attempt_log.push(AttemptRecord::failure(attempt, &error));
+                last_error = Some(error);
+                last_failure_kind = None;
+            }
+            Err(e) => {
+                tracing::debug!(%ip_addr, seq, error = %e, "no echo reply");
+                let error = format!("ping_error: {}", e);
+                attempt_log.push(AttemptRecord::failure(attempt, &error));
+                last_error = Some(error);
+                // Only an unanswered echo is a timeout; anything else failed to send
+                last_failure_kind = matches!(e, SurgeError::Timeout { .. }).then_some(FailureKind::Filtered);
+            }
+        }
+
+        // --retry-on/--retry-budget, as for TCP
+        if attempt < max_attempts && attempt_log.last().is_some_and(|a| !a.success) && !opts.retries(last_failure_kind) {
+            tracing::debug!(%ip_addr, seq, "not retrying");
+            attempts = count.max(attempt);
+            break;
+        }
+
+        // --stop-after-successes: that many successes make the probe up, so skip the rest
+        if opts.enough_successes(successful) {
+            tracing::debug!(%ip_addr, seq, "enough successes");
+            attempts = attempt;
+            break;
+        }
+
+        // --ramp-ci, as for TCP
+        if attempt >= count && opts.precise_enough(&response_times) {
+            tracing::debug!(%ip_addr, seq, "confidence interval reached");
+            attempts = attempt;
+            break;
+        }
+    }
+
+    let path_mtu = if opts.mtu_discover && successful > 0 {
+        discover_mtu(&client, &mut pinger, matches!(config.kind, ICMP::V4), ip_addr, &opts.icmp_sequences).await
+    } else {
+        None
+    };
+
+    let success_rate = successful as f64 / attempts as f64;
+    let avg_response_time = if !response_times.is_empty() {
+        Some(response_times.iter().sum::<u128>() as f64 / response_times.len() as f64)
+    } else {
+        None
+    };
+    let min_response_time = response_times.iter().min().map(|&t| t as f64);
+    let max_response_time = response_times.iter().max().map(|&t| t as f64);
+
+    let (status, error) = opts.icmp_status(successful, attempts, last_error);
+
+    HostResult {
+        host: host.to_string(),
+        port: None,
+        test_type: "icmp".to_string(),
+        attempts,
+        successful,
+        success_rate,
+        avg_response_time_ms: avg_response_time,
+        min_response_time_ms: min_response_time,
+        max_response_time_ms: max_response_time,
+        jitter_ms: jitter(&response_times),
+        mean_ci_percent: opts.ramp.and(mean_ci_percent(&response_times)),
+        response_times,
+        attempt_log,
+        status: status.to_string(),
+        error,
+        dscp: opts.dscp,
+        resolved_addr: opts.record_addr.then_some(ip_addr),
+        dns_resolution_ms,
+        send_epoch_us,
+        recv_epoch_us,
+        path_mtu,
+        hops_estimate: reply_ttl.map(hops_from_ttl),
+        ..Default::default()
+    }
+}
+
+/// --icmp-timestamp: ping `ip_addr` with timestamp requests, paced, numbered and retried like
+/// echo requests. Each reply's clocks are kept alongside its round trip.
+async fn timestamp_ping(
+    host: &str,
+    ip_addr: Ipv4Addr,
+    count: u32,
+    timeout_ms: u64,
+    dns_resolution_ms: Option<f64>,
+    opts: &ProbeOptions,
+) -> HostResult {
+    let scoped = ScopedIp::from(IpAddr::V4(ip_addr));
+    let mut response_times = Vec::new();
+    let mut icmp_timestamps = Vec::new();
+    let mut successful = 0;
+    let mut last_error = None;
+    let mut last_failure_kind = None;
+    let mut attempt_log = Vec::new();
+    let mut attempts = count;
+    let mut slowest = None;
+
+    let icmp_id = opts.icmp_ids.lease(scoped);
+    tracing::debug!(%ip_addr, icmp_id = icmp_id.id, "sending timestamp requests");
+    let interval = Duration::from_millis(opts.ping_interval_ms);
+    let first_send = tokio::time::Instant::now();
+
+    for i in 1..=opts.warmup + count {
+        if i > 1 {
+            tokio::time::sleep_until(first_send + interval * (i - 1)).await;
+        }
+
+        let attempt_timeout = opts.attempt_timeout(timeout_ms, slowest);
+        let seq = opts.icmp_sequences.next(scoped);
+        let reply = icmp_timestamp::probe(ip_addr, icmp_id.id, seq, attempt_timeout, opts.dscp, opts.fwmark).await;
+        if opts.is_warmup(i) {
+            tracing::trace!(%ip_addr, seq, ok = matches!(reply, Ok(Some(_))), "warm-up timestamp request");
+            continue;
+        }
+        let attempt = i - opts.warmup;
+        match reply {
+            Ok(Some((timestamps, duration))) => {
+                successful += 1;
+                response_times.push(duration.as_millis());
+                attempt_log.push(AttemptRecord::success(attempt, duration.as_millis()));
+                icmp_timestamps.push(timestamps);
+                slowest = slowest.max(Some(duration));
+            }
+            Ok(None) => {
+                tracing::debug!(%ip_addr, seq, "no timestamp reply");
+                attempt_log.push(AttemptRecord::failure(attempt, "timeout"));
+                last_error = Some("timeout".to_string());
+                last_failure_kind = Some(FailureKind::Filtered);
+            }
+            Err(e) => {
+                tracing::debug!(%ip_addr, seq, error = %e, "timestamp request failed");
+                let error = format!("icmp_timestamp_error: {}", e);
+                attempt_log.push(AttemptRecord::failure(attempt, &error));
+                last_error = Some(error);
+                last_failure_kind = None;
+            }
+        }
+
+        // --retry-on/--retry-budget, as for TCP
+        if attempt < count && attempt_log.last().is_some_and(|a| !a.success) && !opts.retries(last_failure_kind) {
+            tracing::debug!(%ip_addr, seq, "not retrying");
+            break;
+        }
+
+        // --stop-after-successes: that many successes make the probe up, so skip the rest
+        if opts.enough_successes(successful) {
+            tracing::debug!(%ip_addr, seq, "enough successes");
+            attempts = attempt;
+            break;
+        }
+    }
+
+    let (status, error) = opts.icmp_status(successful, attempts, last_error);
+    let offsets: Vec<f64> = icmp_timestamps.iter().filter_map(|t| t.clock_offset_ms()).collect();
+    let samples: Vec<f64> = response_times.iter().map(|&t| t as f64).collect();
+
+    HostResult {
+        host: host.to_string(),
+        port: None,
+        test_type: "icmp".to_string(),
+        attempts,
+        successful,
+        success_rate: successful as f64 / attempts as f64,
+        avg_response_time_ms: mean(&samples),
+        min_response_time_ms: response_times.iter().min().map(|&t| t as f64),
+        max_response_time_ms: response_times.iter().max().map(|&t| t as f64),
+        jitter_ms: jitter(&response_times),
+        response_times,
+        attempt_log,
+        status: status.to_string(),
+        error,
+        dscp: opts.dscp,
+        resolved_addr: opts.record_addr.then_some(scoped),
+        dns_resolution_ms,
+        icmp_timestamps,
+        clock_offset_ms: mean(&offsets),
+        ..Default::default()
+    }
+}
+
+/// Routers between us and a host whose reply arrived with `ttl`, assuming the host started from
+/// the nearest of the usual initial TTLs above it: 64 (Linux, macOS), 128 (Windows) or 255
+/// (many routers and network OSes)
+fn hops_from_ttl(ttl: u8) -> u8 {
+    match ttl {
+        0..=64 => 64 - ttl,
+        65..=128 => 128 - ttl,
+        _ => 255 - ttl,
+    }
+}
+
+/// IP header plus the 8-byte ICMP echo header
+fn echo_headers(ip_addr: IpAddr) -> u32 {
+    if ip_addr.is_ipv4() {
+        28
+    } else {
+        48
+    }
+}
+
+/// Largest MTU --mtu-discover will report, enough for jumbo frames
+const MAX_MTU: u32 = 9000;
+
+/// Binary-search the largest echo request that reaches `ip_addr` with fragmentation disabled.
+/// Sizes over the local or already-learned path MTU fail on send; larger ones that a router
+/// can't forward get a fragmentation-needed error instead of a reply, and time out.
+async fn discover_mtu(
+    client: &Client,
+    pinger: &mut surge_ping::Pinger,
+    socket_v4: bool,
+    ip_addr: ScopedIp,
+    sequences: &IcmpSequences,
+) -> Option<u32> {
+    if let Err(e) = socket::set_icmp_dont_fragment(client, socket_v4) {
+        tracing::debug!(%ip_addr, error = %e, "can't set don't-fragment");
+        return None;
+    }
+
+    let headers = echo_headers(ip_addr.ip);
+    // An empty echo request already got through
+    let (mut fits, mut too_big) = (headers, MAX_MTU + 1);
+    while too_big - fits > 1 {
+        let size = fits + (too_big - fits) / 2;
+        let payload = vec![0; (size - headers) as usize];
+        match pinger.ping(PingSequence(sequences.next(ip_addr)), &payload).await {
+            Ok(_) => fits = size,
+            Err(e) => {
+                tracing::trace!(%ip_addr, size, error = %e, "MTU probe failed");
+                too_big = size;
+            }
+        }
+    }
+    tracing::debug!(%ip_addr, mtu = fits, "path MTU found");
+    Some(fits)
+}
+
+/// ARP `host` (IPv4 only, and only on a directly connected network), once per resolved address
+/// with --all-addresses. Two devices answering for one address get a duplicate-address warning.
+#[tracing::instrument(level = "debug", skip(count, timeout_ms, opts))]
+async fn arp_ping(host: String, count: u32, timeout_ms: u64, opts: &ProbeOptions) -> Vec<HostResult> {
+    let resolve_start = Instant::now();
+    let (addrs, dns_resolution_ms, addresses) = match host.parse::<IpAddr>() {
+        Ok(ip) => (vec![ip], None, Vec::new()),
+        Err(_) => match opts.resolver.lookup(&host).await {
+            Ok(ips) => {
+                let addresses = address_records(&ips);
+                let v4 = ips.into_iter().filter(IpAddr::is_ipv4).map(|ip| SocketAddr::new(ip, 0)).collect();
+                let addrs: Vec<IpAddr> = opts.pick_addresses(v4).iter().map(|a| a.ip()).collect();
+                (addrs, Some(resolve_start.elapsed().as_secs_f64() * 1000.0), addresses)
+            }
+            Err(e) => return vec![HostResult::failure(&host, None, "arp", count, format!("dns_error: {}", e))],
+        },
+    };
+
+    if addrs.is_empty() {
+        let failure = HostResult::failure(&host, None, "arp", count, "arp_error: no IPv4 address".to_string());
+        return vec![HostResult { addresses, ..failure }];
+    }
+
+    let host = host.as_str();
+    let probes = addrs.iter().map(|&ip| async move {
+        let IpAddr::V4(v4) = ip else {
+            return HostResult::failure(host, None, "arp", count, "arp_error: ARP is IPv4-only".to_string());
+        };
+        let mut outcome = match arp::probe(v4, opts.warmup + count, Duration::from_millis(timeout_ms)).await {
+            Ok(outcome) => outcome,
+            Err(e) => return HostResult::failure(host, None, "arp", count, format!("arp_error: {}", e)),
+        };
+        outcome.rtts.drain(..opts.warmup as usize);
+
+        let mac_addresses: Vec<String> = outcome.macs.iter().map(arp::format_mac).collect();
+        if mac_addresses.len() > 1 {
+            report_warning(&format!("duplicate address: {} answered ARP from {}", ip, mac_addresses.join(", ")));
+        }
+
+        let response_times: Vec<u128> = outcome.rtts.iter().flatten().map(|rtt| rtt.as_millis()).collect();
+        let attempt_log = (1..)
+            .zip(&outcome.rtts)
+            .map(|(seq, rtt)| match rtt {
+                Some(rtt) => AttemptRecord::success(seq, rtt.as_millis()),
+                None => AttemptRecord::failure(seq, "arp_timeout"),
+            })
+            .collect();
+        let successful = response_times.len() as u32;
+        let samples: Vec<f64> = outcome.rtts.iter().flatten().map(|rtt| rtt.as_secs_f64() * 1000.0).collect();
+        // ARP has no notion of a timeout distinct from no answer, so never `filtered`
+        let status = opts.status(successful, 0, count);
+        HostResult {
+            host: host.to_string(),
+            port: None,
+            test_type: "arp".to_string(),
+            attempts: count,
+            successful,
+            success_rate: successful as f64 / count as f64,
+            avg_response_time_ms: mean(&samples),
+            min_response_time_ms: samples.iter().copied().reduce(f64::min),
+            max_response_time_ms: samples.iter().copied().reduce(f64::max),
+            jitter_ms: jitter(&response_times),
+            response_times,
+            attempt_log,
+            status: status.to_string(),
+            error: (successful == 0).then(|| "arp_timeout".to_string()),
+            mac_addresses,
+            resolved_addr: opts.record_addr.then_some(ip.into()),
+            dns_resolution_ms,
+            ..Default::default()
+        }
+    });
+    let mut results = future::join_all(probes).await;
+    if opts.fastest_address {
+        results = keep_fastest(results);
+    }
+    for result in &mut results {
+        result.addresses = addresses.clone();
+    }
+    results
+}
+
+/// Wall-clock time in microseconds since the Unix epoch, for correlating with other hosts' clocks
+fn epoch_us() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_micros() as u64)
+}
+
+fn format_timestamp(utc: bool) -> String {
+    format_time(SystemTime::now(), utc)
+}
+
+fn format_time(time: SystemTime, utc: bool) -> String {
+    if utc {
+        DateTime::<Utc>::from(time).to_rfc3339_opts(SecondsFormat::Secs, true)
+    } else {
+        DateTime::<Local>::from(time).to_rfc3339_opts(SecondsFormat::Secs, false)
+    }
+}
+
+fn print_result(result: &HostResult, args: &Args, indent: &str) {
+    let status_icon = match (result.status.as_str(), result.failure_kind) {
+        ("up", _) => icons::get("up"),
+        ("down", Some(kind)) => kind.icon(),
+        ("down", None) => icons::get("down"),
+        ("filtered", _) => FailureKind::Filtered.icon(),
+        ("partial", _) => icons::get("partial"),
+        _ => icons::get("unknown"),
+    };
+    let (arrow, detail_mark) = (icons::get("arrow"), icons::get("detail"));
+
+    let host = result.label_host();
+    let mut host_port = if let Some(port) = result.port {
+        if host.contains(':') {
+            format!("[{}]:{}", host.blue(), port.to_string().yellow())
+        } else {
+            format!("{}:{}", host.blue(), port.to_string().yellow())
+        }
+    } else {
+        format!("{} ({})", host.blue(), result.test_type.to_uppercase())
+    };
+    if let Some(addr) = result.label_addr() {
+        host_port.push_str(&format!(" @ {}", addr).dimmed().to_string());
+    }
+    let geo: Vec<String> = [
+        result.country.clone(),
+        match (result.asn, &result.as_org) {
+            (Some(asn), Some(org)) => Some(format!("AS{} {}", asn, org)),
+            (Some(asn), None) => Some(format!("AS{}", asn)),
+            (None, org) => org.clone(),
+        },
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+    if !geo.is_empty() {
+        host_port.push_str(&format!(" [{}]", geo.join(", ")).dimmed().to_string());
+    }
+
+    let mut latency_suffix = String::new();
+    for (label, ms) in [("p50", result.p50_ms), ("p95", result.p95_ms), ("p99", result.p99_ms), ("ewma", result.ewma_response_time_ms)] {
+        if let Some(ms) = ms {
+            latency_suffix.push_str(&format!(", {} {} {}", label, args.unit.format(ms, 1), args.unit.label()));
+        }
+    }
+    // Under -v the probe ID is shown with the test type and on every detail line
+    let (type_label, probe_tag) = match (args.verbose, result.probe_id) {
+        (true, Some(id)) => (format!("{} #{}", result.test_type, id), format!("#{} ", id)),
+        _ => (result.test_type.clone(), String::new()),
+    };
+
+    // --quiet-errors keeps the failure kind, which is short, but not the message
+    let failed = result.status == "down" || result.status == "filtered";
+    let error = result.error.as_ref().filter(|_| !(failed && args.quiet_errors.is_some()));
+    let mut error_suffix = match (result.failure_kind, error) {
+        (Some(kind), Some(error)) => format!(" {} ({})", kind.as_str().red().bold(), error.red()),
+        (Some(kind), None) => format!(" {}", kind.as_str().red().bold()),
+        (_, Some(error)) => format!(" ({})", error.red()),
+        _ => String::new(),
+    };
+    if result.rescans > 0 {
+        let passes = if result.rescans == 1 { "rescan" } else { "rescans" };
+        error_suffix.push_str(&format!(" (after {} {})", result.rescans, passes).dimmed().to_string());
+    }
+
+    if let (false, Some(min), Some(avg), Some(max)) = (
+        args.no_minmax,
+        result.min_response_time_ms,
+        result.avg_response_time_ms,
+        result.max_response_time_ms,
+    ) {
+        println!(
+            "{}{} {} {} {}/{} ({}/{}/{} {}{}) [{}]{}",
+            indent,
+            status_icon,
+            host_port,
+            arrow,
+            result.successful,
+            result.attempts,
+            args.unit.format(min, 1),
+            args.color_latency(avg, 1),
+            args.unit.format(max, 1),
+            args.unit.label(),
+            latency_suffix,
+            type_label.cyan(),
+            error_suffix
+        );
+    } else if let Some(avg_time) = result.avg_response_time_ms {
+        println!(
+            "{}{} {} {} {}/{} successful (Avg: {} {}{}) [{}]{}",
+            indent,
+            status_icon,
+            host_port,
+            arrow,
+            result.successful,
+            result.attempts,
+            args.color_latency(avg_time, 2),
+            args.unit.label(),
+            latency_suffix,
+            type_label.cyan(),
+            error_suffix
+        );
+    } else {
+        println!(
+            "{}{} {} {} {}/{} successful [{}]{}",
+            indent,
+            status_icon,
+            host_port,
+            arrow,
+            result.successful,
+            result.attempts,
+            type_label.cyan(),
+            error_suffix
+        );
+    }
+
+    if let Some(banner) = &result.banner {
+        println!("{}    {} {}", indent, detail_mark, banner.dimmed());
+    }
+
+    if let Some(hash) = &result.content_sha256 {
+        println!("{}    {} {}", indent, detail_mark, format!("{}content sha256 {}", probe_tag, hash).dimmed());
+    }
+
+    match result.mac_addresses.as_slice() {
+        [] => {}
+        [mac] => println!("{}    {} {}", indent, detail_mark, format!("{}MAC {}", probe_tag, mac).dimmed()),
+        macs => println!("{}    {} {}", indent, detail_mark, format!("{}{} duplicate address: answered by {}", probe_tag, icons::get("warning"), macs.join(", ")).yellow()),
+    }
+
+    if let Some(mismatch) = result.peer_mismatch {
+        let detail = format!("{}{} dialed {} but connected to {}", probe_tag, icons::get("warning"), mismatch.dialed, mismatch.connected);
+        println!("{}    {} {}", indent, detail_mark, detail.yellow());
+    }
+
+    if let Some(ci) = result.mean_ci_percent {
+        println!(
+            "{}    {} {}",
+            indent,
+            detail_mark,
+            format!("{}mean {}{:.1}% at 95% confidence after {} attempts", probe_tag, icons::get("plus-minus"), ci, result.attempts).dimmed()
+        );
+    }
+
+    if let Some(mtu) = result.path_mtu {
+        println!("{}    {} {}", indent, detail_mark, format!("{}path MTU {}", probe_tag, mtu).dimmed());
+    }
+
+    if let (true, Some(hops)) = (args.verbose, result.hops_estimate) {
+        let hops = if hops == 1 { "1 hop".to_string() } else { format!("{} hops", hops) };
+        println!("{}    {} {}", indent, detail_mark, format!("{}~{} away (from the reply TTL)", probe_tag, hops).dimmed());
+    }
+
+    if let Some(offset) = result.clock_offset_ms {
+        let offset = format!("{}{} {}", if offset < 0.0 { "" } else { "+" }, args.unit.format(offset, 1), args.unit.label());
+        println!("{}    {} {}", indent, detail_mark, format!("{}remote clock {}", probe_tag, offset).dimmed());
+    }
+
+    if let Some(version) = result.quic_version {
+        println!("{}    {} {}", indent, detail_mark, format!("{}QUIC {}", probe_tag, quic::version_name(version)).dimmed());
+    }
+
+    if let Some(mbps) = result.throughput_mbps {
+        println!("{}    {} {}", indent, detail_mark, format!("{}throughput {:.1} MB/s", probe_tag, mbps).dimmed());
+    }
+
+    if let (true, Some(proxy)) = (args.verbose, &result.proxy) {
+        println!("{}    {} {}", indent, detail_mark, format!("{}via {}", probe_tag, proxy).dimmed());
+    }
+
+    if args.verbose && !result.candidates.is_empty() {
+        let raced: Vec<String> = result
+            .candidates
+            .iter()
+            .map(|c| match c.avg_response_time_ms {
+                Some(avg) => format!("{} {} {}", c.addr, args.unit.format(avg, 1), args.unit.label()),
+                None => format!("{} {}", c.addr, c.status),
+            })
+            .collect();
+        println!("{}    {} {}", indent, detail_mark, format!("{}fastest of {}", probe_tag, raced.join(", ")).dimmed());
+    }
+
+    if args.verbose && !result.source_ports.is_empty() {
+        let ports: Vec<String> = result.source_ports.iter().map(|p| p.to_string()).collect();
+        println!("{}    {} {}", indent, detail_mark, format!("{}source ports {}", probe_tag, ports.join(", ")).dimmed());
+    }
+
+    if args.verbose {
+        let timings = [
+            ("dns", result.dns_resolution_ms),
+            ("connect", result.connect_ms),
+            ("tls", result.tls_ms),
+            ("ttfb", result.ttfb_ms),
+        ];
+        let mut breakdown: Vec<String> = timings
+            .iter()
+            .filter_map(|(label, ms)| ms.map(|ms| format!("{} {} {}", label, args.unit.format(ms, 1), args.unit.label())))
+            .collect();
+        if let Some(status) = result.http_status {
+            breakdown.push(format!("HTTP {}", status));
+        }
+        if let Some(used) = result.tfo_used {
+            breakdown.push(if used { "TFO".to_string() } else { "no TFO".to_string() });
+        }
+        if let Some(pooled) = &result.pool_connection {
+            breakdown.push(match pooled.as_str() {
+                "reconnected" => "reconnected".to_string(),
+                "dropped" => "connection dropped".to_string(),
+                pooled => format!("{} connection", pooled),
+            });
+        }
+        if !breakdown.is_empty() {
+            println!("{}    {} {}", indent, detail_mark, format!("{}{}", probe_tag, breakdown.join(&format!(" {} ", icons::get("separator")))).dimmed());
+        }
+    }
+}
+
+fn print_human_readable(results: &[HostResult], timestamp: &str, args: &Args) {
+    // A template means exactly the lines asked for, with no header to strip
+    if let Some(template) = &args.format {
+        for result in results {
+            println!("{}", template.render(result));
+        }
+        return;
+    }
+
+    let tags = if args.tags.is_empty() { String::new() } else { format!(" {}", format_tags(&args.tags).dimmed()) };
+    println!("\n{} {}Summary{}", format!("[{}]", timestamp).dimmed(), icons::lead("📊"), tags);
+    println!("{}", icons::rule().dimmed());
+
+    if args.by_host {
+        print_by_host(results, args);
+    } else if args.table {
+        table::print_table(results, args.verbose);
+    } else if args.compare_hosts {
+        print_leaderboard(results, args);
+    } else {
+        let hidden = |r: &&HostResult| args.quiet_errors == Some(QuietErrors::Lines) && (r.status == "down" || r.status == "filtered");
+        for result in results.iter().filter(|r| !hidden(r)) {
+            print_result(result, args, "");
+        }
+        match results.iter().filter(hidden).count() {
+            0 => {}
+            n => println!("{}", format!("({} down not shown)", n).dimmed()),
+        }
+    }
+
+    if args.compare_protocols {
+        print_protocol_comparison(results, args);
+    }
+    // The leaderboard already ranks every target
+    if args.compare_hosts {
+        return;
+    }
+
+    if !args.quiet {
+        print_extremes(results, args);
+    }
+    print_totals(results);
+}
+
+/// The human-readable summary of a scan, with its anomalies, histogram and drift
+fn print_scan(scan: &ScanResult, args: &Args) {
+    print_human_readable(&scan.results, &scan.scan_timestamp, args);
+    if args.format.is_none() {
+        anomaly::print_anomalies(&scan.anomalies);
+    }
+    if let Some(histogram) = &scan.histogram {
+        histogram::print_histogram(histogram);
+    }
+    if let Some(drift) = &scan.drift {
+        manifest::print_drift(drift);
+    }
+}
+
+/// --only-changed: the first scan in full, then only the results `changes` finds different
+/// from the scan before, with a `no changes` line every --heartbeat quiet scans
+fn print_changes(scan: &ScanResult, changes: &mut watch::ChangeTracker, args: &Args) {
+    let timestamp = format!("[{}]", scan.scan_timestamp).dimmed();
+    match changes.update(&scan.results, |r| args.latency_band(r.avg_response_time_ms)) {
+        None => print_scan(scan, args),
+        Some(changed) if !changed.is_empty() => {
+            println!("\n{} {}{} of {} targets changed", timestamp, icons::lead("🔀"), changed.len(), scan.results.len());
+            for result in changed {
+                print_result(result, args, "");
+            }
+        }
+        Some(_) if args.heartbeat > 0 && changes.quiet_scans().is_multiple_of(args.heartbeat) => {
+            let up = scan.results.iter().filter(|r| r.status == "up").count();
+            println!("{} {}", timestamp, format!("no changes ({}/{} up)", up, scan.results.len()).dimmed());
+        }
+        Some(_) => {}
+    }
+}
+
+/// --replay: print a saved scan as if it had just run, in whichever format was asked for. Its
+/// tags are shown in place of any --tag, and under --silent the exit status says whether it was
+/// all up.
+fn replay(path: &str, mut args: Args) -> Result<ExitCode, Box<dyn std::error::Error>> {
+    let scan = match output::load_scan(path, "scan") {
+        Ok(scan) => scan,
+        Err(e) => {
+            report_error(&e);
+            std::process::exit(1);
+        }
+    };
+    if scan.schema_version != SCHEMA_VERSION {
+        report_warning(&format!(
+            "'{}' has schema version {}, but this ring writes version {}; some fields may be missing or ignored",
+            path, scan.schema_version, SCHEMA_VERSION
+        ));
+    }
+    args.tags = scan.tags.clone().into_iter().collect();
+    let all_up = scan.results.iter().all(|r| r.status == "up");
+
+    if args.openmetrics {
+        let mut latest = serve::Latest::default();
+        latest.record(scan);
+        print!("{}", serve::openmetrics(&latest));
+    } else if args.prometheus {
+        print!("{}", serve::prometheus(&scan));
+    } else if args.json_by_host {
+        println!("{}", args.to_json(&args.scan_output(&GroupedScanResult::new(&scan))?)?);
+    } else if args.yaml {
+        print!("---\n{}", serde_yaml::to_string(&args.scan_output(&scan)?)?);
+    } else if args.json {
+        println!("{}", args.to_json(&args.scan_output(&scan)?)?);
+    } else if args.silent {
+        // Nothing to print; the exit status is the result
+    } else if args.summary_only {
+        print_summary_line(&scan.results, &scan.scan_timestamp, &args);
+    } else {
+        print_scan(&scan, &args);
+    }
+
+    if args.silent && !all_up {
+        return Ok(ExitCode::from(EXIT_NOT_UP));
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// `Total: 20  Up: 17  Partial: 2  Down: 1`, then the same per test type when more than one ran
+fn print_totals(results: &[HostResult]) {
+    let counts = |results: &[&HostResult]| {
+        let count = |status: &str| results.iter().filter(|r| r.status == status).count();
+        // Filtered only turns up with --timeout-as-filtered, so it's left out otherwise
+        let filtered = match count("filtered") {
+            0 => String::new(),
+            n => format!("  {}", format!("Filtered: {}", n).red()),
+        };
+        format!(
+            "{}  {}  {}{}",
+            format!("Up: {}", count("up")).green(),
+            format!("Partial: {}", count("partial")).yellow(),
+            format!("Down: {}", count("down")).red(),
+            filtered
+        )
+    };
+
+    let all: Vec<&HostResult> = results.iter().collect();
+    println!("\n{} {}  {}", "Total:".bold(), all.len(), counts(&all));
+
+    let mut test_types: Vec<&str> = Vec::new();
+    for result in results {
+        if !test_types.contains(&result.test_type.as_str()) {
+            test_types.push(&result.test_type);
+        }
+    }
+    if test_types.len() > 1 {
+        for test_type in test_types {
+            let of_type: Vec<&HostResult> = results.iter().filter(|r| r.test_type == test_type).collect();
+            println!("  {} {}  {}", format!("{}:", test_type.to_uppercase()).cyan(), of_type.len(), counts(&of_type));
+        }
+    }
+
+    let errors = error_tally(results);
+    if !errors.is_empty() {
+        let times = icons::get("times");
+        let list: Vec<String> = errors.iter().map(|(error, n)| format!("{} {}{}", error, times, n)).collect();
+        println!("{} {}", "Errors:".bold(), list.join(", ").red());
+    }
+}
+
+/// How often each kind of error came up, most common first: the failure kind where it says more
+/// than the message (`refused` rather than `connection_error`), otherwise the message's leading
+/// `name:` (`timeout`, `dns_resolution_failed`, `http_error`)
+fn error_tally(results: &[HostResult]) -> Vec<(&str, usize)> {
+    let mut tally: Vec<(&str, usize)> = Vec::new();
+    for result in results {
+        let Some(error) = &result.error else {
+            continue;
+        };
+        let name = match result.failure_kind {
+            Some(kind @ (FailureKind::Refused | FailureKind::Unreachable)) => kind.as_str(),
+            _ => error.split(':').next().unwrap_or_default().trim(),
+        };
+        match tally.iter_mut().find(|(n, _)| *n == name) {
+            Some((_, count)) => *count += 1,
+            None => tally.push((name, 1)),
+        }
+    }
+    // Ties keep the order the errors first came up in
+    tally.sort_by_key(|&(_, count)| std::cmp::Reverse(count));
+    tally
+}
+
+/// `Fastest: host:443 (3.2 ms)  Slowest: host2:80 (410.0 ms)` over the up results with a
+/// latency, when there are at least two to compare
+fn print_extremes(results: &[HostResult], args: &Args) {
+    let timed: Vec<(&HostResult, f64)> = results
+        .iter()
+        .filter(|r| r.status == "up")
+        .filter_map(|r| r.avg_response_time_ms.map(|avg| (r, avg)))
+        .collect();
+    if timed.len() < 2 {
+        return;
+    }
+    let by_avg = |a: &&(&HostResult, f64), b: &&(&HostResult, f64)| a.1.total_cmp(&b.1);
+    let (Some(fastest), Some(slowest)) = (timed.iter().min_by(by_avg), timed.iter().max_by(by_avg)) else {
+        return;
+    };
+
+    let label = |r: &HostResult| match r.port {
+        Some(port) => format!("{}:{}", r.label_host(), port),
+        None => format!("{} ({})", r.label_host(), r.test_type.to_uppercase()),
+    };
+    println!(
+        "\n{} {} ({} {})  {} {} ({} {})",
+        "Fastest:".dimmed(),
+        label(fastest.0).blue(),
+        args.color_latency(fastest.1, 1),
+        args.unit.label(),
+        "Slowest:".dimmed(),
+        label(slowest.0).blue(),
+        args.color_latency(slowest.1, 1),
+        args.unit.label()
+    );
+}
+
+/// --compare-protocols: each up TCP or SYN result's connect time against the ICMP round trip to
+/// the same host (and address, with --all-addresses). The ICMP echo is answered by the kernel;
+/// a connect also waits for the listening socket's backlog, so the difference is what accepting
+/// the connection costs on top of the network.
+fn print_protocol_comparison(results: &[HostResult], args: &Args) {
+    let latency = |ms: f64| format!("{} {}", args.unit.format(ms, 1), args.unit.label());
+    let lines: Vec<String> = results
+        .iter()
+        .filter(|r| r.status == "up" && matches!(r.test_type.as_str(), "tcp" | "syn"))
+        .filter_map(|tcp| {
+            // --http and --keepalive averages are whole exchanges; only the connect compares
+            let connect = tcp.connect_ms.or(tcp.avg_response_time_ms)?;
+            let icmp = results.iter().find(|r| {
+                r.test_type == "icmp"
+                    && r.host == tcp.host
+                    && (r.resolved_addr.is_none() || tcp.resolved_addr.is_none() || r.resolved_addr == tcp.resolved_addr)
+            })?;
+            let rtt = icmp.avg_response_time_ms?;
+            let host = tcp.label_host();
+            let port = tcp.port.unwrap_or_default();
+            let mut label = if host.contains(':') { format!("[{}]:{}", host, port) } else { format!("{}:{}", host, port) };
+            if let Some(addr) = tcp.label_addr() {
+                label.push_str(&format!(" @ {}", addr));
+            }
+            let delta = connect - rtt;
+            Some(format!(
+                "{}: icmp {} / {} {} {}",
+                label.blue(),
+                latency(rtt),
+                tcp.test_type,
+                latency(connect),
+                format!("({}{}{})", icons::pick("Δ", "delta "), if delta < 0.0 { "-" } else { "+" }, latency(delta.abs())).dimmed()
+            ))
+        })
+        .collect();
+    if lines.is_empty() {
+        return;
+    }
+    println!("\n{}ICMP vs TCP connect", icons::lead("⚖️ "));
+    for line in lines {
+        println!("{}", line);
+    }
+}
+
+/// A single status line for the whole scan, e.g. `14:02:11 | up: 8 partial: 1 down: 0 | avg 23ms`.
+/// On a terminal the line is redrawn in place each scan.
+fn print_summary_line(results: &[HostResult], timestamp: &str, args: &Args) {
+    let count = |status: &str| results.iter().filter(|r| r.status == status).count();
+    let averages: Vec<f64> = results.iter().filter_map(|r| r.avg_response_time_ms).collect();
+
+    // The scan's own time, already in --utc or local time
+    let time = match DateTime::parse_from_rfc3339(timestamp) {
+        Ok(time) => time.format("%H:%M:%S").to_string(),
+        Err(_) => timestamp.to_string(),
+    };
+    let latency = if averages.is_empty() {
+        "avg -".to_string()
+    } else {
+        let avg = averages.iter().sum::<f64>() / averages.len() as f64;
+        format!("avg {}{}", args.unit.format(avg, 0), args.unit.label())
+    };
+
+    // Only with --timeout-as-filtered, so the usual line keeps its shape
+    let filtered = match count("filtered") {
+        0 => String::new(),
+        n => format!(" {}", format!("filtered: {}", n).red()),
+    };
+    let tags = if args.tags.is_empty() { String::new() } else { format!(" | {}", format_tags(&args.tags)) };
+    let line = format!(
+        "{} | {} {} {}{} | {}{}",
+        time.dimmed(),
+        format!("up: {}", count("up")).green(),
+        format!("partial: {}", count("partial")).yellow(),
+        format!("down: {}", count("down")).red(),
+        filtered,
+        latency,
+        tags.dimmed()
+    );
+
+    let mut stdout = std::io::stdout();
+    if stdout.is_terminal() && !args.once {
+        // Return to the start of the line and clear whatever the previous scan printed
+        let _ = write!(stdout, "\r\x1b[K{}", line);
+        let _ = stdout.flush();
+    } else {
+        println!("{}", line);
+    }
+}
+
+/// Rank `up` results by average latency, medals for the top three and the winner highlighted.
+/// Partially reachable targets follow as unstable, then unreachable ones.
+fn print_leaderboard(results: &[HostResult], args: &Args) {
+    let label = |r: &HostResult| {
+        let host = r.label_host();
+        let mut label = match r.port {
+            Some(port) if host.contains(':') => format!("[{}]:{}", host, port),
+            Some(port) => format!("{}:{}", host, port),
+            None => format!("{} ({})", host, r.test_type.to_uppercase()),
+        };
+        if let Some(addr) = r.label_addr() {
+            label.push_str(&format!(" @ {}", addr));
+        }
+        label
+    };
+
+    let mut ranked: Vec<&HostResult> =
+        results.iter().filter(|r| r.status == "up" && r.avg_response_time_ms.is_some()).collect();
+    ranked.sort_by(|a, b| a.avg_response_time_ms.partial_cmp(&b.avg_response_time_ms).unwrap_or(std::cmp::Ordering::Equal));
+    let width = results.iter().map(|r| label(r).chars().count()).max().unwrap_or(0);
+
+    for (place, result) in ranked.iter().enumerate() {
+        let medal = match place {
+            0 if !icons::ascii() => "🥇".to_string(),
+            1 if !icons::ascii() => "🥈".to_string(),
+            2 if !icons::ascii() => "🥉".to_string(),
+            // Two columns wide, like the medals
+            n => format!("{}.", n + 1),
+        };
+        let avg = result.avg_response_time_ms.unwrap_or_default();
+        let name = format!("{:<width$}", label(result));
+        let name = if place == 0 { name.green().bold() } else { name.blue() };
+        println!(
+            "{} {}  {} {} [{}]",
+            medal,
+            name,
+            args.color_latency(avg, 1),
+            args.unit.label(),
+            result.test_type.cyan()
+        );
+    }
+
+    for result in results.iter().filter(|r| r.status == "partial") {
+        println!(
+            "{} {}  {}/{} successful, unstable [{}]",
+            icons::get("partial"),
+            format!("{:<width$}", label(result)).yellow(),
+            result.successful,
+            result.attempts,
+            result.test_type.cyan()
+        );
+    }
+
+    for result in results.iter().filter(|r| r.status == "down" || r.status == "filtered") {
+        let reason = result.error.as_deref().map(|e| format!(" ({})", e)).unwrap_or_default();
+        let outcome = if result.status == "filtered" { "filtered" } else { "unreachable" };
+        println!(
+            "{} {}  {}{} [{}]",
+            icons::get("down"),
+            format!("{:<width$}", label(result)).dimmed(),
+            outcome.red(),
+            reason.red(),
+            result.test_type.cyan()
+        );
+    }
+}
+
+/// One line per host: how many TCP ports are open (and which), plus the ICMP and ARP outcomes.
+/// Per-port detail is printed underneath with --verbose.
+fn print_by_host(results: &[HostResult], args: &Args) {
+    let mut hosts: Vec<&str> = Vec::new();
+    for result in results {
+        if !hosts.contains(&result.host.as_str()) {
+            hosts.push(&result.host);
+        }
+    }
+
+    for host in hosts {
+        let host_results: Vec<&HostResult> = results.iter().filter(|r| r.host == host).collect();
+        let tcp: Vec<&HostResult> = host_results.iter().copied().filter(|r| r.port.is_some()).collect();
+        let pings: Vec<&HostResult> = host_results.iter().copied().filter(|r| r.port.is_none()).collect();
+        let open: Vec<u16> = tcp.iter().filter(|r| r.successful > 0).filter_map(|r| r.port).collect();
+
+        let reachable = open.len() + pings.iter().filter(|r| r.successful > 0).count();
+        let total = tcp.len() + pings.len();
+        let status_icon = if reachable == total {
+            icons::get("up")
+        } else if reachable == 0 {
+            icons::get("down")
+        } else {
+            icons::get("partial")
+        };
+
+        let mut parts = Vec::new();
+        if !tcp.is_empty() {
+            let mut part = format!("{}/{} ports open", open.len(), tcp.len());
+            if !open.is_empty() {
+                let list = open.iter().map(|p| p.to_string()).collect::<Vec<_>>().join(", ");
+                part.push_str(&format!(" [{}]", list.yellow()));
+            }
+            parts.push(part);
+        }
+        for ping in pings {
+            let label = format!("{} {}", ping.test_type.to_uppercase(), ping.status).magenta();
+            let detail = match ping.avg_response_time_ms {
+                Some(avg) => format!("{} ({} {})", label, args.color_latency(avg, 2), args.unit.label()),
+                None => label.to_string(),
+            };
+            parts.push(detail);
+        }
+
+        let name = match host_results.first().and_then(|r| r.ptr_name.as_ref()) {
+            Some(ptr_name) => format!("{} {}", ptr_name.blue(), format!("@ {}", host).dimmed()),
+            None => host.blue().to_string(),
+        };
+        println!("{} {}: {}", status_icon, name, parts.join(&format!(" {} ", icons::get("separator"))));
+
+        if args.verbose {
+            for result in host_results {
+                print_result(result, args, "    ");
+            }
+        }
+    }
+}
+
+/// --probe-label-from-ptr: fill in `ptr_name` for results whose target is an IP address, looking
+/// each address up once and all of them at the same time
+async fn label_from_ptr(results: &mut [HostResult], resolver: &dns::Resolver, timeout_ms: u64) {
+    let ips: HashSet<IpAddr> = results.iter().filter_map(|r| r.host.parse::<ScopedIp>().ok()).map(|ip| ip.ip).collect();
+    let lookups = ips.into_iter().map(|ip| async move { (ip, resolver.reverse(ip, Duration::from_millis(timeout_ms)).await) });
+    let names: HashMap<IpAddr, String> =
+        future::join_all(lookups).await.into_iter().filter_map(|(ip, name)| Some((ip, name?))).collect();
+    for result in results {
+        if let Ok(ip) = result.host.parse::<ScopedIp>() {
+            result.ptr_name = names.get(&ip.ip).cloned();
+        }
+    }
+}
+
+/// Make sure every port the --expect manifest lists for a host is actually probed
+fn add_expected_ports(targets: &mut [Target], expected: &manifest::Manifest) {
+    for target in targets {
+        if let Some(ports) = expected.get(&target.host) {
+            target.ports.extend(ports);
+            target.ports.sort_unstable();
+            target.ports.dedup();
+        }
+    }
+}
+
+/// --refresh-targets: the targets for a freshly fetched host list, held to the rules the
+/// startup list was
+fn refreshed_targets(
+    hosts: &[String],
+    ports: &[u16],
+    args: &Args,
+    expected: Option<&manifest::Manifest>,
+) -> Result<Vec<Target>, String> {
+    let mut targets = hosts
+        .iter()
+        .map(|h| parse_target(h, ports, args.port_step))
+        .collect::<Result<Vec<_>, _>>()
+        .and_then(|targets| expand_hosts(targets, args))
+        .map_err(|e| format!("invalid host: {}", e))?;
+    if args.no_tcp && targets.iter().any(|t| !t.ports.is_empty()) {
+        return Err("--no-tcp can't be combined with per-host ports (host:port)".to_string());
+    }
+    if let (true, Some(target)) = (args.no_dns, targets.iter().find(|t| !t.is_uds() && t.host.parse::<ScopedIp>().is_err())) {
+        return Err(format!("--no-dns: not an IP address: {}", target.host));
+    }
+    if let Some(expected) = expected {
+        add_expected_ports(&mut targets, expected);
+    }
+    Ok(targets)
+}
+
+/// The host a URL connects to, when that is a name rather than an IP address
+fn url_host_name(url: &str) -> Option<String> {
+    let url = reqwest::Url::parse(url).ok()?;
+    let host = url.host_str()?.trim_start_matches('[').trim_end_matches(']').to_string();
+    host.parse::<IpAddr>().is_err().then_some(host)
+}
+
+/// Every target name that doesn't resolve, looked up concurrently. Literal IPs always resolve.
+async fn unresolvable_hosts(targets: &[Target], args: &Args) -> Vec<String> {
+    let resolver = dns::Resolver::new(args.effective_dns_retries(), Duration::ZERO);
+    let resolver = &resolver;
+    let lookups = targets
+        .iter()
+        .filter(|t| !t.is_uds() && t.host.parse::<ScopedIp>().is_err())
+        .map(|t| async move {
+            let resolved = if args.mdns && mdns::is_mdns_host(&t.host) {
+                mdns::resolve(&t.host, args.connect_timeout_ms()).await.is_some()
+            } else {
+                resolver.lookup(&t.host).await.is_ok_and(|addrs| !addrs.is_empty())
+            };
+            (!resolved).then(|| t.host.clone())
+        });
+    future::join_all(lookups).await.into_iter().flatten().collect()
+}
+
+/// One probe a scan would run, as listed by --dry-run
+#[derive(Serialize, Debug)]
+struct PlannedProbe<'a> {
+    host: &'a str,
+    port: Option<u16>,
+    test_type: &'static str,
+    attempts: u32,
+}
+
+/// Print every probe the scan would run, in the order `run_scan` runs them, without sending anything
+fn print_plan(targets: &[Target], args: &Args) -> Result<(), Box<dyn std::error::Error>> {
+    let tcp = tcp_probe_order(targets, args.probe_order).into_iter().map(|(t, port)| PlannedProbe {
+        host: &t.host,
+        port: Some(port),
+        test_type: "tcp",
+        attempts: args.tcp_attempts(),
+    });
+    let uds = targets.iter().filter(|t| t.is_uds()).map(|t| PlannedProbe {
+        host: &t.host,
+        port: None,
+        test_type: "uds",
+        attempts: args.tcp_attempts(),
+    });
+    let icmp = targets.iter().filter(|t| args.ping && !t.is_uds()).map(|t| PlannedProbe {
+        host: &t.host,
+        port: None,
+        test_type: "icmp",
+        attempts: args.ping_attempts(),
+    });
+    let arp = targets.iter().filter(|t| args.arp && !t.is_uds()).map(|t| PlannedProbe {
+        host: &t.host,
+        port: None,
+        test_type: "arp",
+        attempts: args.count,
+    });
+    let quic = targets.iter().filter(|t| args.quic && !t.is_uds()).map(|t| PlannedProbe {
+        host: &t.host,
+        port: Some(args.quic_port),
+        test_type: "quic",
+        attempts: args.tcp_attempts(),
+    });
+    let probes: Vec<PlannedProbe> = tcp.chain(uds).chain(icmp).chain(arp).chain(quic).collect();
+    // Every TCP port of the scan, host-specific ones included
+    let mut ports: Vec<u16> = targets.iter().filter(|t| !t.is_uds()).flat_map(|t| t.ports.iter().copied()).collect();
+    ports.sort_unstable();
+    ports.dedup();
+
+    if args.json {
+        let plan = serde_json::json!({ "hosts": targets.len(), "ports": ports, "probes": probes });
+        if args.yaml {
+            print!("{}", serde_yaml::to_string(&plan)?);
+        } else {
+            println!("{}", args.to_json(&plan)?);
+        }
+        return Ok(());
+    }
+
+    let attempts: u32 = probes.iter().map(|p| p.attempts).sum();
+    println!(
+        "\n{}Scan plan: {} probes across {} hosts, {} attempts in total",
+        icons::lead("📝"),
+        probes.len(),
+        targets.len(),
+        attempts
+    );
+    if !ports.is_empty() {
+        println!("{} {}", "Ports:".bold(), format_port_ranges(&ports).yellow());
+    }
+    println!("{}", icons::rule().dimmed());
+    let times = icons::get("times");
+    for probe in &probes {
+        match probe.port {
+            Some(port) if probe.host.contains(':') => println!(
+                "[{}]:{} {} {} [{}]",
+                probe.host.blue(),
+                port.to_string().yellow(),
+                times,
+                probe.attempts,
+                probe.test_type.cyan()
+            ),
+            Some(port) => println!(
+                "{}:{} {} {} [{}]",
+                probe.host.blue(),
+                port.to_string().yellow(),
+                times,
+                probe.attempts,
+                probe.test_type.cyan()
+            ),
+            None => println!(
+                "{} ({}) {} {} [{}]",
+                probe.host.blue(),
+                probe.test_type.to_uppercase(),
+                times,
+                probe.attempts,
+                probe.test_type.cyan()
+            ),
+        }
+    }
+    Ok(())
+}
+
+/// Probe all of a host's ports at once and keep the first that comes back `up`; the rest are
+/// cancelled by dropping them. When no port is up, the best remaining result (partial before
+/// down, in port order) stands in for the host.
+async fn first_success(target: &Target, args: &Args, probe_opts: &ProbeOptions) -> Vec<HostResult> {
+    let mut pending: FuturesUnordered<_> = target
+        .ports
+        .iter()
+        .map(|&port| tcp_check(target.host.clone(), port, args.tcp_attempts(), args.connect_timeout_ms(), probe_opts))
+        .collect();
+
+    // With --all-addresses a port's results cover every address; any of them being up counts
+    let mut finished = Vec::with_capacity(target.ports.len());
+    while let Some(results) = pending.next().await {
+        if results.iter().any(|r| r.status == "up") {
+            return results;
+        }
+        finished.push(results);
+    }
+
+    let rank = |results: &Vec<HostResult>| (!results.iter().any(|r| r.status == "partial"), results[0].port);
+    finished.sort_by_key(rank);
+    finished.remove(0)
+}
+
+/// Source of probe IDs; never reused, so IDs stay unique across scans of a continuous run
+static NEXT_PROBE_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Run one probe task in a `probe` span carrying a fresh probe ID, so every --log-level line it
+/// logs names the probe, and stamp its results with the same ID
+async fn traced(probe: impl Future<Output = Vec<HostResult>>) -> Vec<HostResult> {
+    use tracing::Instrument;
+
+    let probe_id = NEXT_PROBE_ID.fetch_add(1, Ordering::Relaxed);
+    let mut results = probe.instrument(tracing::debug_span!("probe", probe_id)).await;
+    for result in &mut results {
+        result.probe_id = Some(probe_id);
+    }
+    results
+}
+
+/// Run one probe task, turning a panic inside it into a `down` result with the error
+/// `internal_probe_panic`, so one probe tripping over a bug doesn't end the whole scan
+async fn contained(
+    key: &resume::ProbeKey,
+    attempts: u32,
+    probe: impl Future<Output = Vec<HostResult>>,
+) -> Vec<HostResult> {
+    use futures::FutureExt;
+
+    match std::panic::AssertUnwindSafe(probe).catch_unwind().await {
+        Ok(results) => results,
+        Err(panic) => {
+            let message = match (panic.downcast_ref::<&str>(), panic.downcast_ref::<String>()) {
+                (Some(message), _) => message.to_string(),
+                (None, Some(message)) => message.clone(),
+                (None, None) => "no message".to_string(),
+            };
+            let target = match key.port {
+                Some(port) => format!("{}:{}", key.host, port),
+                None => format!("{} ({})", key.host, key.kind),
+            };
+            report_warning(&format!("the probe of {} panicked ({}); reporting it down", target, message));
+            // --first-success covers a host's TCP ports
+            let test_type = if key.kind == "first-success" { "tcp" } else { key.kind.as_str() };
+            vec![HostResult::failure(&key.host, key.port, test_type, attempts, "internal_probe_panic".to_string())]
+        }
+    }
+}
+
+/// Run one probe task, abandoning it (None) if it's still going at `deadline`
+async fn bounded(deadline: Option<Instant>, probe: impl Future<Output = Vec<HostResult>>) -> Option<Vec<HostResult>> {
+    match deadline {
+        Some(deadline) => {
+            let finished = timeout_at(deadline.into(), probe).await.ok();
+            if finished.is_none() {
+                tracing::debug!("probe abandoned at the --max-runtime deadline");
+            }
+            finished
+        }
+        None => Some(probe.await),
+    }
+}
+
+/// Run one probe task, reusing its results from the resume file when an earlier run finished
+/// it. Abandoned probes yield nothing and aren't recorded, so a resumed run retries them.
+async fn resumable(
+    checkpoint: Option<&Mutex<resume::Checkpoint>>,
+    deadline: Option<Instant>,
+    key: resume::ProbeKey,
+    attempts: u32,
+    probe: impl Future<Output = Vec<HostResult>>,
+) -> Vec<HostResult> {
+    let probe = contained(&key, attempts, probe);
+    let Some(checkpoint) = checkpoint else {
+        return bounded(deadline, traced(probe)).await.unwrap_or_default();
+    };
+    if let Some(results) = checkpoint.lock().unwrap().get(&key) {
+        return results;
+    }
+
+    let Some(results) = bounded(deadline, traced(probe)).await else {
+        return Vec::new();
+    };
+    checkpoint.lock().unwrap().record(key, &results);
+    results
+}
+
+/// Run one probe task as `resumable` does. With --result-limit its results go to the stream,
+/// which hands back only those it keeps.
+async fn checkpointed(
+    checkpoint: Option<&Mutex<resume::Checkpoint>>,
+    stream: Option<&stream::ResultStream<'_>>,
+    deadline: Option<Instant>,
+    key: resume::ProbeKey,
+    attempts: u32,
+    probe: impl Future<Output = Vec<HostResult>>,
+) -> Vec<HostResult> {
+    let results = resumable(checkpoint, deadline, key, attempts, probe).await;
+    match stream {
+        Some(stream) => stream.emit(results),
+        None => results,
+    }
+}
+
+/// Run `tasks` at most `limit` at a time (without a limit, all at once), returning their
+/// outputs in the order the tasks were given
+async fn run_limited<F: Future>(tasks: impl IntoIterator<Item = F>, limit: Option<u32>) -> Vec<F::Output> {
+    let Some(limit) = limit else {
+        return future::join_all(tasks).await;
+    };
+    let mut outputs: Vec<(usize, F::Output)> = futures::stream::iter(tasks.into_iter().enumerate())
+        .map(|(i, task)| async move { (i, task.await) })
+        .buffer_unordered(limit as usize)
+        .collect()
+        .await;
+    outputs.sort_unstable_by_key(|(i, _)| *i);
+    outputs.into_iter().map(|(_, output)| output).collect()
+}
+
+/// Run numbered TCP probes on two levels: --concurrency hosts at a time, each with
+/// --port-concurrency ports at a time, or with --max-in-flight as one pool that takes the
+/// hosts' probes in turn. Outputs keep their numbers but not their order.
+async fn tcp_batch(
+    probes: Vec<(usize, (&Target, u16))>,
+    args: &Args,
+    probe_opts: &ProbeOptions,
+    checkpoint: Option<&Mutex<resume::Checkpoint>>,
+    stream: Option<&stream::ResultStream<'_>>,
+    deadline: Option<Instant>,
+) -> Vec<(usize, Vec<HostResult>)> {
+    let mut host_ports: Vec<(&Target, Vec<(usize, u16)>)> = Vec::new();
+    let mut index: HashMap<&str, usize> = HashMap::new();
+    for (i, (target, port)) in probes {
+        let slot = *index.entry(target.host.as_str()).or_insert_with(|| {
+            host_ports.push((target, Vec::new()));
+            host_ports.len() - 1
+        });
+        host_ports[slot].1.push((i, port));
+    }
+
+    let probe = |target: &Target, i: usize, port: u16| {
+        let task = tcp_check(target.host.clone(), port, args.tcp_attempts(), args.connect_timeout_ms(), probe_opts);
+        let key = resume::ProbeKey::new(&target.host, Some(port), "tcp");
+        let task = checkpointed(checkpoint, stream, deadline, key, args.tcp_attempts(), task);
+        async move { (i, task.await) }
+    };
+
+    if let Some(limit) = args.max_in_flight {
+        // Deal the probes out round-robin, every host's first port, then every host's second, ...,
+        // so as slots free up in start order each host gets its turn at the next one
+        let mut queues: Vec<_> = host_ports.into_iter().map(|(target, ports)| (target, ports.into_iter())).collect();
+        let mut tasks = Vec::new();
+        while !queues.is_empty() {
+            queues.retain_mut(|(target, ports)| match ports.next() {
+                Some((i, port)) => {
+                    tasks.push(probe(target, i, port));
+                    true
+                }
+                None => false,
+            });
+        }
+        return run_limited(tasks, Some(limit)).await;
+    }
+
+    let host_tasks = host_ports.into_iter().map(|(target, ports)| {
+        let port_tasks = ports.into_iter().map(move |(i, port)| probe(target, i, port));
+        run_limited(port_tasks, args.port_concurrency)
+    });
+    run_limited(host_tasks, args.concurrency).await.into_iter().flatten().collect()
+}
+
+/// Probe every target once: TCP checks for each port, then ICMP and ARP if enabled
+async fn run_scan(
+    targets: &[Target],
+    args: &Args,
+    probe_opts: &ProbeOptions,
+    checkpoint: Option<&Mutex<resume::Checkpoint>>,
+    stream: Option<&stream::ResultStream<'_>>,
+    deadline: Option<Instant>,
+) -> Vec<HostResult> {
+    let started = Instant::now();
+    if let Some(budget) = &probe_opts.retry_budget {
+        budget.reset();
+    }
+    let tcp_probes: usize = targets.iter().map(|t| t.ports.len()).sum();
+    tracing::info!(targets = targets.len(), tcp_probes, ping = args.ping, "scan started");
+    let mut all_results = Vec::new();
+
+    // Run TCP checks
+    if args.first_success {
+        let host_tasks = targets.iter().filter(|t| !t.ports.is_empty()).map(|t| {
+            let key = resume::ProbeKey::new(&t.host, None, "first-success");
+            checkpointed(checkpoint, stream, deadline, key, args.tcp_attempts(), first_success(t, args, probe_opts))
+        });
+        all_results.extend(run_limited(host_tasks, args.concurrency).await.into_iter().flatten());
+    } else if targets.iter().any(|t| !t.ports.is_empty()) {
+        // Probes are numbered in --probe-order so the results can be put back in it afterwards
+        let mut probes: Vec<(usize, (&Target, u16))> =
+            tcp_probe_order(targets, args.probe_order).into_iter().enumerate().collect();
+        let mut tcp_results = Vec::new();
+        match args.batch_size {
+            Some(size) => {
+                rng::with(|rng| probes.shuffle(rng));
+                for (n, batch) in probes.chunks(size as usize).enumerate() {
+                    if n > 0 {
+                        let delay = Duration::from_millis(args.batch_delay);
+                        let delay = deadline.map_or(delay, |d| delay.min(d.saturating_duration_since(Instant::now())));
+                        tracing::debug!(batch = n + 1, ?delay, "waiting for the next batch");
+                        tokio::time::sleep(delay).await;
+                    }
+                    tcp_results.extend(tcp_batch(batch.to_vec(), args, probe_opts, checkpoint, stream, deadline).await);
+                }
+            }
+            None => tcp_results = tcp_batch(probes, args, probe_opts, checkpoint, stream, deadline).await,
+        }
+        tcp_results.sort_unstable_by_key(|(i, _)| *i);
+        all_results.extend(tcp_results.into_iter().flat_map(|(_, results)| results));
+    }
+
+    // Run Unix domain socket checks
+    let uds_tasks = targets.iter().filter(|t| t.is_uds()).map(|target| {
+        let task = async { vec![uds_check(target.host.clone(), args.tcp_attempts(), args.connect_timeout_ms(), probe_opts).await] };
+        let key = resume::ProbeKey::new(&target.host, None, "uds");
+        checkpointed(checkpoint, stream, deadline, key, args.tcp_attempts(), task)
+    });
+    all_results.extend(run_limited(uds_tasks, args.probe_limit()).await.into_iter().flatten());
+
+    // Run ICMP ping checks
+    if args.ping {
+        let mut ping_tasks = vec![];
+        for target in targets.iter().filter(|t| !t.is_uds()) {
+            let host_clone = target.host.clone();
+            let task = icmp_ping(host_clone, args.ping_attempts(), args.ping_timeout, probe_opts);
+            let key = resume::ProbeKey::new(&target.host, None, "icmp");
+            ping_tasks.push(checkpointed(checkpoint, stream, deadline, key, args.ping_attempts(), task));
+        }
+
+        let ping_results = run_limited(ping_tasks, args.probe_limit()).await;
+        all_results.extend(ping_results.into_iter().flatten());
+    }
+
+    // Run ARP checks
+    if args.arp {
+        let arp_tasks = targets.iter().filter(|t| !t.is_uds()).map(|target| {
+            let task = arp_ping(target.host.clone(), args.count, args.ping_timeout, probe_opts);
+            let key = resume::ProbeKey::new(&target.host, None, "arp");
+            checkpointed(checkpoint, stream, deadline, key, args.count, task)
+        });
+        all_results.extend(run_limited(arp_tasks, args.probe_limit()).await.into_iter().flatten());
+    }
+
+    // Run QUIC handshakes
+    if args.quic {
+        let quic_tasks = targets.iter().filter(|t| !t.is_uds()).map(|target| {
+            let task = quic_check(target.host.clone(), args.quic_port, args.tcp_attempts(), args.connect_timeout_ms(), probe_opts);
+            let key = resume::ProbeKey::new(&target.host, Some(args.quic_port), "quic");
+            checkpointed(checkpoint, stream, deadline, key, args.tcp_attempts(), task)
+        });
+        all_results.extend(run_limited(quic_tasks, args.probe_limit()).await.into_iter().flatten());
+    }
+
+    tracing::info!(results = all_results.len(), elapsed = ?started.elapsed(), "scan finished");
+    all_results
+}
+
+/// --icmp-flood: resolve every target, refuse the lot if any is public without
+/// --i-know-what-im-doing, then flood them one at a time so their loads don't add up
+async fn icmp_flood(targets: &[Target], args: &Args) -> Result<ExitCode, Box<dyn std::error::Error>> {
+    let resolver = dns::Resolver::new(args.effective_dns_retries(), Duration::ZERO);
+    let mut addrs = Vec::new();
+    for target in targets.iter().filter(|t| !t.is_uds()) {
+        let addr = match target.host.parse::<IpAddr>() {
+            Ok(ip) => ip,
+            Err(_) => match resolver.lookup(&target.host).await.map(|ips| ips.first().copied()) {
+                Ok(Some(ip)) => ip,
+                Ok(None) => {
+                    report_error(&format!("--icmp-flood: {} resolved to no addresses", target.host));
+                    std::process::exit(1);
+                }
+                Err(e) => {
+                    report_error(&format!("--icmp-flood: could not resolve {}: {}", target.host, e));
+                    std::process::exit(1);
+                }
+            },
+        };
+        addrs.push((target.host.as_str(), addr));
+    }
+
+    let public: Vec<String> = addrs
+        .iter()
+        .filter(|&&(_, addr)| is_public_range(addr, if addr.is_ipv4() { 32 } else { 128 }))
+        .map(|&(host, addr)| if host == addr.to_string() { host.to_string() } else { format!("{} ({})", host, addr) })
+        .collect();
+    if !public.is_empty() && !args.allow_public_ranges {
+        report_error(&format!(
+            "--icmp-flood only floods private, loopback, link-local and documentation addresses; {} {} public \
+             (add --i-know-what-im-doing if you own the network)",
+            public.join(", "),
+            if public.len() == 1 { "is" } else { "are" }
+        ));
+        std::process::exit(1);
+    }
+
+    let rate = args.rate.unwrap_or_default();
+    let mut reports = Vec::new();
+    for (host, addr) in addrs {
+        match flood::run(host, addr, rate, Duration::from_millis(args.ping_timeout), usize::from(args.ping_size)).await {
+            Ok(report) if args.json => reports.push(report),
+            Ok(report) => report.print(),
+            Err(e) => {
+                report_error(&format!("--icmp-flood: {}", e));
+                std::process::exit(1);
+            }
+        }
+    }
+    if args.json {
+        println!("{}", args.to_json(&reports)?);
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Report that the --max-runtime budget is spent and return the exit status for it
+fn max_runtime_reached(args: &Args) -> ExitCode {
+    report_warning(&format!("--max-runtime of {}s reached; stopping", args.max_runtime.unwrap_or_default()));
+    ExitCode::from(EXIT_MAX_RUNTIME)
+}
+
+/// Pause before each --rescan-failures pass
+const RESCAN_DELAY: Duration = Duration::from_millis(500);
+
+/// Pause before --rescan-on-total-failure scans again: long enough for Wi-Fi to reassociate or
+/// a VPN to reconnect
+const TOTAL_FAILURE_DELAY: Duration = Duration::from_secs(5);
+
+fn status_rank(status: &str) -> u8 {
+    match status {
+        "up" => 2,
+        "partial" => 1,
+        _ => 0,
+    }
+}
+
+/// Fold a rescan into the result it repeats. Status, error and details come from the better
+/// of the two passes; attempts, successes and samples add up across both, in order.
+fn merge_rescan(prev: HostResult, next: HostResult) -> HostResult {
+    let mut response_times = prev.response_times.clone();
+    response_times.extend(&next.response_times);
+    let mut source_ports = prev.source_ports.clone();
+    source_ports.extend(&next.source_ports);
+    let mut send_epoch_us = prev.send_epoch_us.clone();
+    send_epoch_us.extend(&next.send_epoch_us);
+    let mut recv_epoch_us = prev.recv_epoch_us.clone();
+    recv_epoch_us.extend(&next.recv_epoch_us);
+    let mut icmp_timestamps = prev.icmp_timestamps.clone();
+    icmp_timestamps.extend(&next.icmp_timestamps);
+    let offsets: Vec<f64> = icmp_timestamps.iter().filter_map(|t| t.clock_offset_ms()).collect();
+    // The rescan's attempts follow on from the earlier ones
+    let mut attempt_log = prev.attempt_log.clone();
+    attempt_log.extend(next.attempt_log.iter().map(|a| AttemptRecord { seq: a.seq + prev.attempts, ..a.clone() }));
+
+    let attempts = prev.attempts + next.attempts;
+    let successful = prev.successful + next.successful;
+    let rescans = prev.rescans + 1;
+    let probe_id = prev.probe_id;
+    let hops_estimate = next.hops_estimate.or(prev.hops_estimate);
+    let best = if status_rank(&next.status) > status_rank(&prev.status) { next } else { prev };
+
+    let samples: Vec<f64> = response_times.iter().map(|&t| t as f64).collect();
+    HostResult {
+        probe_id,
+        attempts,
+        successful,
+        success_rate: successful as f64 / attempts as f64,
+        avg_response_time_ms: mean(&samples),
+        min_response_time_ms: response_times.iter().min().map(|&t| t as f64),
+        max_response_time_ms: response_times.iter().max().map(|&t| t as f64),
+        jitter_ms: jitter(&response_times),
+        response_times,
+        source_ports,
+        send_epoch_us,
+        recv_epoch_us,
+        clock_offset_ms: mean(&offsets),
+        icmp_timestamps,
+        hops_estimate,
+        attempt_log,
+        rescans,
+        ..best
+    }
+}
+
+/// --rescan-failures: re-run the probes behind results that aren't up, up to `passes` more
+/// times, stopping early for anything that comes up. A probe covers every address of its host
+/// with --all-addresses, so its fresh results are matched back by address.
+async fn rescan_failures(results: &mut [HostResult], args: &Args, probe_opts: &ProbeOptions, deadline: Option<Instant>) {
+    for _ in 0..args.rescan_failures {
+        let mut probes: Vec<(String, Option<u16>, &str, Option<u64>)> = Vec::new();
+        for r in results.iter().filter(|r| r.status != "up") {
+            let probe = (r.host.clone(), r.port, r.test_type.as_str(), r.probe_id);
+            if !probes.contains(&probe) {
+                probes.push(probe);
+            }
+        }
+        // Each rescanned probe is a retry as far as --retry-budget is concerned
+        probes.retain(|_| probe_opts.spend_retry());
+        if probes.is_empty() {
+            return;
+        }
+
+        // Give whatever made the first pass fail a moment to clear
+        tokio::time::sleep(RESCAN_DELAY).await;
+        let passes = probes.iter().map(|&(ref host, port, test_type, probe_id)| async move {
+            use tracing::Instrument;
+
+            let probe = async {
+                match (port, test_type) {
+                    (_, "icmp") => icmp_ping(host.clone(), args.ping_attempts(), args.ping_timeout, probe_opts).await,
+                    (_, "arp") => arp_ping(host.clone(), args.count, args.ping_timeout, probe_opts).await,
+                    (_, "uds") => vec![uds_check(host.clone(), args.tcp_attempts(), args.connect_timeout_ms(), probe_opts).await],
+                    (Some(port), "quic") => {
+                        quic_check(host.clone(), port, args.tcp_attempts(), args.connect_timeout_ms(), probe_opts).await
+                    }
+                    (Some(port), _) => {
+                        tcp_check(host.clone(), port, args.tcp_attempts(), args.connect_timeout_ms(), probe_opts).await
+                    }
+                    (None, _) => Vec::new(),
+                }
+            };
+            let key = resume::ProbeKey::new(host, port, test_type);
+            let attempts = match test_type {
+                "icmp" => args.ping_attempts(),
+                "arp" => args.count,
+                _ => args.tcp_attempts(),
+            };
+            // The rescan's lines carry the ID of the probe its result is merged into
+            let probe = probe.instrument(tracing::debug_span!("probe", probe_id));
+            bounded(deadline, contained(&key, attempts, probe)).await.unwrap_or_default()
+        });
+        // The same --concurrency/--max-in-flight cap as the first pass
+        let fresh: Vec<HostResult> = run_limited(passes, args.probe_limit()).await.into_iter().flatten().collect();
+
+        for result in results.iter_mut().filter(|r| r.status != "up") {
+            let same_probe = |f: &&HostResult| f.host == result.host && f.port == result.port && f.test_type == result.test_type;
+            let matched = fresh
+                .iter()
+                .filter(same_probe)
+                .find(|f| f.resolved_addr == result.resolved_addr)
+                .or_else(|| fresh.iter().find(same_probe));
+            if let Some(next) = matched {
+                *result = merge_rescan(std::mem::take(result), next.clone());
+            }
+        }
+    }
+}
+
+/// --min-interval-drift-correction: the first multiple of `interval_ms` since the Unix epoch
+/// that is still to come, as its slot number and how long until it starts
+fn next_slot(interval_ms: u64) -> (u64, Duration) {
+    let interval = u128::from(interval_ms.max(1)) * 1000;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_micros());
+    let slot = now / interval + 1;
+    (slot as u64, Duration::from_micros((slot * interval - now) as u64))
+}
+
+/// "5 seconds" for whole seconds, "1500 ms" otherwise
+fn format_interval(ms: u64) -> String {
+    match ms {
+        1000 => "1 second".to_string(),
+        ms if ms % 1000 == 0 => format!("{} seconds", ms / 1000),
+        ms => format!("{} ms", ms),
+    }
+}
+
+/// --version-json: exactly what a ring binary was built from, for bug reports
+#[derive(Serialize, Debug)]
+struct BuildInfo {
+    version: &'static str,
+    git_hash: &'static str, // "unknown" when built outside a git checkout
+    rustc: &'static str,
+    target: &'static str,
+    features: Vec<&'static str>,
+}
+
+impl BuildInfo {
+    fn current() -> Self {
+        let features = [
+            ("geoip", cfg!(feature = "geoip")),
+            ("mdns", cfg!(feature = "mdns")),
+            ("quic", cfg!(feature = "quic")),
+            ("sqlite", cfg!(feature = "sqlite")),
+        ];
+        BuildInfo {
+            version: env!("CARGO_PKG_VERSION"),
+            git_hash: env!("GIT_HASH"),
+            rustc: env!("RUSTC_VERSION"),
+            target: env!("TARGET"),
+            features: features.into_iter().filter(|(_, enabled)| *enabled).map(|(name, _)| name).collect(),
+        }
+    }
+}
+
+fn main() -> Result<ExitCode, Box<dyn std::error::Error>> {
+    let args = Args::parse();
+    if args.version_json {
+        println!("{}", serde_json::to_string_pretty(&BuildInfo::current())?);
+        return Ok(ExitCode::SUCCESS);
+    }
+    let mut runtime = tokio::runtime::Builder::new_multi_thread();
+    runtime.enable_all();
+    if let Some(n) = args.worker_threads {
+        runtime.worker_threads(n as usize);
+    }
+    if let Some(n) = args.max_blocking_threads {
+        runtime.max_blocking_threads(n as usize);
+    }
+    runtime.build()?.block_on(run(args))
+}
+
+async fn run(mut args: Args) -> Result<ExitCode, Box<dyn std::error::Error>> {
+    rng::init(args.seed);
+    // Both are machine-readable output, so everything that stays quiet for --json applies
+    args.json |= args.json_by_host || args.yaml || args.json_compact;
+    if let Some(level) = args.log_level {
+        init_tracing(level);
+    }
+    let runtime_deadline = args.max_runtime.map(|secs| Instant::now() + Duration::from_secs(secs));
+    JSON_ERRORS.store(args.json_errors, Ordering::Relaxed);
+    SILENT.store(args.silent, Ordering::Relaxed);
+    // --format lines are meant for scripts, so leave out the banner and progress notes too
+    args.quiet |= args.silent || args.format.is_some();
+
+    icons::init(args.ascii, &args.icons);
+    match args.color {
+        ColorChoice::Always => colored::control::set_override(true),
+        ColorChoice::Never => colored::control::set_override(false),
+        ColorChoice::Auto => {}
+    }
+
+    if args.max_response_bytes.is_some() && !args.http && !args.banner {
+        report_error("--max-response-bytes hashes what --http or --banner reads; use it with one of them");
+        std::process::exit(1);
+    }
+
+    if args.result_limit.is_some() && args.format.is_none() && !args.json_compact {
+        report_error("--result-limit prints each result as a --format line or a --json-compact object; use it with one of them");
+        std::process::exit(1);
+    }
+
+    if args.openmetrics && args.serve.is_none() && args.replay.is_none() {
+        report_error("--openmetrics changes what --serve has at /metrics and what --replay prints; use it with one of them");
+        std::process::exit(1);
+    }
+
+    if let Some(path) = args.replay.clone() {
+        return replay(&path, args);
+    }
+
+    if args.doctor {
+        let healthy = doctor::run(args.effective_dns_retries(), Duration::from_millis(args.connect_timeout_ms())).await;
+        return Ok(if healthy { ExitCode::SUCCESS } else { ExitCode::FAILURE });
+    }
+
+    if args.proxy.is_some() && args.ping {
+        report_warning("ICMP can't be sent through a proxy; skipping --ping");
+        args.ping = false;
+    }
+
+    if args.proxy.is_some() && args.quic {
+        report_warning("QUIC runs over UDP, which the proxy doesn't carry; skipping --quic");
+        args.quic = false;
+    }
+
+    if args.icmp_loss_threshold.is_some_and(|p| !(0.0..=100.0).contains(&p)) {
+        report_error("--icmp-loss-threshold must be a percentage between 0 and 100");
+        std::process::exit(1);
+    }
+
+    // Read before anything is printed, so a missing file fails the run at once
+    let payload = (args.probe_payload_file.is_some() || args.expect_bytes.is_some()).then(|| {
+        let send = match args.probe_payload_file.as_deref().map(|path| (path, std::fs::read(path))) {
+            Some((_, Ok(bytes))) => bytes,
+            Some((path, Err(e))) => {
+                report_error(&format!("can't read --probe-payload-file '{}': {}", path, e));
+                std::process::exit(1);
+            }
+            None => Vec::new(),
+        };
+        let expect = match args.expect_bytes.as_deref().map(parse_hex).transpose() {
+            Ok(expect) => expect,
+            Err(e) => {
+                report_error(&format!("invalid --expect-bytes: {}", e));
+                std::process::exit(1);
+            }
+        };
+        (send, expect)
+    });
+
+    if args.adaptive_multiplier.is_nan() || args.adaptive_multiplier < 1.0 {
+        report_error("--adaptive-multiplier must be at least 1 (a timeout shorter than the round trip would always fail)");
+        std::process::exit(1);
+    }
+
+    if args.stop_after_successes.is_some_and(|n| n > args.tcp_attempts().max(args.ping_attempts())) {
+        report_error("--stop-after-successes can't be more than the attempt count (-c)");
+        std::process::exit(1);
+    }
+
+    if args.ramp_ci.is_some() && args.ramp_max < args.tcp_attempts().max(args.ping_attempts()) {
+        report_error("--ramp-max can't be less than the attempt count (-c)");
+        std::process::exit(1);
+    }
+
+    if args.pool && !(args.keepalive || args.echo) {
+        report_error("--pool keeps --keepalive and --echo connections open; use it with one of them");
+        std::process::exit(1);
+    }
+
+    if args.interval_adaptive && args.max_interval < args.interval {
+        report_error("--max-interval can't be shorter than --interval");
+        std::process::exit(1);
+    }
+
+    if args.arp && !arp::SUPPORTED {
+        report_warning("--arp is only supported on Linux; skipping ARP checks");
+        args.arp = false;
+    } else if args.arp {
+        if let Err(e) = arp::check_privileges() {
+            report_error(&format!("--arp needs packet sockets (run as root or grant CAP_NET_RAW): {}", e));
+            std::process::exit(1);
+        }
+    }
+
+    if args.syn && !syn::SUPPORTED {
+        report_warning("--syn is only supported on Linux; falling back to connect scans");
+        args.syn = false;
+    } else if args.syn {
+        if let Err(e) = syn::check_privileges() {
+            report_error(&format!("--syn needs raw sockets (run as root or grant CAP_NET_RAW): {}", e));
+            std::process::exit(1);
+        }
+    }
+
+    if args.icmp_timestamp && !icmp_timestamp::SUPPORTED {
+        report_warning("--icmp-timestamp is only supported on Linux; sending echo requests instead");
+        args.icmp_timestamp = false;
+    } else if args.icmp_timestamp {
+        if let Err(e) = icmp_timestamp::check_privileges() {
+            report_error(&format!("--icmp-timestamp needs raw sockets (run as root or grant CAP_NET_RAW): {}", e));
+            std::process::exit(1);
+        }
+    }
+
+    if args.mdns && !cfg!(feature = "mdns") {
+        report_error("--mdns requires ring to be built with the `mdns` feature (cargo install --features mdns)");
+        std::process::exit(1);
+    }
+
+    if args.quic && !quic::SUPPORTED {
+        report_error("--quic requires ring to be built with the `quic` feature (cargo install --features quic)");
+        std::process::exit(1);
+    }
+
+    let mut store = match args.sqlite.as_deref().map(sqlite::Store::open).transpose() {
+        Ok(store) => store,
+        Err(e) => {
+            report_error(&e);
+            std::process::exit(1);
+        }
+    };
+
+    let geoip = match (!args.geoip.is_empty()).then(|| geoip::GeoIp::open(&args.geoip)).transpose() {
+        Ok(geoip) => geoip,
+        Err(e) => {
+            report_error(&e);
+            std::process::exit(1);
+        }
+    };
+
+    let mut ports = match parse_ports(&args.ports, args.port_step) {
+        Ok(_) if args.no_tcp => Vec::new(),
+        Ok(ports) => ports,
+        Err(e) => {
+            report_error(&e);
+            std::process::exit(1);
+        }
+    };
+
+    if let Some(n) = args.ports_top {
+        match services::top_ports(n as usize) {
+            Ok(top) => {
+                ports.extend(top);
+                ports.sort_unstable();
+                ports.dedup();
+            }
+            Err(e) => {
+                report_error(&e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let http_client = reqwest::Client::builder().timeout(Duration::from_secs(10)).build()?;
+    // The hosts given on the command line, which every --refresh-targets list is added to
+    let cli_hosts = args.hosts.clone();
+    if let Some(url) = args.targets_url.clone() {
+        if let (true, Some(name)) = (args.no_dns, url_host_name(&url)) {
+            report_error(&format!("--no-dns: not IP addresses: {}", name));
+            std::process::exit(1);
+        }
+        match inventory::fetch(&http_client, &url).await {
+            Ok(hosts) => args.hosts.extend(hosts),
+            Err(e) => {
+                report_error(&e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    if args.hosts.is_empty() {
+        report_error("You must provide at least one host!");
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let targets = args
+        .hosts
+        .iter()
+        .map(|h| parse_target(h, &ports, args.port_step))
+        .collect::<Result<Vec<_>, _>>()
+        .and_then(|targets| expand_hosts(targets, &args));
+    let mut targets = match targets {
+        Ok(targets) => targets,
+        Err(e) => {
+            report_error(&format!("invalid host: {}", e));
+            std::process::exit(1);
+        }
+    };
+
+    if args.no_tcp && targets.iter().any(|t| !t.ports.is_empty()) {
+        report_error("--no-tcp can't be combined with per-host ports (host:port)");
+        std::process::exit(1);
+    }
+
+    if !args.ping && !args.arp && !args.quic && targets.iter().all(|t| t.ports.is_empty() && !t.is_uds()) {
+        report_error("You must provide at least one port or enable --ping, --arp or --quic!");
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let expected = match args.expect.as_deref().map(manifest::load_manifest).transpose() {
+        Ok(expected) => expected,
+        Err(e) => {
+            report_error(&e);
+            std::process::exit(1);
+        }
+    };
+
+    if let Some(expected) = &expected {
+        add_expected_ports(&mut targets, expected);
+    }
+
+    if args.no_dns {
+        let mut names: Vec<&str> =
+            targets.iter().filter(|t| !t.is_uds() && t.host.parse::<ScopedIp>().is_err()).map(|t| t.host.as_str()).collect();
+        // The proxy and webhook are connected to by name too
+        if let Some(proxy) = args.proxy.as_ref().filter(|p| p.addr().parse::<SocketAddr>().is_err()) {
+            names.push(proxy.addr());
+        }
+        let webhook_host = args.webhook.as_deref().and_then(url_host_name);
+        names.extend(webhook_host.as_deref());
+        if !names.is_empty() {
+            report_error(&format!("--no-dns: not IP addresses: {}", names.join(", ")));
+            std::process::exit(1);
+        }
+    }
+
+    if args.strict_dns {
+        let unresolved = unresolvable_hosts(&targets, &args).await;
+        if !unresolved.is_empty() {
+            report_error(&format!("--strict-dns: could not resolve {}", unresolved.join(", ")));
+            std::process::exit(1);
+        }
+    }
+
+    if args.dry_run {
+        print_plan(&targets, &args)?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if args.icmp_flood {
+        return icmp_flood(&targets, &args).await;
+    }
+
+    if !args.json && !args.quiet {
+        println!(
+            "\n{} Hosts: [{}]{}{}{}{}",
+            format!("{}Scanning", icons::lead("🔍")).bold(),
+                 args.hosts.join(", ").green(),
+                 if !ports.is_empty() {
+                     format!(", Ports: [{}]", format_port_ranges(&ports).yellow())
+                 } else {
+                     String::new()
+                 },
+                 if args.ping { format!("{}", ", ICMP Ping: enabled".magenta()) } else { String::new() },
+                 if args.arp { format!("{}", ", ARP: enabled".magenta()) } else { String::new() },
+                 if args.quic { format!("{}", format!(", QUIC: UDP {}", args.quic_port).magenta()) } else { String::new() }
+        );
+        println!("{}", icons::rule().dimmed());
+    }
+
+    let baseline = match args.baseline.as_deref().map(diff::load_baseline).transpose() {
+        Ok(baseline) => baseline,
+        Err(e) => {
+            report_error(&e);
+            std::process::exit(1);
+        }
+    };
+    let scan_config = ScanConfig::from_args(&args);
+    // Output from before the config was recorded can't be checked
+    if let Some(recorded) = baseline.as_ref().and_then(|b| b.config.as_ref()) {
+        if *recorded != scan_config {
+            report_warning("--baseline: the baseline was scanned with different settings, so not every change is the network's");
+        }
+    }
+
+    // Every probe holds a socket open at the same time, so large scans can exhaust the fd limit
+    let per_host = usize::from(args.ping) + usize::from(args.arp) + usize::from(args.quic);
+    let port_limit = [args.port_concurrency, args.batch_size].into_iter().flatten().min().map_or(usize::MAX, |n| n as usize);
+    let mut in_flight: Vec<usize> = targets
+        .iter()
+        .map(|t| if t.is_uds() { 1 } else { t.ports.len().min(port_limit) + per_host })
+        .collect();
+    // With --concurrency, at worst the busiest hosts are the ones running together
+    in_flight.sort_unstable_by(|a, b| b.cmp(a));
+    let task_count = in_flight
+        .into_iter()
+        .take(args.concurrency.map_or(usize::MAX, |n| n as usize))
+        .sum::<usize>()
+        .min(args.max_in_flight.map_or(usize::MAX, |n| n as usize));
+    if task_count >= 256 {
+        if let Some(limit) = fd_soft_limit() {
+            if task_count as u64 + 64 > limit {
+                report_warning(&format!(
+                    "{} probes will run concurrently but the open file limit is {}; expect too_many_open_files errors (raise it with `ulimit -n`, or lower --concurrency or --max-in-flight)",
+                    task_count, limit
+                ));
+            }
+        }
+    }
+
+    if let Some(dscp) = args.dscp {
+        if let Err(e) = socket::validate_dscp(dscp) {
+            report_error(&format!("the OS rejected DSCP value {}: {}", dscp, e));
+            std::process::exit(1);
+        }
+    }
+
+    if let Some(mark) = args.fwmark {
+        if let Err(e) = socket::validate_mark(mark) {
+            report_error(&format!("can't set --fwmark {}: {}", mark, e));
+            std::process::exit(1);
+        }
+    }
+
+    if args.tfo {
+        if let Err(e) = socket::check_fastopen() {
+            report_warning(&format!("TCP Fast Open unavailable ({}); connecting normally", e));
+            args.tfo = false;
+        }
+    }
+
+    if args.mtu_discover && !cfg!(target_os = "linux") {
+        report_warning("--mtu-discover is only supported on Linux; skipping");
+        args.mtu_discover = false;
+    }
+    if args.ping_df && !cfg!(target_os = "linux") {
+        report_warning("--ping-df is only supported on Linux; pinging without it");
+        args.ping_df = false;
+    }
+
+    let probe_opts = ProbeOptions { payload, ..ProbeOptions::from_args(&args) };
+    let mut exit_code = ExitCode::SUCCESS;
+
+    // With --wait-up: Some(deadline), where the deadline itself is optional
+    let wait_deadline = args
+        .wait_up
+        .then(|| args.wait_timeout.map(|ms| Instant::now() + Duration::from_millis(ms)));
+    let mut wait_attempt = 1;
+
+    let mut tracker = watch::StatusTracker::default();
+    let mut changes = args.only_changed.then(watch::ChangeTracker::default);
+    let mut ewma = args.ewma.then(|| watch::EwmaTracker::new(args.ewma_alpha));
+    let mut histogram_samples: Vec<u128> = Vec::new();
+    let checkpoint = match args.resume_file.as_deref().map(resume::Checkpoint::load).transpose() {
+        Ok(checkpoint) => checkpoint,
+        Err(e) => {
+            report_error(&e);
+            std::process::exit(1);
+        }
+    };
+    if let (Some(checkpoint), false) = (&checkpoint, args.json || args.quiet) {
+        if checkpoint.completed_count() > 0 {
+            println!("{}", format!("{}Resuming: {} probes already completed", icons::lead("↩️ "), checkpoint.completed_count()).dimmed());
+        }
+    }
+    let checkpoint = checkpoint.map(Mutex::new);
+    let scan_log = args.log_file.as_ref().map(|path| log::ScanLog::new(path, args.log_rotate_size));
+    let host_logs = args.output_dir.as_ref().map(log::HostLogs::new);
+    let output_file = args.output.as_ref().map(|path| output::OutputFile::new(path, args.output_format, "output file"));
+    // Written after every scan, so however the run ends the file holds its last scan
+    let baseline_file =
+        args.baseline_save.as_ref().map(|path| output::OutputFile::new(path, OutputFormat::Json, "baseline"));
+    let summary_file =
+        args.summary_json_to.as_ref().map(|path| output::OutputFile::new(path, OutputFormat::Json, "summary file"));
+    let latest_scan: serve::LatestScan = Arc::default();
+    if let Some(addr) = args.serve {
+        if let Err(e) = serve::spawn(addr, latest_scan.clone(), args.openmetrics).await {
+            report_error(&format!("--serve couldn't listen on {}: {}", addr, e));
+            std::process::exit(1);
+        }
+        if !args.json && !args.quiet {
+            println!("{}", format!("{}Serving http://{}/status and /metrics", icons::lead("🌐"), addr).dimmed());
+        }
+    }
+
+    let mut dashboard = if args.dashboard {
+        if !std::io::stdout().is_terminal() {
+            report_error("--dashboard needs a terminal to draw on");
+            std::process::exit(1);
+        }
+        let dashboard = dashboard::Dashboard::enter()?;
+        tokio::spawn(async {
+            let _ = tokio::signal::ctrl_c().await;
+            dashboard::restore();
+            std::process::exit(130);
+        });
+        Some(dashboard)
+    } else {
+        None
+    };
+
+    let stream = args.result_limit.map(|limit| stream::ResultStream::new(&args, limit, geoip.as_ref()));
+    let probe_deadline = runtime_deadline.map(|d| d + MAX_RUNTIME_GRACE);
+    let out_of_time = || runtime_deadline.is_some_and(|d| Instant::now() >= d);
+    let mut first_scan = true;
+    let mut stability = args.flaky_detector.map(|_| stability::StabilityTracker::default());
+    // --alert-command runs still going, which a run that is about to end waits for
+    let mut alerts: Vec<tokio::task::JoinHandle<()>> = Vec::new();
+    // The delay --interval-adaptive has backed off to, while every target has stayed up
+    let mut calm_interval: Option<u64> = None;
+    // The --min-interval-drift-correction slot the current scan started in; the first scan waits
+    // for one too, so every timestamp is on the grid
+    let mut slot = None;
+    if args.align_interval {
+        let (first, wait) = next_slot(args.interval);
+        tokio::time::sleep(wait).await;
+        slot = Some(first);
+    }
+
+    loop {
+        if out_of_time() {
+            exit_code = max_runtime_reached(&args);
+            break;
+        }
+
+        if let (false, true, Some(url)) = (first_scan, args.refresh_targets, &args.targets_url) {
+            let refreshed = inventory::fetch(&http_client, url).await.and_then(|hosts| {
+                let hosts: Vec<String> = cli_hosts.iter().cloned().chain(hosts).collect();
+                refreshed_targets(&hosts, &ports, &args, expected.as_ref())
+            });
+            match refreshed {
+                Ok(refreshed) => targets = refreshed,
+                Err(e) => report_warning(&format!("{}; scanning the last list again", e)),
+            }
+        }
+
+        let scan_started = Instant::now();
+        let mut all_results = run_scan(&targets, &args, &probe_opts, checkpoint.as_ref(), stream.as_ref(), probe_deadline).await;
+        let down = |r: &HostResult| r.status == "down" || r.status == "filtered";
+        if args.rescan_on_total_failure && !all_results.is_empty() && all_results.iter().all(down) && !out_of_time() {
+            if !args.json && !args.quiet {
+                let delay = format_interval(TOTAL_FAILURE_DELAY.as_millis() as u64);
+                println!("{}", format!("{}Every result is down; scanning again in {} in case it was a local blip", icons::lead("🔁"), delay).dimmed());
+            }
+            let pause = probe_deadline
+                .map_or(TOTAL_FAILURE_DELAY, |d| TOTAL_FAILURE_DELAY.min(d.saturating_duration_since(Instant::now())));
+            tokio::time::sleep(pause).await;
+            // Not checkpointed: --resume-file already holds this scan's probes, and would hand
+            // back the same failures
+            all_results = run_scan(&targets, &args, &probe_opts, None, None, probe_deadline).await;
+        }
+        if args.rescan_failures > 0 {
+            rescan_failures(&mut all_results, &args, &probe_opts, probe_deadline).await;
+        }
+        // A --result-limit stream has already finished and printed each result
+        if stream.is_none() {
+            all_results.iter_mut().for_each(HostResult::collect_errors);
+        }
+        if probe_opts.retry_budget_refused() {
+            report_warning(&format!(
+                "the --retry-budget of {} ran out; later failures weren't retried",
+                args.retry_budget.unwrap_or_default()
+            ));
+        }
+        if let Some(checkpoint) = &checkpoint {
+            checkpoint.lock().unwrap().finish();
+        }
+        if let (Some(geoip), None) = (&geoip, &stream) {
+            geoip.annotate(&mut all_results);
+        }
+        if args.probe_label_from_ptr {
+            label_from_ptr(&mut all_results, &probe_opts.resolver, args.connect_timeout_ms()).await;
+        }
+        if stream.as_ref().map_or_else(|| apply_thresholds(&mut all_results, &args), |s| s.exceeded()) {
+            exit_code = ExitCode::from(EXIT_THRESHOLD);
+        }
+        if let Some(ewma) = &mut ewma {
+            ewma.update(&mut all_results);
+        }
+        if stream.as_ref().map_or_else(|| check_assertions(&mut all_results, &args.assertions), |s| s.failed()) {
+            exit_code = ExitCode::from(EXIT_ASSERTION);
+        }
+
+        if let Some(deadline) = wait_deadline {
+            let up = all_results.iter().filter(|r| r.status == "up").count();
+            let all_up = up == all_results.len();
+            let timed_out = deadline.is_some_and(|d| Instant::now() >= d);
+
+            if !args.json && !args.quiet {
+                let pending: Vec<String> = all_results
+                    .iter()
+                    .filter(|r| r.status != "up")
+                    .map(|r| match r.port {
+                        Some(port) => format!("{}:{}", r.host, port),
+                        None => format!("{} ({})", r.host, r.test_type.to_uppercase()),
+                    })
+                    .collect();
+                println!(
+                    "{}{} Attempt {}: {}/{} up{}",
+                    icons::lead("⏳"),
+                    format!("[{}]", format_timestamp(args.utc)).dimmed(),
+                    wait_attempt,
+                    up,
+                    all_results.len(),
+                    if pending.is_empty() { String::new() } else { format!(" (waiting on {})", pending.join(", ")).dimmed().to_string() }
+                );
+            }
+
+            if !all_up && !timed_out {
+                wait_attempt += 1;
+                let mut pause = jittered(Duration::from_millis(args.interval), args.timeout_jitter);
+                for d in deadline.iter().chain(&runtime_deadline) {
+                    pause = pause.min(d.saturating_duration_since(Instant::now()));
+                }
+                tokio::time::sleep(pause).await;
+                continue;
+            }
+
+            if !all_up {
+                exit_code = ExitCode::from(EXIT_WAIT_TIMEOUT);
+            }
+        }
+
+        let drift = expected.as_ref().map(|m| manifest::check_drift(m, &all_results));
+        if drift.as_ref().is_some_and(|d| !d.is_empty()) {
+            exit_code = ExitCode::from(EXIT_DRIFT);
+        }
+        if let Some(limit) = args.fail_threshold_percent {
+            if fleet_down(&all_results, limit) && exit_code == ExitCode::SUCCESS {
+                exit_code = ExitCode::from(EXIT_FLEET_DOWN);
+            }
+        } else if args.silent && exit_code == ExitCode::SUCCESS && all_results.iter().any(|r| r.status != "up") {
+            exit_code = ExitCode::from(EXIT_NOT_UP);
+        }
+
+        let histogram = args.histogram.then(|| {
+            histogram_samples.extend(all_results.iter().flat_map(|r| r.response_times.iter().copied()));
+            histogram::build(&histogram_samples, &args.hist_buckets.clone().unwrap_or_default())
+        });
+
+        // On the --min-interval-drift-correction grid a scan is stamped with the slot it started in
+        let scan_time = match slot {
+            Some(n) => UNIX_EPOCH + Duration::from_millis(n * args.interval),
+            None => SystemTime::now(),
+        };
+        let scan_timestamp = format_time(scan_time, args.utc);
+        let scan_epoch = scan_time.duration_since(UNIX_EPOCH)?.as_secs();
+
+        let transitions = tracker.update(&all_results, &scan_timestamp);
+        if let Some(command) = &args.alert_command {
+            let is_down = |status: &str| status == "down" || status == "filtered";
+            let alerting: Vec<&HostResult> = if args.once {
+                all_results.iter().filter(|r| is_down(&r.status)).collect()
+            } else {
+                transitions
+                    .iter()
+                    .filter(|t| is_down(&t.new_status))
+                    .filter_map(|t| {
+                        all_results.iter().find(|r| {
+                            r.host == t.host && r.port == t.port && r.test_type == t.test_type && r.resolved_addr == t.resolved_addr
+                        })
+                    })
+                    .collect()
+            };
+            alerts.retain(|alert| !alert.is_finished());
+            alerts.extend(watch::run_alert_commands(command, &alerting));
+        }
+
+        let anomalies = anomaly::detect(&all_results);
+        let scan_result = ScanResult {
+            schema_version: SCHEMA_VERSION,
+            scan_timestamp,
+            scan_epoch,
+            tool_version: env!("CARGO_PKG_VERSION").to_string(),
+            config: Some(scan_config.clone()),
+            tags: args.tags.iter().cloned().collect(),
+            results: all_results,
+            results_dropped: stream.as_ref().map(|stream| stream.dropped() as u64),
+            drift,
+            histogram,
+            anomalies,
+        };
+        if let Some(log) = &scan_log {
+            log.append(&scan_result);
+        }
+        if let Some(host_logs) = &host_logs {
+            host_logs.append(&scan_result);
+        }
+        if let Some(store) = &mut store {
+            store.insert(&scan_result);
+        }
+        if let Some(output) = &output_file {
+            output.write(&scan_result);
+        }
+        if let Some(baseline_file) = &baseline_file {
+            baseline_file.write(&scan_result);
+        }
+        if let Some(summary_file) = &summary_file {
+            summary_file.write_summary(&scan_result, scan_started.elapsed());
+        }
+
+        // Output results
+        if let Some(stability) = &mut stability {
+            // Only the report at the end is printed
+            stability.record(&scan_result.results);
+            if !args.json && !args.yaml && !args.quiet && !args.silent {
+                let up = scan_result.results.iter().filter(|r| r.status == "up").count();
+                println!(
+                    "{}",
+                    format!(
+                        "{}Scan {}/{}: {}/{} up",
+                        icons::lead("🎲"),
+                        stability.scans(),
+                        args.flaky_detector.unwrap_or_default(),
+                        up,
+                        scan_result.results.len()
+                    )
+                    .dimmed()
+                );
+            }
+        } else if args.until_change {
+            // Only changes are printed; the first scan just sets the starting point
+            if first_scan && !args.json && !args.quiet {
+                let up = scan_result.results.iter().filter(|r| r.status == "up").count();
+                println!(
+                    "{}",
+                    format!("{}Watching {} targets for a status change ({} up now)", icons::lead("👀"), scan_result.results.len(), up).dimmed()
+                );
+            }
+        } else if let Some(baseline) = &baseline {
+            let scan_diff = diff::compute_diff(
+                baseline,
+                &scan_result.results,
+                &scan_result.scan_timestamp,
+                args.latency_tolerance,
+                args.baseline_tolerance,
+            );
+            if scan_diff.has_regressions() {
+                exit_code = ExitCode::from(EXIT_REGRESSION);
+            }
+            if args.yaml {
+                print!("---\n{}", serde_yaml::to_string(&scan_diff)?);
+            } else if args.json {
+                println!("{}", args.to_json(&scan_diff)?);
+            } else if !args.silent {
+                diff::print_diff(&scan_diff);
+            }
+        } else if args.json_by_host {
+            println!("{}", args.to_json(&args.scan_output(&GroupedScanResult::new(&scan_result))?)?);
+        } else if args.yaml {
+            // One document per scan, so continuous output is still a valid YAML stream
+            print!("---\n{}", serde_yaml::to_string(&args.scan_output(&scan_result)?)?);
+        } else if args.json {
+            println!("{}", args.to_json(&args.scan_output(&scan_result)?)?);
+        } else if stream.is_some() {
+            // Every --format line was printed as its probe finished
+        } else if let Some(dashboard) = &mut dashboard {
+            dashboard.draw(&scan_result.results, &scan_result.scan_timestamp)?;
+        } else if args.silent {
+            // Nothing to print; the exit status is the result
+        } else if args.summary_only {
+            print_summary_line(&scan_result.results, &scan_result.scan_timestamp, &args);
+        } else if let Some(changes) = &mut changes {
+            print_changes(&scan_result, changes, &args);
+        } else {
+            print_scan(&scan_result, &args);
+        }
+
+        let all_up = scan_result.results.iter().all(|r| r.status == "up");
+        if args.serve.is_some() {
+            latest_scan.write().unwrap().record(scan_result);
+        }
+
+        let changed = !transitions.is_empty();
+        if changed {
+            if args.until_change && args.yaml {
+                print!("---\n{}", serde_yaml::to_string(&transitions)?);
+            } else if args.until_change && args.json {
+                println!("{}", args.to_json(&transitions)?);
+            } else if !args.json && !args.summary_only && !args.silent && !args.dashboard && stability.is_none() {
+                watch::print_transitions(&transitions);
+            }
+            if let Some(url) = &args.webhook {
+                watch::send_webhooks(&http_client, url, transitions);
+            }
+        }
+
+        if args.once || args.wait_up || (args.until_change && changed && !args.until_change_continuous) {
+            if out_of_time() {
+                exit_code = max_runtime_reached(&args);
+            }
+            break;
+        }
+        if let Some(stability) = &stability {
+            if Some(stability.scans()) == args.flaky_detector {
+                break;
+            }
+            // The scans run back to back
+            continue;
+        }
+
+        let interval = match calm_interval {
+            Some(ms) if all_up => ms.saturating_mul(2).min(args.max_interval),
+            _ => args.interval,
+        };
+        calm_interval = (args.interval_adaptive && all_up).then_some(interval);
+
+        let mut pause = match slot {
+            Some(current) => {
+                let (next, wait) = next_slot(interval);
+                if next > current + 1 {
+                    let skipped = next - current - 1;
+                    report_warning(&format!(
+                        "the scan took longer than --interval; skipping {} {}",
+                        skipped,
+                        if skipped == 1 { "beat" } else { "beats" }
+                    ));
+                }
+                slot = Some(next);
+                wait
+            }
+            None => jittered(Duration::from_millis(interval), args.timeout_jitter),
+        };
+        if !args.json && !args.quiet && !args.summary_only && !args.until_change && !args.dashboard && !args.only_changed {
+            // On the grid the wait is whatever is left of the interval
+            let wait = if slot.is_some() { pause.as_millis() as u64 } else { interval };
+            println!("\n{}Waiting {} before next scan...\n", icons::lead("⏱️ "), format_interval(wait));
+        }
+        first_scan = false;
+        if let Some(d) = runtime_deadline {
+            pause = pause.min(d.saturating_duration_since(Instant::now()));
+        }
+        tokio::time::sleep(pause).await;
+    }
+
+    future::join_all(alerts).await;
+
+    if let Some(stability) = &stability {
+        let report = stability.report();
+        if args.yaml {
+            print!("---\n{}", serde_yaml::to_string(&report)?);
+        } else if args.json {
+            println!("{}", args.to_json(&report)?);
+        } else if !args.silent {
+            stability::print_report(&report);
+        }
+    }
+
+    Ok(exit_code)
 }
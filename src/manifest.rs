@@ -0,0 +1,90 @@
+use crate::{icons, HostResult};
+use colored::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Host → ports that are allowed (and expected) to be open
+pub type Manifest = HashMap<String, Vec<u16>>;
+
+/// Load a manifest from JSON (`{"host": [22, 443]}`) or, for `.toml` files, TOML (`"host" = [22, 443]`)
+pub fn load_manifest(path: &str) -> Result<Manifest, String> {
+    let contents = std::fs::read_to_string(path).map_err(|e| format!("failed to read manifest '{}': {}", path, e))?;
+
+    if path.ends_with(".toml") {
+        toml::from_str(&contents).map_err(|e| format!("failed to parse manifest '{}': {}", path, e))
+    } else {
+        serde_json::from_str(&contents).map_err(|e| format!("failed to parse manifest '{}': {}", path, e))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct DriftEntry {
+    pub host: String,
+    pub port: u16,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct DriftReport {
+    pub unexpected_open: Vec<DriftEntry>,
+    pub expected_missing: Vec<DriftEntry>,
+}
+
+impl DriftReport {
+    pub fn is_empty(&self) -> bool {
+        self.unexpected_open.is_empty() && self.expected_missing.is_empty()
+    }
+}
+
+/// Compare TCP (connect or SYN) results against the manifest. Hosts that aren't listed in the manifest are ignored.
+/// A port counts as open when at least one attempt connected.
+pub fn check_drift(manifest: &Manifest, results: &[HostResult]) -> DriftReport {
+    let mut report = DriftReport::default();
+
+    for result in results.iter().filter(|r| r.test_type == "tcp" || r.test_type == "syn") {
+        let (Some(allowed), Some(port)) = (manifest.get(&result.host), result.port) else {
+            continue;
+        };
+
+        let open = result.successful > 0;
+        let expected = allowed.contains(&port);
+        let entry = DriftEntry { host: result.host.clone(), port };
+
+        if open && !expected {
+            report.unexpected_open.push(entry);
+        } else if !open && expected {
+            report.expected_missing.push(entry);
+        }
+    }
+
+    report
+}
+
+pub fn print_drift(report: &DriftReport) {
+    println!("\n{}Manifest Drift", icons::lead("📋"));
+    println!("{}", icons::rule().dimmed());
+
+    if report.is_empty() {
+        println!("{}", "All scanned ports match the manifest".green());
+        return;
+    }
+
+    for entry in &report.unexpected_open {
+        println!(
+            "{} {}:{} {}",
+            icons::pick("🚨", "[OPEN]"),
+            entry.host.blue(),
+            entry.port.to_string().yellow(),
+            "open but not in manifest".red()
+        );
+    }
+
+    for entry in &report.expected_missing {
+        println!(
+            "{} {}:{} {}",
+            icons::pick("🚫", "[CLOSED]"),
+            entry.host.blue(),
+            entry.port.to_string().yellow(),
+            "expected open but closed".red()
+        );
+    }
+}
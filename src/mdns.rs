@@ -0,0 +1,47 @@
+use std::net::IpAddr;
+
+/// Whether `host` should be resolved over multicast DNS rather than the system resolver
+pub fn is_mdns_host(host: &str) -> bool {
+    let host = host.trim_end_matches('.');
+    host.len() > ".local".len() && host.to_ascii_lowercase().ends_with(".local")
+}
+
+/// Resolve a `.local` name over mDNS, returning the first address announced within `timeout_ms`
+#[cfg(feature = "mdns")]
+pub async fn resolve(host: &str, timeout_ms: u64) -> Option<IpAddr> {
+    use mdns_sd::{HostnameResolutionEvent, ServiceDaemon};
+    use std::sync::OnceLock;
+    use std::time::Duration;
+
+    // One daemon (and its multicast sockets) shared by every probe
+    static DAEMON: OnceLock<Option<ServiceDaemon>> = OnceLock::new();
+    let daemon = DAEMON.get_or_init(|| ServiceDaemon::new().ok()).as_ref()?;
+
+    let hostname = format!("{}.", host.trim_end_matches('.'));
+    let events = daemon.resolve_hostname(&hostname, Some(timeout_ms)).ok()?;
+
+    let wait = async {
+        while let Ok(event) = events.recv_async().await {
+            match event {
+                HostnameResolutionEvent::AddressesFound(_, addrs) => {
+                    // Prefer IPv4 when both families are announced
+                    let mut addrs: Vec<IpAddr> = addrs.iter().map(|a| a.to_ip_addr()).collect();
+                    addrs.sort_by_key(|a| a.is_ipv6());
+                    return addrs.into_iter().next();
+                }
+                HostnameResolutionEvent::SearchTimeout(_) | HostnameResolutionEvent::SearchStopped(_) => return None,
+                _ => {}
+            }
+        }
+        None
+    };
+
+    let found = tokio::time::timeout(Duration::from_millis(timeout_ms), wait).await.ok().flatten();
+    let _ = daemon.stop_resolve_hostname(&hostname);
+    found
+}
+
+#[cfg(not(feature = "mdns"))]
+pub async fn resolve(_host: &str, _timeout_ms: u64) -> Option<IpAddr> {
+    None
+}
@@ -0,0 +1,142 @@
+use crate::{report_warning, GroupedScanResult, HostResult, OutputFormat, ScanResult};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// The --output file: the latest scan in --output-format, whatever stdout is showing. The
+/// --baseline-save file is one too, always in JSON, and so is --summary-json-to, which holds
+/// only the scan's totals.
+#[derive(Debug)]
+pub struct OutputFile {
+    path: PathBuf,
+    format: OutputFormat,
+    /// Names the file in warnings
+    what: &'static str,
+}
+
+impl OutputFile {
+    pub fn new(path: impl Into<PathBuf>, format: OutputFormat, what: &'static str) -> Self {
+        OutputFile { path: path.into(), format, what }
+    }
+
+    fn render(&self, scan: &ScanResult) -> Result<String, String> {
+        match self.format {
+            OutputFormat::Json => serde_json::to_string_pretty(scan).map_err(|e| e.to_string()),
+            OutputFormat::JsonByHost => {
+                serde_json::to_string_pretty(&GroupedScanResult::new(scan)).map_err(|e| e.to_string())
+            }
+            OutputFormat::Yaml => serde_yaml::to_string(scan).map_err(|e| e.to_string()),
+        }
+    }
+
+    /// Replace the file's contents with `scan`.
+    pub fn write(&self, scan: &ScanResult) {
+        self.replace(self.render(scan));
+    }
+
+    /// Replace the file's contents with `scan`'s totals, as JSON
+    pub fn write_summary(&self, scan: &ScanResult, duration: Duration) {
+        self.replace(serde_json::to_string_pretty(&Summary::new(scan, duration)).map_err(|e| e.to_string()));
+    }
+
+    /// The new contents go to a temporary file that is then renamed over the old one, so a
+    /// reader never sees half a scan. Failures are reported but never stop monitoring.
+    fn replace(&self, rendered: Result<String, String>) {
+        let mut temp = self.path.clone().into_os_string();
+        temp.push(".tmp");
+        let temp = Path::new(&temp);
+
+        let written = rendered.and_then(|contents| {
+            std::fs::write(temp, contents + "\n")
+                .and_then(|()| std::fs::rename(temp, &self.path))
+                .map_err(|e| e.to_string())
+        });
+        if let Err(e) = written {
+            report_warning(&format!("failed to write {} '{}': {}", self.what, self.path.display(), e));
+        }
+    }
+}
+
+/// How many results had each status
+#[derive(Serialize, Default)]
+struct Counts {
+    total: usize,
+    up: usize,
+    partial: usize,
+    down: usize,
+    #[serde(skip_serializing_if = "is_zero")]
+    filtered: usize, // --timeout-as-filtered only
+}
+
+fn is_zero(n: &usize) -> bool {
+    *n == 0
+}
+
+impl Counts {
+    fn add(&mut self, result: &HostResult) {
+        self.total += 1;
+        match result.status.as_str() {
+            "up" => self.up += 1,
+            "partial" => self.partial += 1,
+            "filtered" => self.filtered += 1,
+            _ => self.down += 1,
+        }
+    }
+}
+
+/// --summary-json-to: a scan without its results, i.e. its metadata and the counts the human
+/// summary's `Total:` lines show
+#[derive(Serialize)]
+struct Summary<'a> {
+    schema_version: u32,
+    scan_timestamp: &'a str,
+    scan_epoch: u64,
+    tool_version: &'a str,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    tags: &'a BTreeMap<String, String>,
+    duration_ms: f64, // probing and everything after it, up to writing the summary
+    #[serde(flatten)]
+    counts: Counts,
+    by_test_type: BTreeMap<&'a str, Counts>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    drift: Option<bool>, // --expect only: whether any port didn't match the manifest
+    #[serde(skip_serializing_if = "is_zero")]
+    anomalies: usize,
+}
+
+impl<'a> Summary<'a> {
+    fn new(scan: &'a ScanResult, duration: Duration) -> Self {
+        let mut counts = Counts::default();
+        let mut by_test_type: BTreeMap<&str, Counts> = BTreeMap::new();
+        for result in &scan.results {
+            counts.add(result);
+            by_test_type.entry(&result.test_type).or_default().add(result);
+        }
+        Summary {
+            schema_version: scan.schema_version,
+            scan_timestamp: &scan.scan_timestamp,
+            scan_epoch: scan.scan_epoch,
+            tool_version: &scan.tool_version,
+            tags: &scan.tags,
+            duration_ms: duration.as_secs_f64() * 1000.0,
+            counts,
+            by_test_type,
+            drift: scan.drift.as_ref().map(|d| !d.is_empty()),
+            anomalies: scan.anomalies.len(),
+        }
+    }
+}
+
+/// Read a scan saved with --json or --output, for --baseline and --replay. `what` names the file
+/// in errors.
+pub fn load_scan(path: &str, what: &str) -> Result<ScanResult, String> {
+    let contents = std::fs::read_to_string(path).map_err(|e| format!("failed to read {} '{}': {}", what, path, e))?;
+    let value: serde_json::Value =
+        serde_json::from_str(&contents).map_err(|e| format!("failed to parse {} '{}': {}", what, path, e))?;
+    // Latencies saved with --unit us or s would be read as milliseconds
+    if let Some(unit) = value.get("unit").and_then(|u| u.as_str()).filter(|u| *u != "ms") {
+        return Err(format!("{} '{}' was saved with --unit {}; save it without --unit", what, path, unit));
+    }
+    serde_json::from_value(value).map_err(|e| format!("failed to parse {} '{}': {}", what, path, e))
+}
@@ -0,0 +1,83 @@
+use std::net::SocketAddr;
+
+/// Whether this build can send QUIC handshakes
+pub const SUPPORTED: bool = cfg!(feature = "quic");
+
+/// QUIC version 1 (RFC 9000), the version ring offers
+pub const QUIC_V1: u32 = 1;
+
+/// Why a handshake didn't complete
+#[derive(Debug)]
+#[cfg_attr(not(feature = "quic"), allow(dead_code))]
+pub enum Failure {
+    /// The server answered, but the handshake still failed: no common version, a TLS alert,
+    /// a certificate that didn't verify, or a server that doesn't speak HTTP/3
+    Rejected(String),
+    /// Nothing came back
+    NoResponse,
+    /// The local endpoint couldn't be set up
+    Local(String),
+}
+
+/// "v1" for the published version, hex for drafts and anything else
+pub fn version_name(version: u32) -> String {
+    match version {
+        QUIC_V1 => "v1".to_string(),
+        other => format!("{:#010x}", other),
+    }
+}
+
+#[cfg(feature = "quic")]
+fn client_config() -> quinn::ClientConfig {
+    use quinn::crypto::rustls::QuicClientConfig;
+    use quinn::rustls::{ClientConfig, RootCertStore};
+    use std::sync::{Arc, OnceLock};
+
+    static CONFIG: OnceLock<quinn::ClientConfig> = OnceLock::new();
+    CONFIG
+        .get_or_init(|| {
+            let roots = RootCertStore { roots: webpki_roots::TLS_SERVER_ROOTS.to_vec() };
+            let mut tls = ClientConfig::builder().with_root_certificates(roots).with_no_client_auth();
+            tls.alpn_protocols = vec![b"h3".to_vec()];
+            let crypto = QuicClientConfig::try_from(tls).expect("the default rustls provider supports QUIC");
+            quinn::ClientConfig::new(Arc::new(crypto))
+        })
+        .clone()
+}
+
+/// Complete a QUIC handshake with `addr`, offering HTTP/3 and checking the certificate against
+/// `server_name`, and return the negotiated version. The caller bounds how long this may take.
+#[cfg(feature = "quic")]
+pub async fn handshake(addr: SocketAddr, server_name: &str) -> Result<u32, Failure> {
+    use quinn::{ConnectionError, Endpoint};
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    let bind: SocketAddr = match addr {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let mut endpoint = Endpoint::client(bind).map_err(|e| Failure::Local(format!("quic_error: {}", e)))?;
+    endpoint.set_default_client_config(client_config());
+    let connecting = endpoint
+        .connect(addr, server_name)
+        .map_err(|e| Failure::Local(format!("quic_error: {}", e)))?;
+
+    let result = match connecting.await {
+        Ok(connection) => {
+            connection.close(0u32.into(), b"");
+            // Only version 1 is offered, so a completed handshake negotiated it
+            Ok(QUIC_V1)
+        }
+        Err(ConnectionError::TimedOut) => Err(Failure::NoResponse),
+        Err(e) => Err(Failure::Rejected(format!("quic_handshake_failed: {}", e))),
+    };
+    // Let the endpoint send the CONNECTION_CLOSE in the background instead of making the
+    // probe wait out the draining period
+    tokio::spawn(async move { endpoint.wait_idle().await });
+    result
+}
+
+#[cfg(not(feature = "quic"))]
+pub async fn handshake(_addr: SocketAddr, _server_name: &str) -> Result<u32, Failure> {
+    Err(Failure::Local("quic_error: ring was built without the `quic` feature".to_string()))
+}
@@ -0,0 +1,99 @@
+use crate::{report_warning, HostResult};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+/// How often completed probes are flushed to the resume file
+const FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Identifies one unit of scan work: a TCP probe of host:port, a host's ICMP ping, or a
+/// host's --first-success sweep
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ProbeKey {
+    pub host: String,
+    pub port: Option<u16>,
+    pub kind: String,
+}
+
+impl ProbeKey {
+    pub fn new(host: &str, port: Option<u16>, kind: &str) -> Self {
+        ProbeKey { host: host.to_string(), port, kind: kind.to_string() }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct Saved {
+    completed: Vec<(ProbeKey, Vec<HostResult>)>,
+}
+
+/// Completed probes of an interrupted scan, persisted so a re-run can pick up where it stopped
+#[derive(Debug)]
+pub struct Checkpoint {
+    path: PathBuf,
+    completed: HashMap<ProbeKey, Vec<HostResult>>,
+    last_flush: Option<Instant>,
+}
+
+impl Checkpoint {
+    /// Load the resume file, or start empty when it doesn't exist yet
+    pub fn load(path: impl Into<PathBuf>) -> Result<Self, String> {
+        let path = path.into();
+        let completed = match std::fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str::<Saved>(&contents)
+                .map_err(|e| format!("failed to parse resume file '{}': {}", path.display(), e))?
+                .completed
+                .into_iter()
+                .collect(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(format!("failed to read resume file '{}': {}", path.display(), e)),
+        };
+        Ok(Checkpoint { path, completed, last_flush: None })
+    }
+
+    pub fn completed_count(&self) -> usize {
+        self.completed.len()
+    }
+
+    /// Results recorded for `key` by an earlier, interrupted run
+    pub fn get(&self, key: &ProbeKey) -> Option<Vec<HostResult>> {
+        self.completed.get(key).cloned()
+    }
+
+    /// Remember a finished probe, writing the file out if it hasn't been for a while
+    pub fn record(&mut self, key: ProbeKey, results: &[HostResult]) {
+        self.completed.insert(key, results.to_vec());
+        if self.last_flush.is_none_or(|t| t.elapsed() >= FLUSH_INTERVAL) {
+            self.flush();
+        }
+    }
+
+    /// Write the file atomically (temp file + rename) so a kill mid-write can't corrupt it
+    fn flush(&mut self) {
+        self.last_flush = Some(Instant::now());
+        let saved = Saved {
+            completed: self.completed.iter().map(|(k, v)| (k.clone(), v.clone())).collect(),
+        };
+
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+        let written = serde_json::to_string(&saved)
+            .map_err(std::io::Error::from)
+            .and_then(|json| std::fs::write(&tmp, json))
+            .and_then(|_| std::fs::rename(&tmp, &self.path));
+        if let Err(e) = written {
+            report_warning(&format!("failed to write resume file '{}': {}", self.path.display(), e));
+        }
+    }
+
+    /// The scan completed: forget everything and remove the file
+    pub fn finish(&mut self) {
+        self.completed.clear();
+        self.last_flush = None;
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                report_warning(&format!("failed to remove resume file '{}': {}", self.path.display(), e));
+            }
+        }
+    }
+}
@@ -0,0 +1,22 @@
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::sync::{Mutex, OnceLock};
+
+/// The one random number generator every random choice comes from, so --seed repeats them all
+static RNG: OnceLock<Mutex<StdRng>> = OnceLock::new();
+
+/// Seed the generator with --seed, or from the OS without one. Only the first call counts, and
+/// it has to come before anything random is drawn.
+pub fn init(seed: Option<u64>) {
+    let rng = match seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+    let _ = RNG.set(Mutex::new(rng));
+}
+
+/// Draw from the shared generator
+pub fn with<T>(draw: impl FnOnce(&mut StdRng) -> T) -> T {
+    let mut rng = RNG.get_or_init(|| Mutex::new(StdRng::from_entropy())).lock().unwrap();
+    draw(&mut rng)
+}
@@ -0,0 +1,126 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr, SocketAddrV6};
+use std::str::FromStr;
+
+/// An IP address plus, for IPv6 link-local addresses, the zone (interface) it is reached
+/// through: `fe80::1%eth0`. A link-local address alone doesn't say which link to use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ScopedIp {
+    pub ip: IpAddr,
+    /// Interface index, 0 when the address has no zone
+    pub scope_id: u32,
+}
+
+impl ScopedIp {
+    pub fn socket_addr(self, port: u16) -> SocketAddr {
+        match self.ip {
+            IpAddr::V6(v6) => SocketAddr::V6(SocketAddrV6::new(v6, port, 0, self.scope_id)),
+            IpAddr::V4(_) => SocketAddr::new(self.ip, port),
+        }
+    }
+}
+
+impl From<IpAddr> for ScopedIp {
+    fn from(ip: IpAddr) -> Self {
+        ScopedIp { ip, scope_id: 0 }
+    }
+}
+
+impl From<SocketAddr> for ScopedIp {
+    fn from(addr: SocketAddr) -> Self {
+        match addr {
+            SocketAddr::V6(v6) => ScopedIp { ip: IpAddr::V6(*v6.ip()), scope_id: v6.scope_id() },
+            SocketAddr::V4(_) => addr.ip().into(),
+        }
+    }
+}
+
+/// Resolve a zone to an interface index: either the index itself or an interface name
+fn zone_index(zone: &str) -> Result<u32, String> {
+    if zone.is_empty() {
+        return Err("empty zone after '%'".to_string());
+    }
+    if let Ok(index) = zone.parse::<u32>() {
+        return Ok(index);
+    }
+    interface_index(zone).ok_or_else(|| format!("no network interface named '{}'", zone))
+}
+
+#[cfg(unix)]
+fn interface_index(name: &str) -> Option<u32> {
+    let name = std::ffi::CString::new(name).ok()?;
+    // SAFETY: `name` is a valid NUL-terminated string for the duration of the call
+    let index = unsafe { libc::if_nametoindex(name.as_ptr()) };
+    (index != 0).then_some(index)
+}
+
+#[cfg(not(unix))]
+fn interface_index(_name: &str) -> Option<u32> {
+    None
+}
+
+#[cfg(unix)]
+fn interface_name(index: u32) -> Option<String> {
+    let mut buf = [0 as libc::c_char; libc::IF_NAMESIZE];
+    // SAFETY: `buf` is IF_NAMESIZE bytes, as if_indextoname requires
+    let name = unsafe { libc::if_indextoname(index, buf.as_mut_ptr()) };
+    if name.is_null() {
+        return None;
+    }
+    // SAFETY: on success `buf` holds a NUL-terminated interface name
+    Some(unsafe { std::ffi::CStr::from_ptr(buf.as_ptr()) }.to_string_lossy().into_owned())
+}
+
+#[cfg(not(unix))]
+fn interface_name(_index: u32) -> Option<String> {
+    None
+}
+
+impl FromStr for ScopedIp {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let Some((addr, zone)) = s.split_once('%') else {
+            return s.parse::<IpAddr>().map(ScopedIp::from).map_err(|_| format!("'{}' is not an IP address", s));
+        };
+        let ip: Ipv6Addr = addr
+            .parse()
+            .map_err(|_| format!("'{}': a %zone needs an IPv6 address, not '{}'", s, addr))?;
+        let scope_id = zone_index(zone).map_err(|e| format!("'{}': {}", s, e))?;
+        Ok(ScopedIp { ip: IpAddr::V6(ip), scope_id })
+    }
+}
+
+/// Zones are shown by interface name where it is still known, else by index
+impl fmt::Display for ScopedIp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.ip, self.scope_id) {
+            (IpAddr::V6(_), scope_id) if scope_id != 0 => match interface_name(scope_id) {
+                Some(name) => write!(f, "{}%{}", self.ip, name),
+                None => write!(f, "{}%{}", self.ip, scope_id),
+            },
+            _ => write!(f, "{}", self.ip),
+        }
+    }
+}
+
+impl Serialize for ScopedIp {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for ScopedIp {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        match s.parse() {
+            Ok(scoped) => Ok(scoped),
+            // A baseline from another machine may name an interface this one doesn't have
+            Err(e) => match s.split_once('%') {
+                Some((addr, _)) => addr.parse::<IpAddr>().map(ScopedIp::from).map_err(|_| serde::de::Error::custom(e)),
+                None => Err(serde::de::Error::custom(e)),
+            },
+        }
+    }
+}
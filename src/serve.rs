@@ -0,0 +1,255 @@
+use crate::{report_warning, HostResult, ScanResult};
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// What --serve publishes, shared between the scan loop and the handler
+pub type LatestScan = Arc<RwLock<Latest>>;
+
+/// The latest completed scan, and the counters --openmetrics adds up over every scan so far
+#[derive(Debug, Default)]
+pub struct Latest {
+    pub scan: Option<ScanResult>,
+    /// By series, i.e. rendered labels
+    totals: HashMap<String, Totals>,
+}
+
+#[derive(Debug, Default)]
+struct Totals {
+    attempts: u64,
+    successes: u64,
+    /// The most recent successful attempt's response time in seconds, and when its scan ran
+    exemplar: Option<(f64, u64)>,
+}
+
+impl Latest {
+    /// Publish `scan`, adding its attempts to the running totals
+    pub fn record(&mut self, scan: ScanResult) {
+        for result in &scan.results {
+            let totals = self.totals.entry(labels(result)).or_default();
+            totals.attempts += u64::from(result.attempts);
+            totals.successes += u64::from(result.successful);
+            if let Some(&ms) = result.response_times.last() {
+                totals.exemplar = Some((ms as f64 / 1000.0, scan.scan_epoch));
+            }
+        }
+        self.scan = Some(scan);
+    }
+}
+
+/// What an OpenMetrics scraper asks for and gets
+const OPENMETRICS_CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// Longest request head the server will read
+const MAX_REQUEST: usize = 8192;
+
+/// How long a client gets to send its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Bind `addr` and answer requests in the background for the rest of the run. With
+/// `openmetrics`, /metrics is in the OpenMetrics format rather than Prometheus text.
+pub async fn spawn(addr: SocketAddr, latest: LatestScan, openmetrics: bool) -> std::io::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    tokio::spawn(handle(stream, latest.clone(), openmetrics));
+                }
+                Err(e) => report_warning(&format!("--serve failed to accept a connection: {}", e)),
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Serve one request and close the connection
+async fn handle(mut stream: TcpStream, latest: LatestScan, openmetrics: bool) {
+    let Ok(Some(head)) = tokio::time::timeout(REQUEST_TIMEOUT, read_head(&mut stream)).await else {
+        return;
+    };
+    let mut parts = head.split_whitespace();
+    let (method, path) = (parts.next().unwrap_or_default(), parts.next().unwrap_or_default());
+    // Ignore any query string, e.g. Prometheus scrape parameters
+    let path = path.split('?').next().unwrap_or_default();
+
+    let response = match (method, path) {
+        ("GET" | "HEAD", "/status" | "/metrics") => {
+            let latest = latest.read().unwrap();
+            match (latest.scan.as_ref(), path) {
+                (None, _) => response("503 Service Unavailable", "text/plain", "no scan has completed yet\n".into()),
+                (Some(scan), "/status") => match serde_json::to_string_pretty(scan) {
+                    Ok(json) => response("200 OK", "application/json", json + "\n"),
+                    Err(e) => response("500 Internal Server Error", "text/plain", format!("{}\n", e)),
+                },
+                (Some(_), _) if openmetrics => response("200 OK", OPENMETRICS_CONTENT_TYPE, self::openmetrics(&latest)),
+                (Some(scan), _) => response("200 OK", "text/plain; version=0.0.4", prometheus(scan)),
+            }
+        }
+        ("GET" | "HEAD", _) => response("404 Not Found", "text/plain", "try /status or /metrics\n".into()),
+        _ => response("405 Method Not Allowed", "text/plain", "only GET is supported\n".into()),
+    };
+
+    let response = if method == "HEAD" {
+        // Same headers, no body
+        let end = response.find("\r\n\r\n").map_or(response.len(), |i| i + 4);
+        response[..end].to_string()
+    } else {
+        response
+    };
+    let _ = stream.write_all(response.as_bytes()).await;
+    let _ = stream.shutdown().await;
+}
+
+/// Read up to the blank line ending the request head and return its first line
+async fn read_head(stream: &mut TcpStream) -> Option<String> {
+    let mut head = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = stream.read(&mut buf).await.ok()?;
+        if n == 0 || head.len() + n > MAX_REQUEST {
+            return None;
+        }
+        head.extend_from_slice(&buf[..n]);
+    }
+    let head = String::from_utf8_lossy(&head);
+    head.lines().next().map(str::to_string)
+}
+
+fn response(status: &str, content_type: &str, body: String) -> String {
+    format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )
+}
+
+/// Escape a Prometheus label value
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+fn labels(result: &HostResult) -> String {
+    let mut labels = format!("host=\"{}\",test_type=\"{}\"", escape(&result.host), result.test_type);
+    if let Some(port) = result.port {
+        let _ = write!(labels, ",port=\"{}\"", port);
+    }
+    if let Some(addr) = result.resolved_addr {
+        let _ = write!(labels, ",addr=\"{}\"", addr);
+    }
+    labels
+}
+
+type Metric = fn(&HostResult) -> Option<f64>;
+
+/// The latest scan's per-result gauges, in both formats: name, unit and help
+const GAUGES: [(&str, Option<&str>, &str, Metric); 4] = [
+    ("ring_up", None, "Whether the target was up in the latest scan (1) or not (0)", |r| {
+        Some(if r.status == "up" { 1.0 } else { 0.0 })
+    }),
+    ("ring_success_ratio", None, "Fraction of attempts that succeeded in the latest scan", |r| Some(r.success_rate)),
+    ("ring_response_time_seconds", Some("seconds"), "Average response time of the successful attempts", |r| {
+        r.avg_response_time_ms.map(|ms| ms / 1000.0)
+    }),
+    ("ring_jitter_seconds", Some("seconds"), "Mean difference between consecutive response times", |r| {
+        r.jitter_ms.map(|ms| ms / 1000.0)
+    }),
+];
+
+/// Render a scan in the Prometheus text exposition format: one gauge family per metric, one
+/// series per result. Latencies are converted to seconds, as Prometheus expects.
+pub fn prometheus(scan: &ScanResult) -> String {
+    let attempts: (&str, Option<&str>, &str, Metric) =
+        ("ring_attempts", None, "Attempts made in the latest scan", |r| Some(f64::from(r.attempts)));
+
+    let mut out = String::new();
+    for (name, _, help, metric) in GAUGES.into_iter().chain([attempts]) {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} gauge", name);
+        for result in &scan.results {
+            if let Some(value) = metric(result) {
+                let _ = writeln!(out, "{}{{{}}} {}", name, labels(result), value);
+            }
+        }
+    }
+    let _ = writeln!(out, "# HELP ring_scan_timestamp_seconds When the latest scan finished, as a Unix timestamp");
+    let _ = writeln!(out, "# TYPE ring_scan_timestamp_seconds gauge");
+    let _ = writeln!(out, "ring_scan_timestamp_seconds {}", scan.scan_epoch);
+    out
+}
+
+/// Suffixes OpenMetrics gives a meaning of its own, which a family's name can't end in
+const RESERVED_SUFFIXES: [&str; 8] = ["_total", "_created", "_count", "_sum", "_bucket", "_gcount", "_gsum", "_info"];
+
+/// Whether `name` is a valid OpenMetrics family name for a metric in `unit`: the metric name
+/// syntax, no reserved suffix, and ending in the unit when it has one
+fn valid_family(name: &str, unit: Option<&str>) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == ':')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == ':')
+        && !RESERVED_SUFFIXES.iter().any(|suffix| name.ends_with(suffix))
+        && unit.is_none_or(|unit| name.ends_with(&format!("_{}", unit)))
+}
+
+/// A family's metadata lines
+fn family(out: &mut String, name: &str, kind: &str, unit: Option<&str>, help: &str) {
+    debug_assert!(valid_family(name, unit), "'{}' isn't a valid OpenMetrics {} name", name, kind);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+    if let Some(unit) = unit {
+        let _ = writeln!(out, "# UNIT {} {}", name, unit);
+    }
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+}
+
+/// Render what --serve has in the OpenMetrics text format: the latest scan's gauges as in
+/// `prometheus`, then attempts and successes as counters over every scan so far. Each success
+/// counter carries the most recent response time as an exemplar.
+pub fn openmetrics(latest: &Latest) -> String {
+    let mut out = String::new();
+    let Some(scan) = &latest.scan else {
+        return "# EOF\n".to_string();
+    };
+
+    for (name, unit, help, metric) in GAUGES {
+        family(&mut out, name, "gauge", unit, help);
+        for result in &scan.results {
+            if let Some(value) = metric(result) {
+                let _ = writeln!(out, "{}{{{}}} {}", name, labels(result), value);
+            }
+        }
+    }
+
+    // The latest scan's results, so a target that has been dropped stops being exported
+    let series: Vec<(String, &Totals)> = scan
+        .results
+        .iter()
+        .filter_map(|result| {
+            let labels = labels(result);
+            let totals = latest.totals.get(&labels)?;
+            Some((labels, totals))
+        })
+        .collect();
+    family(&mut out, "ring_attempts", "counter", None, "Attempts made over every scan so far");
+    for (labels, totals) in &series {
+        let _ = writeln!(out, "ring_attempts_total{{{}}} {}", labels, totals.attempts);
+    }
+    family(&mut out, "ring_successes", "counter", None, "Successful attempts over every scan so far");
+    for (labels, totals) in &series {
+        let exemplar = match totals.exemplar {
+            Some((seconds, epoch)) => format!(" # {{}} {} {}", seconds, epoch),
+            None => String::new(),
+        };
+        let _ = writeln!(out, "ring_successes_total{{{}}} {}{}", labels, totals.successes, exemplar);
+    }
+
+    family(&mut out, "ring_scan_timestamp_seconds", "gauge", Some("seconds"), "When the latest scan finished, as a Unix timestamp");
+    let _ = writeln!(out, "ring_scan_timestamp_seconds {}", scan.scan_epoch);
+    out.push_str("# EOF\n");
+    out
+}
@@ -0,0 +1,95 @@
+/// Well-known TCP service names and their ports, in the spirit of `/etc/services`
+const SERVICES: &[(&str, u16)] = &[
+    ("echo", 7),
+    ("discard", 9),
+    ("daytime", 13),
+    ("ftp-data", 20),
+    ("ftp", 21),
+    ("ssh", 22),
+    ("telnet", 23),
+    ("smtp", 25),
+    ("time", 37),
+    ("whois", 43),
+    ("domain", 53),
+    ("dns", 53),
+    ("gopher", 70),
+    ("finger", 79),
+    ("http", 80),
+    ("kerberos", 88),
+    ("pop3", 110),
+    ("sunrpc", 111),
+    ("ident", 113),
+    ("nntp", 119),
+    ("ntp", 123),
+    ("msrpc", 135),
+    ("netbios-ssn", 139),
+    ("imap", 143),
+    ("snmp", 161),
+    ("bgp", 179),
+    ("ldap", 389),
+    ("https", 443),
+    ("microsoft-ds", 445),
+    ("smb", 445),
+    ("kpasswd", 464),
+    ("smtps", 465),
+    ("submission", 587),
+    ("ipp", 631),
+    ("ldaps", 636),
+    ("rsync", 873),
+    ("ftps", 990),
+    ("imaps", 993),
+    ("pop3s", 995),
+    ("socks", 1080),
+    ("openvpn", 1194),
+    ("mssql", 1433),
+    ("oracle", 1521),
+    ("pptp", 1723),
+    ("mqtt", 1883),
+    ("nfs", 2049),
+    ("docker", 2375),
+    ("docker-tls", 2376),
+    ("etcd", 2379),
+    ("mysql", 3306),
+    ("rdp", 3389),
+    ("svn", 3690),
+    ("postgresql", 5432),
+    ("postgres", 5432),
+    ("amqp", 5672),
+    ("vnc", 5900),
+    ("couchdb", 5984),
+    ("redis", 6379),
+    ("kubernetes", 6443),
+    ("irc", 6667),
+    ("http-alt", 8080),
+    ("https-alt", 8443),
+    ("zookeeper", 2181),
+    ("kafka", 9092),
+    ("prometheus", 9090),
+    ("elasticsearch", 9200),
+    ("memcached", 11211),
+    ("mongodb", 27017),
+];
+
+/// The most frequently open TCP ports, most common first (nmap's top-100 ordering)
+const TOP_PORTS: &[u16] = &[
+    80, 23, 443, 21, 22, 25, 3389, 110, 445, 139, 143, 53, 135, 3306, 8080, 1723, 111, 995, 993, 5900,
+    1025, 587, 8888, 199, 1720, 465, 548, 113, 81, 6001, 10000, 514, 5060, 179, 1026, 2000, 8443, 8000, 32768, 554,
+    26, 1433, 49152, 2001, 515, 8008, 49154, 1027, 5666, 646, 5000, 5631, 631, 49153, 8081, 2049, 88, 79, 5800, 106,
+    2121, 1110, 49155, 6000, 513, 990, 5357, 427, 49156, 543, 544, 5101, 144, 7, 389, 8009, 3128, 444, 9999, 5009,
+    7070, 5190, 3000, 5432, 1900, 3986, 13, 1029, 9, 5051, 6646, 49157, 1028, 873, 1755, 2717, 4899, 9100, 119, 37,
+];
+
+/// The `n` most common ports, for --ports-top
+pub fn top_ports(n: usize) -> Result<&'static [u16], String> {
+    TOP_PORTS
+        .get(..n)
+        .ok_or_else(|| format!("--ports-top {} is more than the {} ports ring knows about", n, TOP_PORTS.len()))
+}
+
+/// Look up a service name (case-insensitive), e.g. `https` → 443
+pub fn port_for_name(name: &str) -> Option<u16> {
+    SERVICES
+        .iter()
+        .find(|(service, _)| service.eq_ignore_ascii_case(name))
+        .map(|&(_, port)| port)
+}
@@ -0,0 +1,333 @@
+use crate::ProbeOptions;
+use rand::Rng;
+use socket2::SockRef;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use surge_ping::Client;
+use tokio::net::{TcpSocket, TcpStream};
+
+/// Set the DSCP bits (the upper six bits of the IPv4 TOS / IPv6 traffic class byte)
+pub fn set_dscp(sock: &SockRef<'_>, ipv4: bool, dscp: u8) -> io::Result<()> {
+    let tos = u32::from(dscp) << 2;
+    if ipv4 {
+        return sock.set_tos_v4(tos);
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android", target_os = "macos", target_os = "freebsd"))]
+    {
+        sock.set_tclass_v6(tos)
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "android", target_os = "macos", target_os = "freebsd")))]
+    {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "IPv6 traffic class is not supported on this platform"))
+    }
+}
+
+/// Set SO_MARK, so policy routing rules matching the mark (`ip rule add fwmark N table T`) pick
+/// the route. Needs CAP_NET_ADMIN.
+#[cfg(target_os = "linux")]
+pub fn set_mark(sock: &SockRef<'_>, mark: u32) -> io::Result<()> {
+    sock.set_mark(mark)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn set_mark(_sock: &SockRef<'_>, _mark: u32) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "socket marks (SO_MARK) are only supported on Linux"))
+}
+
+fn new_tcp_socket(addr: &SocketAddr) -> io::Result<TcpSocket> {
+    if addr.is_ipv4() {
+        TcpSocket::new_v4()
+    } else {
+        TcpSocket::new_v6()
+    }
+}
+
+/// Pick a source port from the IANA dynamic range (49152-65535)
+pub fn random_source_port() -> u16 {
+    crate::rng::with(|rng| rng.gen_range(49152..=65535))
+}
+
+/// Open a TCP connection to `addr` with the socket options requested on the command line applied.
+/// With `source_port` the socket is bound to that local port before connecting.
+pub async fn connect_tcp(addr: SocketAddr, source_port: Option<u16>, opts: &ProbeOptions) -> io::Result<TcpStream> {
+    let socket = new_tcp_socket(&addr)?;
+
+    if let Some(dscp) = opts.dscp {
+        set_dscp(&SockRef::from(&socket), addr.is_ipv4(), dscp)?;
+    }
+
+    if let Some(mark) = opts.fwmark {
+        set_mark(&SockRef::from(&socket), mark)?;
+    }
+
+    if opts.tfo {
+        set_fastopen_connect(&socket)?;
+    }
+
+    if let Some(port) = source_port {
+        let local_ip = if addr.is_ipv4() { IpAddr::V4(Ipv4Addr::UNSPECIFIED) } else { IpAddr::V6(Ipv6Addr::UNSPECIFIED) };
+        socket.bind(SocketAddr::new(local_ip, port))?;
+    }
+
+    socket.connect(addr).await
+}
+
+/// Turn on client-side TCP Fast Open. The kernel then holds the SYN back until the first write
+/// and sends the data in it when it has a cookie for the server.
+#[cfg(target_os = "linux")]
+fn set_fastopen_connect(socket: &TcpSocket) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let enable: libc::c_int = 1;
+    // SAFETY: the fd is owned by `socket` and the option value outlives the call
+    let rc = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::IPPROTO_TCP,
+            libc::TCP_FASTOPEN_CONNECT,
+            &enable as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if rc == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_fastopen_connect(_socket: &TcpSocket) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "TCP Fast Open is only supported on Linux"))
+}
+
+/// Check up front that client-side TCP Fast Open can be used, so an old kernel or a sysctl
+/// that disables it is reported once rather than failing every probe
+pub fn check_fastopen() -> Result<(), String> {
+    let socket = TcpSocket::new_v4().map_err(|e| e.to_string())?;
+    set_fastopen_connect(&socket).map_err(|e| e.to_string())?;
+
+    // Bit 0 of net.ipv4.tcp_fastopen enables the client side
+    #[cfg(target_os = "linux")]
+    if let Ok(value) = std::fs::read_to_string("/proc/sys/net/ipv4/tcp_fastopen") {
+        if value.trim().parse::<u32>().is_ok_and(|v| v & 1 == 0) {
+            return Err(format!("client TFO is disabled (net.ipv4.tcp_fastopen = {})", value.trim()));
+        }
+    }
+    Ok(())
+}
+
+/// Whether the server acknowledged data carried in our SYN, i.e. Fast Open was actually used
+#[cfg(target_os = "linux")]
+pub fn fastopen_used(stream: &TcpStream) -> Option<bool> {
+    use std::os::fd::AsRawFd;
+
+    /// TCPI_OPT_SYN_DATA from linux/tcp.h
+    const SYN_DATA: u8 = 32;
+
+    // SAFETY: tcp_info is plain integers, for which all zeroes is a valid value
+    let mut info: libc::tcp_info = unsafe { std::mem::zeroed() };
+    let mut len = std::mem::size_of::<libc::tcp_info>() as libc::socklen_t;
+    // SAFETY: getsockopt writes at most `len` bytes into `info`
+    let rc = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::IPPROTO_TCP,
+            libc::TCP_INFO,
+            &mut info as *mut libc::tcp_info as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    (rc == 0).then_some(info.tcpi_options & SYN_DATA != 0)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn fastopen_used(_stream: &TcpStream) -> Option<bool> {
+    None
+}
+
+/// Check up front that the OS accepts the requested DSCP value, so a bad value fails the run
+/// instead of every probe
+pub fn validate_dscp(dscp: u8) -> io::Result<()> {
+    let socket = TcpSocket::new_v4()?;
+    set_dscp(&SockRef::from(&socket), true, dscp)
+}
+
+/// Run `f` on the ICMP client's socket
+fn with_icmp_socket<T>(client: &Client, f: impl FnOnce(&SockRef<'_>) -> io::Result<T>) -> io::Result<T> {
+    let socket = client.get_socket();
+
+    #[cfg(unix)]
+    // SAFETY: the fd belongs to `client`, which outlives this borrow
+    let fd = unsafe { std::os::fd::BorrowedFd::borrow_raw(socket.get_native_sock()) };
+    #[cfg(windows)]
+    // SAFETY: the socket belongs to `client`, which outlives this borrow
+    let fd = unsafe { std::os::windows::io::BorrowedSocket::borrow_raw(socket.get_native_sock()) };
+
+    f(&SockRef::from(&fd))
+}
+
+/// Check up front that the mark can be set, so a missing CAP_NET_ADMIN or another OS fails the
+/// run once instead of every probe
+pub fn validate_mark(mark: u32) -> io::Result<()> {
+    let socket = TcpSocket::new_v4()?;
+    set_mark(&SockRef::from(&socket), mark)
+}
+
+/// Apply the DSCP marking to the ICMP client's socket
+pub fn set_icmp_dscp(client: &Client, ipv4: bool, dscp: u8) -> io::Result<()> {
+    with_icmp_socket(client, |sock| set_dscp(sock, ipv4, dscp))
+}
+
+/// Mark the ICMP client's echo requests for policy routing
+pub fn set_icmp_mark(client: &Client, mark: u32) -> io::Result<()> {
+    with_icmp_socket(client, |sock| set_mark(sock, mark))
+}
+
+/// Set the don't-fragment bit on the ICMP client's echo requests, so oversized ones fail
+/// rather than being fragmented
+#[cfg(target_os = "linux")]
+pub fn set_icmp_dont_fragment(client: &Client, ipv4: bool) -> io::Result<()> {
+    let (level, name, value) = if ipv4 {
+        (libc::IPPROTO_IP, libc::IP_MTU_DISCOVER, libc::IP_PMTUDISC_DO)
+    } else {
+        (libc::IPPROTO_IPV6, libc::IPV6_MTU_DISCOVER, libc::IPV6_PMTUDISC_DO)
+    };
+    // SAFETY: the fd belongs to `client` and the option value outlives the call
+    let rc = unsafe {
+        libc::setsockopt(
+            client.get_socket().get_native_sock(),
+            level,
+            name,
+            &value as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if rc == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn set_icmp_dont_fragment(_client: &Client, _ipv4: bool) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "path MTU discovery is only supported on Linux"))
+}
+
+/// Whether a send failed because the packet is bigger than the path allows (EMSGSIZE)
+#[cfg(unix)]
+pub fn is_message_too_big(e: &io::Error) -> bool {
+    e.raw_os_error() == Some(libc::EMSGSIZE)
+}
+
+#[cfg(not(unix))]
+pub fn is_message_too_big(_e: &io::Error) -> bool {
+    false
+}
+
+/// Whether a socket couldn't be created because this host lacks the address family, as an
+/// IPv6 socket can't on a host with IPv6 disabled (EAFNOSUPPORT)
+#[cfg(unix)]
+pub fn is_family_unsupported(e: &io::Error) -> bool {
+    e.raw_os_error() == Some(libc::EAFNOSUPPORT)
+}
+
+#[cfg(not(unix))]
+pub fn is_family_unsupported(_e: &io::Error) -> bool {
+    false
+}
+
+/// Whether this host has a route to `ip`. Connecting a UDP socket sends nothing, but fails at
+/// once without one, as it does for IPv4 addresses on an IPv6-only host.
+pub fn has_route(ip: IpAddr) -> bool {
+    let local: SocketAddr = match ip {
+        IpAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        IpAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    std::net::UdpSocket::bind(local).and_then(|socket| socket.connect((ip, 9))).is_ok()
+}
+
+/// A raw IPv4 socket for `protocol`, as --syn and --icmp-timestamp send on. Opening one is also
+/// how they check up front that the process is allowed to.
+#[cfg(target_os = "linux")]
+pub fn raw_ipv4_socket(protocol: socket2::Protocol) -> io::Result<socket2::Socket> {
+    use socket2::{Domain, Socket, Type};
+    Socket::new(Domain::IPV4, Type::RAW, Some(protocol))
+}
+
+/// A non-blocking raw IPv4 socket for `protocol`, with the DSCP marking and firewall mark
+/// requested on the command line applied
+#[cfg(target_os = "linux")]
+pub fn open_raw_ipv4(
+    protocol: socket2::Protocol,
+    dscp: Option<u8>,
+    fwmark: Option<u32>,
+) -> io::Result<tokio::io::unix::AsyncFd<socket2::Socket>> {
+    let socket = raw_ipv4_socket(protocol)?;
+    socket.set_nonblocking(true)?;
+    if let Some(dscp) = dscp {
+        set_dscp(&SockRef::from(&socket), true, dscp)?;
+    }
+    if let Some(mark) = fwmark {
+        set_mark(&SockRef::from(&socket), mark)?;
+    }
+    tokio::io::unix::AsyncFd::new(socket)
+}
+
+/// The RFC 1071 checksum: the ones' complement of the ones' complement sum of `parts`, read as
+/// one run of big-endian 16-bit words. Every part but the last must have an even length, as a
+/// pseudo-header's fields do.
+#[cfg(target_os = "linux")]
+pub fn internet_checksum(parts: &[&[u8]]) -> u16 {
+    let mut sum: u32 = parts
+        .iter()
+        .flat_map(|part| part.chunks(2))
+        .map(|chunk| u32::from(u16::from_be_bytes([chunk[0], *chunk.get(1).unwrap_or(&0)])))
+        .sum();
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// What follows the header of an IPv4 packet read from a raw socket, if `src` sent it and at
+/// least `min_len` bytes follow
+#[cfg(target_os = "linux")]
+fn ipv4_payload_from(packet: &[u8], src: Ipv4Addr, min_len: usize) -> Option<&[u8]> {
+    let ihl = usize::from(packet.first().copied().unwrap_or(0) & 0x0f) * 4;
+    if packet.len() < ihl.max(20) + min_len || packet[12..16] != src.octets() {
+        return None;
+    }
+    Some(&packet[ihl..])
+}
+
+/// Read packets off a raw IPv4 socket until `answer` recognizes the payload of one from `src`,
+/// or `deadline` passes (`None`). A raw socket sees every inbound packet of its protocol, not
+/// just the replies to what it sent.
+#[cfg(target_os = "linux")]
+pub async fn recv_raw_from<T>(
+    socket: &tokio::io::unix::AsyncFd<socket2::Socket>,
+    src: Ipv4Addr,
+    deadline: tokio::time::Instant,
+    min_len: usize,
+    mut answer: impl FnMut(&[u8]) -> Option<T>,
+) -> io::Result<Option<T>> {
+    use std::io::Read;
+
+    let mut buf = [0u8; 1500];
+    loop {
+        let mut ready = match tokio::time::timeout_at(deadline, socket.readable()).await {
+            Ok(ready) => ready?,
+            Err(_) => return Ok(None),
+        };
+        let n = match ready.try_io(|fd| fd.get_ref().read(&mut buf)) {
+            Ok(read) => read?,
+            Err(_would_block) => continue,
+        };
+        if let Some(found) = ipv4_payload_from(&buf[..n], src, min_len).and_then(&mut answer) {
+            return Ok(Some(found));
+        }
+    }
+}
@@ -0,0 +1,175 @@
+use crate::ScanResult;
+
+/// Bumped when the tables change in a way that adding a column can't express
+#[cfg(feature = "sqlite")]
+const SCHEMA_VERSION: i32 = 1;
+
+/// --sqlite: a database holding one `scans` row per scan and one `results` row per result. The
+/// `results` table has a column for every field a result has ever had; new fields are added as
+/// columns the first time they turn up, so older databases keep working as ring grows.
+#[cfg(feature = "sqlite")]
+pub struct Store {
+    conn: rusqlite::Connection,
+    path: String,
+    /// The `results` table's columns, so a new field is noticed without asking the database
+    columns: std::collections::HashSet<String>,
+}
+
+#[cfg(not(feature = "sqlite"))]
+pub struct Store;
+
+#[cfg(feature = "sqlite")]
+fn migrate(conn: &rusqlite::Connection) -> Result<(), String> {
+    let version: i32 = conn.pragma_query_value(None, "user_version", |row| row.get(0)).map_err(|e| e.to_string())?;
+    if version > SCHEMA_VERSION {
+        return Err(format!(
+            "it was created by a newer ring (schema version {}, this one knows {})",
+            version, SCHEMA_VERSION
+        ));
+    }
+    if version < 1 {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS scans (
+                 id INTEGER PRIMARY KEY,
+                 scan_timestamp TEXT NOT NULL,
+                 scan_epoch INTEGER NOT NULL,
+                 tool_version TEXT NOT NULL,
+                 schema_version INTEGER NOT NULL,
+                 tags TEXT NOT NULL,
+                 config TEXT
+             );
+             CREATE TABLE IF NOT EXISTS results (
+                 id INTEGER PRIMARY KEY,
+                 scan_id INTEGER NOT NULL REFERENCES scans(id)
+             );
+             CREATE INDEX IF NOT EXISTS results_scan ON results(scan_id);
+             PRAGMA user_version = 1;",
+        )
+        .map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+#[cfg(feature = "sqlite")]
+fn load_columns(conn: &rusqlite::Connection) -> rusqlite::Result<std::collections::HashSet<String>> {
+    let mut stmt = conn.prepare("SELECT name FROM pragma_table_info('results')")?;
+    let columns = stmt.query_map([], |row| row.get(0))?.collect();
+    columns
+}
+
+/// Declared type for a column first seen holding `value`. SQLite doesn't enforce it, so a
+/// later value of another type is stored as it is.
+#[cfg(feature = "sqlite")]
+fn column_type(value: &serde_json::Value) -> &'static str {
+    use serde_json::Value;
+    match value {
+        Value::Null => "",
+        Value::Bool(_) => "INTEGER",
+        Value::Number(n) if n.is_f64() => "REAL",
+        Value::Number(_) => "INTEGER",
+        Value::String(_) => "TEXT",
+        // Lists such as response_times are kept as JSON, which json_each() can unpack
+        Value::Array(_) | Value::Object(_) => "TEXT",
+    }
+}
+
+#[cfg(feature = "sqlite")]
+fn sql_value(value: serde_json::Value) -> rusqlite::types::Value {
+    use rusqlite::types::Value as Sql;
+    use serde_json::Value;
+    match value {
+        Value::Null => Sql::Null,
+        Value::Bool(b) => Sql::Integer(i64::from(b)),
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => Sql::Integer(i),
+            None => Sql::Real(n.as_f64().unwrap_or_default()),
+        },
+        Value::String(s) => Sql::Text(s),
+        other => Sql::Text(other.to_string()),
+    }
+}
+
+/// `"name"`, safe as an identifier whatever it holds
+#[cfg(feature = "sqlite")]
+fn quoted(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+impl Store {
+    #[cfg(feature = "sqlite")]
+    pub fn open(path: &str) -> Result<Self, String> {
+        let conn = rusqlite::Connection::open(path)
+            .map_err(|e| format!("failed to open SQLite database '{}': {}", path, e))?;
+        // Readers can query the database while a long run keeps writing to it
+        let _ = conn.pragma_update_and_check(None, "journal_mode", "WAL", |_| Ok(()));
+        migrate(&conn).map_err(|e| format!("can't use SQLite database '{}': {}", path, e))?;
+        let columns = load_columns(&conn).map_err(|e| format!("can't use SQLite database '{}': {}", path, e))?;
+        Ok(Store { conn, path: path.to_string(), columns })
+    }
+
+    #[cfg(not(feature = "sqlite"))]
+    pub fn open(_path: &str) -> Result<Self, String> {
+        Err("--sqlite requires ring to be built with the `sqlite` feature (cargo install --features sqlite)".to_string())
+    }
+
+    /// Insert the scan and all its results in a single transaction. Failures are reported but
+    /// never stop monitoring.
+    #[cfg(feature = "sqlite")]
+    pub fn insert(&mut self, scan: &ScanResult) {
+        if let Err(e) = self.try_insert(scan) {
+            crate::report_warning(&format!("failed to write SQLite database '{}': {}", self.path, e));
+            // Columns added in the rolled-back transaction are gone again
+            if let Ok(columns) = load_columns(&self.conn) {
+                self.columns = columns;
+            }
+        }
+    }
+
+    #[cfg(not(feature = "sqlite"))]
+    pub fn insert(&mut self, _scan: &ScanResult) {}
+
+    #[cfg(feature = "sqlite")]
+    fn try_insert(&mut self, scan: &ScanResult) -> Result<(), Box<dyn std::error::Error>> {
+        use rusqlite::types::Value as Sql;
+
+        let tx = self.conn.transaction()?;
+        tx.execute(
+            "INSERT INTO scans (scan_timestamp, scan_epoch, tool_version, schema_version, tags, config)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            rusqlite::params![
+                scan.scan_timestamp,
+                scan.scan_epoch as i64,
+                scan.tool_version,
+                scan.schema_version,
+                serde_json::to_string(&scan.tags)?,
+                scan.config.as_ref().map(serde_json::to_string).transpose()?,
+            ],
+        )?;
+        let scan_id = tx.last_insert_rowid();
+
+        for result in &scan.results {
+            let serde_json::Value::Object(fields) = serde_json::to_value(result)? else {
+                continue;
+            };
+            let mut names = vec![quoted("scan_id")];
+            let mut values = vec![Sql::Integer(scan_id)];
+            for (name, value) in fields {
+                if !self.columns.contains(&name) {
+                    tx.execute(&format!("ALTER TABLE results ADD COLUMN {} {}", quoted(&name), column_type(&value)), [])?;
+                    self.columns.insert(name.clone());
+                }
+                names.push(quoted(&name));
+                values.push(sql_value(value));
+            }
+            let sql = format!(
+                "INSERT INTO results ({}) VALUES ({})",
+                names.join(", "),
+                vec!["?"; names.len()].join(", ")
+            );
+            tx.prepare_cached(&sql)?.execute(rusqlite::params_from_iter(values))?;
+        }
+
+        tx.commit()?;
+        Ok(())
+    }
+}
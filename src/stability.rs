@@ -0,0 +1,157 @@
+use crate::scoped::ScopedIp;
+use crate::{icons, mean, HostResult};
+use colored::*;
+use serde::Serialize;
+use std::collections::HashMap;
+
+type TargetKey = (String, Option<u16>, String, Option<ScopedIp>);
+
+/// How one target fared across the --flaky-detector scans
+#[derive(Serialize, Debug, Clone)]
+pub struct Stability {
+    pub host: String,
+    pub port: Option<u16>,
+    pub test_type: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resolved_addr: Option<ScopedIp>,
+    /// Scans the target appeared in, normally all of them
+    pub scans: u32,
+    pub up_scans: u32,
+    /// "stable" (up every time), "flaky" (up some of the time) or "down" (never up)
+    pub verdict: String,
+    /// Each scan's status, in order
+    pub statuses: Vec<String>,
+    /// Mean of the per-scan average latencies
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub avg_response_time_ms: Option<f64>,
+    /// Population variance of the per-scan average latencies, in ms²
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rtt_variance: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rtt_stddev_ms: Option<f64>,
+}
+
+/// The --flaky-detector report: every target's record over the scans that ran
+#[derive(Serialize, Debug)]
+pub struct StabilityReport {
+    pub scans: u32,
+    pub targets: Vec<Stability>,
+}
+
+#[derive(Debug)]
+struct Record {
+    key: TargetKey,
+    statuses: Vec<String>,
+    averages: Vec<f64>,
+}
+
+/// Collects each target's status and average latency from one scan after another
+#[derive(Debug, Default)]
+pub struct StabilityTracker {
+    scans: u32,
+    /// In the order targets first appeared, so the report reads like a scan summary
+    records: Vec<Record>,
+    index: HashMap<TargetKey, usize>,
+}
+
+impl StabilityTracker {
+    pub fn scans(&self) -> u32 {
+        self.scans
+    }
+
+    pub fn record(&mut self, results: &[HostResult]) {
+        self.scans += 1;
+        for result in results {
+            let key = (result.host.clone(), result.port, result.test_type.clone(), result.resolved_addr);
+            let i = *self.index.entry(key.clone()).or_insert_with(|| {
+                self.records.push(Record { key, statuses: Vec::new(), averages: Vec::new() });
+                self.records.len() - 1
+            });
+            let record = &mut self.records[i];
+            record.statuses.push(result.status.clone());
+            record.averages.extend(result.avg_response_time_ms);
+        }
+    }
+
+    pub fn report(&self) -> StabilityReport {
+        let targets = self
+            .records
+            .iter()
+            .map(|record| {
+                let (host, port, test_type, resolved_addr) = record.key.clone();
+                let scans = record.statuses.len() as u32;
+                let up_scans = record.statuses.iter().filter(|s| *s == "up").count() as u32;
+                let verdict = match up_scans {
+                    0 => "down",
+                    n if n == scans => "stable",
+                    _ => "flaky",
+                };
+                let avg = mean(&record.averages);
+                let rtt_variance = avg.map(|avg| {
+                    record.averages.iter().map(|a| (a - avg).powi(2)).sum::<f64>() / record.averages.len() as f64
+                });
+                Stability {
+                    host,
+                    port,
+                    test_type,
+                    resolved_addr,
+                    scans,
+                    up_scans,
+                    verdict: verdict.to_string(),
+                    statuses: record.statuses.clone(),
+                    avg_response_time_ms: avg,
+                    rtt_variance,
+                    rtt_stddev_ms: rtt_variance.map(f64::sqrt),
+                }
+            })
+            .collect();
+        StabilityReport { scans: self.scans, targets }
+    }
+}
+
+pub fn print_report(report: &StabilityReport) {
+    println!("\n{}Stability over {} scans", icons::lead("🎲"), report.scans);
+    println!("{}", icons::rule());
+
+    for target in &report.targets {
+        let mut label = match target.port {
+            Some(port) => format!("{}:{}", target.host.blue(), port.to_string().yellow()),
+            None => format!("{} ({})", target.host.blue(), target.test_type.to_uppercase()),
+        };
+        if let Some(addr) = target.resolved_addr {
+            label.push_str(&format!(" @ {}", addr).dimmed().to_string());
+        }
+        let (icon, verdict) = match target.verdict.as_str() {
+            "stable" => (icons::get("up"), target.verdict.green()),
+            "flaky" => (icons::get("partial"), target.verdict.yellow()),
+            _ => (icons::get("down"), target.verdict.red()),
+        };
+        // One mark per scan, so an intermittent failure's timing is visible at a glance
+        let pattern: String = target
+            .statuses
+            .iter()
+            .map(|s| if s == "up" { icons::pick("▮", "+").green().to_string() } else { icons::pick("▯", "-").red().to_string() })
+            .collect();
+        let latency = match (target.avg_response_time_ms, target.rtt_stddev_ms) {
+            (Some(avg), Some(stddev)) => format!(" ({:.1} ms {}{:.1})", avg, icons::get("plus-minus"), stddev),
+            _ => String::new(),
+        };
+        println!(
+            "{} {} {} {}/{} scans up {} {}{} [{}]",
+            icon,
+            label,
+            icons::get("arrow"),
+            target.up_scans,
+            target.scans,
+            pattern,
+            verdict,
+            latency,
+            target.test_type
+        );
+    }
+
+    let flaky = report.targets.iter().filter(|t| t.verdict == "flaky").count();
+    if flaky > 0 {
+        println!("\n{}", format!("{} of {} targets were flaky", flaky, report.targets.len()).yellow());
+    }
+}
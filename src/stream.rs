@@ -0,0 +1,84 @@
+use crate::{apply_thresholds, check_assertions, geoip::GeoIp, Args, HostResult, LatencyUnit};
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// --result-limit: every result is printed as soon as its probe finishes, as a --format line or
+/// a --json-compact object, and then dropped, except the first `limit`, which are kept for the
+/// summary at the end of the scan. That way a sweep of a /16 never holds all its results at once.
+pub struct ResultStream<'a> {
+    args: &'a Args,
+    geoip: Option<&'a GeoIp>,
+    limit: usize,
+    /// Results emitted so far, kept or not
+    seen: AtomicUsize,
+    /// A result breached --max-rtt or --jitter-alert
+    exceeded: AtomicBool,
+    /// A result failed an --assert
+    failed: AtomicBool,
+}
+
+impl<'a> ResultStream<'a> {
+    pub fn new(args: &'a Args, limit: usize, geoip: Option<&'a GeoIp>) -> Self {
+        ResultStream {
+            args,
+            geoip,
+            limit,
+            seen: AtomicUsize::new(0),
+            exceeded: AtomicBool::new(false),
+            failed: AtomicBool::new(false),
+        }
+    }
+
+    /// Finish one probe's results the way the end of a scan would, print them, and hand back
+    /// the ones still within the limit
+    pub fn emit(&self, mut results: Vec<HostResult>) -> Vec<HostResult> {
+        results.iter_mut().for_each(HostResult::collect_errors);
+        if let Some(geoip) = self.geoip {
+            geoip.annotate(&mut results);
+        }
+        if apply_thresholds(&mut results, self.args) {
+            self.exceeded.store(true, Ordering::Relaxed);
+        }
+        if check_assertions(&mut results, &self.args.assertions) {
+            self.failed.store(true, Ordering::Relaxed);
+        }
+
+        let mut stdout = std::io::stdout().lock();
+        for result in &results {
+            let line = match &self.args.format {
+                Some(template) => template.render(result),
+                None => self.json(result),
+            };
+            let _ = writeln!(stdout, "{}", line);
+        }
+
+        let seen = self.seen.fetch_add(results.len(), Ordering::Relaxed);
+        results.truncate(self.limit.saturating_sub(seen));
+        results
+    }
+
+    /// One result on one line, with its latencies in --unit like the scan documents
+    fn json(&self, result: &HostResult) -> String {
+        let mut value = serde_json::to_value(result).unwrap_or_default();
+        if self.args.unit != LatencyUnit::Ms {
+            self.args.unit.rescale(&mut value);
+            if let serde_json::Value::Object(fields) = &mut value {
+                fields.insert("unit".to_string(), self.args.unit.label().into());
+            }
+        }
+        value.to_string()
+    }
+
+    /// How many results were printed but not kept
+    pub fn dropped(&self) -> usize {
+        self.seen.load(Ordering::Relaxed).saturating_sub(self.limit)
+    }
+
+    pub fn exceeded(&self) -> bool {
+        self.exceeded.load(Ordering::Relaxed)
+    }
+
+    pub fn failed(&self) -> bool {
+        self.failed.load(Ordering::Relaxed)
+    }
+}
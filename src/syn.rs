@@ -0,0 +1,125 @@
+use std::io;
+use std::net::Ipv4Addr;
+use std::time::Duration;
+
+/// What a half-open probe learned about a port
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PortState {
+    /// Answered with SYN-ACK
+    Open,
+    /// Answered with RST
+    Closed,
+    /// No answer before the timeout
+    Filtered,
+}
+
+/// Whether this platform can send SYN probes at all
+pub const SUPPORTED: bool = cfg!(target_os = "linux");
+
+#[cfg(target_os = "linux")]
+const SYN: u8 = 0x02;
+#[cfg(target_os = "linux")]
+const RST: u8 = 0x04;
+#[cfg(target_os = "linux")]
+const ACK: u8 = 0x10;
+
+/// Fail early, with the OS error, when the process isn't allowed to open raw sockets
+#[cfg(target_os = "linux")]
+pub fn check_privileges() -> io::Result<()> {
+    crate::socket::raw_ipv4_socket(socket2::Protocol::TCP).map(drop)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn check_privileges() -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "SYN probes are only supported on Linux"))
+}
+
+/// The checksum over the TCP pseudo-header and segment
+#[cfg(target_os = "linux")]
+fn tcp_checksum(src: Ipv4Addr, dst: Ipv4Addr, segment: &[u8]) -> u16 {
+    let length = (segment.len() as u16).to_be_bytes();
+    crate::socket::internet_checksum(&[&src.octets(), &dst.octets(), &[0, 6], &length, segment])
+}
+
+/// A bare SYN segment with an MSS option, since some stacks ignore SYNs without one
+#[cfg(target_os = "linux")]
+fn syn_segment(src: Ipv4Addr, src_port: u16, dst: Ipv4Addr, dst_port: u16, seq: u32) -> [u8; 24] {
+    let mut segment = [0u8; 24];
+    segment[0..2].copy_from_slice(&src_port.to_be_bytes());
+    segment[2..4].copy_from_slice(&dst_port.to_be_bytes());
+    segment[4..8].copy_from_slice(&seq.to_be_bytes());
+    segment[12] = 6 << 4; // data offset: six 32-bit words
+    segment[13] = SYN;
+    segment[14..16].copy_from_slice(&64240u16.to_be_bytes()); // window
+    segment[20..24].copy_from_slice(&[2, 4, 0x05, 0xb4]); // MSS 1460
+
+    let checksum = tcp_checksum(src, dst, &segment);
+    segment[16..18].copy_from_slice(&checksum.to_be_bytes());
+    segment
+}
+
+/// The local address the kernel would route `dst` from, needed for the checksum
+#[cfg(target_os = "linux")]
+pub fn source_addr_for(dst: Ipv4Addr) -> io::Result<Ipv4Addr> {
+    let probe = std::net::UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    probe.connect((dst, 9))?;
+    match probe.local_addr()?.ip() {
+        std::net::IpAddr::V4(ip) => Ok(ip),
+        std::net::IpAddr::V6(_) => Err(io::Error::new(io::ErrorKind::AddrNotAvailable, "no IPv4 source address")),
+    }
+}
+
+/// Send one SYN to `dst:port` and classify the reply. The kernel answers a SYN-ACK with a RST
+/// of its own, since no socket owns the connection, so the handshake is never completed.
+#[cfg(target_os = "linux")]
+pub async fn probe(
+    dst: Ipv4Addr,
+    port: u16,
+    timeout: Duration,
+    dscp: Option<u8>,
+    fwmark: Option<u32>,
+) -> io::Result<(PortState, Duration)> {
+    use rand::Rng;
+    use socket2::SockAddr;
+    use std::net::SocketAddrV4;
+    use tokio::time::Instant;
+
+    let socket = crate::socket::open_raw_ipv4(socket2::Protocol::TCP, dscp, fwmark)?;
+
+    let src = source_addr_for(dst)?;
+    let src_port = crate::socket::random_source_port();
+    let seq: u32 = crate::rng::with(|rng| rng.gen());
+    let segment = syn_segment(src, src_port, dst, port, seq);
+
+    let start = Instant::now();
+    let deadline = start + timeout;
+    socket.get_ref().send_to(&segment, &SockAddr::from(SocketAddrV4::new(dst, 0)))?;
+
+    // Wait for the segment answering ours
+    let state = crate::socket::recv_raw_from(&socket, dst, deadline, 20, |tcp| {
+        let from_port = u16::from_be_bytes([tcp[0], tcp[1]]);
+        let to_port = u16::from_be_bytes([tcp[2], tcp[3]]);
+        let ack = u32::from_be_bytes([tcp[8], tcp[9], tcp[10], tcp[11]]);
+        let flags = tcp[13];
+        if from_port != port || to_port != src_port || ack != seq.wrapping_add(1) {
+            return None;
+        }
+        if flags & RST != 0 {
+            return Some(PortState::Closed);
+        }
+        (flags & (SYN | ACK) == SYN | ACK).then_some(PortState::Open)
+    })
+    .await?;
+    Ok((state.unwrap_or(PortState::Filtered), start.elapsed()))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub async fn probe(
+    _dst: Ipv4Addr,
+    _port: u16,
+    _timeout: Duration,
+    _dscp: Option<u8>,
+    _fwmark: Option<u32>,
+) -> io::Result<(PortState, Duration)> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "SYN probes are only supported on Linux"))
+}
@@ -0,0 +1,97 @@
+use crate::{icons, FailureKind, HostResult};
+use comfy_table::presets::{ASCII_FULL_CONDENSED, UTF8_FULL_CONDENSED};
+use comfy_table::{CellAlignment, ContentArrangement, Table};
+use std::collections::{HashMap, HashSet};
+
+pub fn status_icon(result: &HostResult) -> &'static str {
+    match (result.status.as_str(), result.failure_kind) {
+        ("up", _) => icons::get("up"),
+        ("down", Some(kind)) => kind.icon(),
+        ("down", None) => icons::get("down"),
+        ("filtered", _) => FailureKind::Filtered.icon(),
+        ("partial", _) => icons::get("partial"),
+        _ => icons::get("unknown"),
+    }
+}
+
+fn row_label(result: &HostResult) -> String {
+    match (result.resolved_addr, &result.ptr_name) {
+        (Some(addr), _) => format!("{} @ {}", result.label_host(), addr),
+        (None, Some(ptr_name)) => format!("{} @ {}", ptr_name, result.host),
+        (None, None) => result.host.clone(),
+    }
+}
+
+fn cell(result: &HostResult, verbose: bool) -> String {
+    match (verbose, result.avg_response_time_ms) {
+        (true, Some(avg)) => format!("{} {:.1} ms", status_icon(result), avg),
+        _ => status_icon(result).to_string(),
+    }
+}
+
+/// Which column a result belongs in: its port, or for port-less probes their test type
+fn column(result: &HostResult) -> (Option<u16>, &str) {
+    match result.port {
+        // QUIC is always on --quic-port, which may be a TCP column too
+        Some(port) if result.test_type != "quic" => (Some(port), ""),
+        _ => (None, result.test_type.as_str()),
+    }
+}
+
+/// Render results as a host × port grid, with ICMP, ARP, UDS and QUIC in their own trailing columns.
+/// Cells for ports a host wasn't scanned on are left as `·`.
+pub fn print_table(results: &[HostResult], verbose: bool) {
+    println!("{}", grid(results, |r| cell(r, verbose)));
+}
+
+/// The grid behind --table, with each result's cell drawn by `cell` (--dashboard adds trends)
+pub fn grid(results: &[HostResult], cell: impl Fn(&HostResult) -> String) -> Table {
+    let mut ports: Vec<u16> = results.iter().filter_map(|r| column(r).0).collect();
+    ports.sort_unstable();
+    ports.dedup();
+    let pings: Vec<&str> = ["icmp", "arp", "uds", "quic"]
+        .into_iter()
+        .filter(|&test_type| results.iter().any(|r| column(r) == (None, test_type)))
+        .collect();
+
+    // Rows in the order hosts were first reported
+    let mut rows: Vec<String> = Vec::new();
+    let mut seen = HashSet::new();
+    let mut cells: HashMap<(String, (Option<u16>, &str)), &HostResult> = HashMap::new();
+    for result in results {
+        let label = row_label(result);
+        if seen.insert(label.clone()) {
+            rows.push(label.clone());
+        }
+        cells.insert((label, column(result)), result);
+    }
+
+    let mut header = vec!["Host".to_string()];
+    header.extend(ports.iter().map(|p| p.to_string()));
+    header.extend(pings.iter().map(|test_type| test_type.to_uppercase()));
+
+    let mut table = Table::new();
+    table
+        .load_preset(if icons::ascii() { ASCII_FULL_CONDENSED } else { UTF8_FULL_CONDENSED })
+        .set_content_arrangement(ContentArrangement::Dynamic)
+        .set_header(header);
+
+    for label in &rows {
+        let find = |column: (Option<u16>, &str)| {
+            cells
+                .get(&(label.clone(), column))
+                .map(|r| cell(r))
+                .unwrap_or_else(|| icons::get("empty").to_string())
+        };
+
+        let mut row = vec![label.clone()];
+        row.extend(ports.iter().map(|&p| find((Some(p), ""))));
+        row.extend(pings.iter().map(|&test_type| find((None, test_type))));
+        table.add_row(row);
+    }
+
+    for column in table.column_iter_mut().skip(1) {
+        column.set_cell_alignment(CellAlignment::Center);
+    }
+    table
+}
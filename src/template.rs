@@ -0,0 +1,195 @@
+use crate::HostResult;
+
+/// Placeholders --format and --alert-command understand
+const FIELDS: &[&str] = &[
+    "host",
+    "port",
+    "test_type",
+    "status",
+    "success",
+    "attempts",
+    "success_rate",
+    "avg_ms",
+    "min_ms",
+    "max_ms",
+    "jitter_ms",
+    "addr",
+    "error",
+];
+
+/// Command placeholders pasted in shell-quoted. The rest are values ring makes itself, a number,
+/// a name from a fixed set or a parsed address, which can't carry shell syntax; the host is
+/// whatever the command line, a --targets file or --targets-url said, and the error can hold
+/// bytes from the network.
+const QUOTED_FIELDS: &[&str] = &["host", "error"];
+
+#[derive(Debug, Clone)]
+enum Piece {
+    Text(String),
+    Field(&'static str),
+    /// A field pasted into a shell command as one quoted word
+    Quoted(&'static str, Quoting),
+}
+
+/// Where a placeholder sits in a shell command, which decides how its value is quoted: sh's
+/// single quotes, double quotes and backslash, or cmd's double quotes and caret
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Quoting {
+    Bare,
+    Single,
+    Double,
+    BareEscape,
+    DoubleEscape,
+}
+
+impl Quoting {
+    fn after(self, c: char) -> Quoting {
+        use Quoting::*;
+        match (self, c) {
+            (BareEscape, _) => Bare,
+            (DoubleEscape, _) => Double,
+            (Bare, '\'') if !cfg!(windows) => Single,
+            (Single, '\'') => Bare,
+            (Bare, '"') => Double,
+            (Double, '"') => Bare,
+            (Bare, c) if c == ESCAPE => BareEscape,
+            (Double, '\\') if !cfg!(windows) => DoubleEscape,
+            (state, _) => state,
+        }
+    }
+
+    /// The value as one word, leaving the command's quoting as it was. In sh it's single-quoted,
+    /// closing and reopening double quotes around it and with `'\''` for a quote; in cmd it's
+    /// double-quoted, with `""` for a quote.
+    fn quote(self, value: &str) -> String {
+        if cfg!(windows) {
+            let value = value.replace('"', "\"\"");
+            match self {
+                Quoting::Double => value,
+                _ => format!("\"{}\"", value),
+            }
+        } else {
+            let value = value.replace('\'', "'\\''");
+            match self {
+                Quoting::Single => value,
+                Quoting::Double => format!("\"'{}'\"", value),
+                _ => format!("'{}'", value),
+            }
+        }
+    }
+}
+
+/// The character that escapes the next one in the shell --alert-command runs in
+const ESCAPE: char = if cfg!(windows) { '^' } else { '\\' };
+
+/// A parsed --format template: literal text with `{field}` placeholders
+#[derive(Debug, Clone)]
+pub struct Template(Vec<Piece>);
+
+/// Parse `--format`. `{{` and `}}` stand for literal braces; any other brace must open or close
+/// a known placeholder. The --probe-send escapes work too, so `\t` gives tab-separated columns.
+pub fn parse_template(s: &str) -> Result<Template, String> {
+    parse(&String::from_utf8_lossy(&crate::unescape(s)), FIELDS, &[])
+}
+
+/// Parse `--alert-command`. Braces work as in `--format`, but escapes are left to the shell, and
+/// `{host}` and `{error}` are quoted for wherever they are in the command, so
+/// `notify-send 'host down: {host}'` keeps the host inside the quotes.
+pub fn parse_command(s: &str) -> Result<Template, String> {
+    parse(s, FIELDS, QUOTED_FIELDS)
+}
+
+fn parse(s: &str, fields: &[&'static str], quoted: &[&str]) -> Result<Template, String> {
+    let mut pieces = Vec::new();
+    let mut text = String::new();
+    let mut quoting = Quoting::Bare;
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                text.push('{');
+                quoting = quoting.after('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                text.push('}');
+                quoting = quoting.after('}');
+            }
+            '{' => {
+                let mut name = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => name.push(c),
+                        None => return Err(format!("unclosed placeholder '{{{}'", name)),
+                    }
+                }
+                let field = fields
+                    .iter()
+                    .find(|&&f| f == name)
+                    .ok_or_else(|| format!("unknown placeholder '{{{}}}'; expected one of {}", name, fields.join(", ")))?;
+                if !text.is_empty() {
+                    pieces.push(Piece::Text(std::mem::take(&mut text)));
+                }
+                if !quoted.contains(field) {
+                    pieces.push(Piece::Field(field));
+                    // Its value is plain characters, which only end an escape
+                    quoting = quoting.after('-');
+                } else if matches!(quoting, Quoting::BareEscape | Quoting::DoubleEscape) {
+                    return Err(format!("{{{}}} can't come straight after a '{}'", field, ESCAPE));
+                } else {
+                    pieces.push(Piece::Quoted(field, quoting));
+                }
+            }
+            '}' => return Err("unmatched '}'; write '}}' for a literal brace".to_string()),
+            _ => {
+                text.push(c);
+                quoting = quoting.after(c);
+            }
+        }
+    }
+    if !text.is_empty() {
+        pieces.push(Piece::Text(text));
+    }
+    Ok(Template(pieces))
+}
+
+/// `-` stands in for values a result doesn't have, so columns stay aligned for `cut` and `awk`
+fn ms(value: Option<f64>) -> String {
+    value.map_or_else(|| "-".to_string(), |ms| format!("{:.1}", ms))
+}
+
+fn value(result: &HostResult, field: &str) -> String {
+    match field {
+        "host" => result.host.clone(),
+        "port" => result.port.map_or_else(|| "-".to_string(), |p| p.to_string()),
+        "test_type" => result.test_type.clone(),
+        "status" => result.status.clone(),
+        "success" => result.successful.to_string(),
+        "attempts" => result.attempts.to_string(),
+        "success_rate" => format!("{:.0}%", result.success_rate * 100.0),
+        "avg_ms" => ms(result.avg_response_time_ms),
+        "min_ms" => ms(result.min_response_time_ms),
+        "max_ms" => ms(result.max_response_time_ms),
+        "jitter_ms" => ms(result.jitter_ms),
+        "addr" => result.resolved_addr.map_or_else(|| "-".to_string(), |a| a.to_string()),
+        "error" => result.error.clone().unwrap_or_else(|| "-".to_string()),
+        _ => unreachable!("placeholders are checked when the template is parsed"),
+    }
+}
+
+impl Template {
+    /// Expand the template for one result
+    pub fn render(&self, result: &HostResult) -> String {
+        let mut line = String::new();
+        for piece in &self.0 {
+            match piece {
+                Piece::Text(text) => line.push_str(text),
+                Piece::Field(field) => line.push_str(&value(result, field)),
+                Piece::Quoted(field, quoting) => line.push_str(&quoting.quote(&value(result, field))),
+            }
+        }
+        line
+    }
+}
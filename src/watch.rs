@@ -0,0 +1,217 @@
+use crate::scoped::ScopedIp;
+use crate::template::Template;
+use crate::{icons, report_warning, HostResult};
+use colored::*;
+use serde::Serialize;
+use std::collections::HashMap;
+use tokio::task::JoinHandle;
+type TargetKey = (String, Option<u16>, String, Option<ScopedIp>);
+
+/// A target whose status, or --max-response-bytes content, differs from the previous scan
+#[derive(Serialize, Debug, Clone)]
+pub struct Transition {
+    pub host: String,
+    pub port: Option<u16>,
+    pub test_type: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resolved_addr: Option<ScopedIp>,
+    pub old_status: String,
+    pub new_status: String,
+    /// Both content hashes, when both scans have one and they differ
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub old_content_sha256: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub new_content_sha256: Option<String>,
+    pub timestamp: String,
+}
+
+/// Remembers the last status and content hash of every (host, port, test_type) across
+/// continuous-mode scans
+#[derive(Debug, Default)]
+pub struct StatusTracker {
+    previous: HashMap<TargetKey, (String, Option<String>)>,
+}
+
+impl StatusTracker {
+    /// Record this scan's statuses and return every target whose status or content hash changed.
+    /// Targets seen for the first time are not transitions, and neither is a hash appearing or
+    /// going away, which comes with the probe succeeding or failing.
+    pub fn update(&mut self, results: &[HostResult], timestamp: &str) -> Vec<Transition> {
+        let mut transitions = Vec::new();
+
+        for result in results {
+            let key = (result.host.clone(), result.port, result.test_type.clone(), result.resolved_addr);
+            let current = (result.status.clone(), result.content_sha256.clone());
+            if let Some((old_status, old_hash)) = self.previous.insert(key, current) {
+                let content_changed = matches!((&old_hash, &result.content_sha256), (Some(old), Some(new)) if old != new);
+                if old_status != result.status || content_changed {
+                    transitions.push(Transition {
+                        host: result.host.clone(),
+                        port: result.port,
+                        test_type: result.test_type.clone(),
+                        resolved_addr: result.resolved_addr,
+                        old_status,
+                        new_status: result.status.clone(),
+                        old_content_sha256: old_hash.filter(|_| content_changed),
+                        new_content_sha256: result.content_sha256.clone().filter(|_| content_changed),
+                        timestamp: timestamp.to_string(),
+                    });
+                }
+            }
+        }
+
+        transitions
+    }
+}
+
+/// What --only-changed compares: each target's status and latency band in the previous scan
+#[derive(Debug, Default)]
+pub struct ChangeTracker {
+    previous: Option<HashMap<TargetKey, (String, u8)>>,
+    /// Scans in a row without a change
+    quiet_scans: u32,
+}
+
+impl ChangeTracker {
+    /// Record this scan and return the results whose status or `band` differ from the scan
+    /// before, targets new since then included. The first scan has nothing to compare with,
+    /// so it gets `None`.
+    pub fn update<'a>(&mut self, results: &'a [HostResult], band: impl Fn(&HostResult) -> u8) -> Option<Vec<&'a HostResult>> {
+        let key = |r: &HostResult| (r.host.clone(), r.port, r.test_type.clone(), r.resolved_addr);
+        let current: HashMap<TargetKey, (String, u8)> = results.iter().map(|r| (key(r), (r.status.clone(), band(r)))).collect();
+        let changed = self.previous.as_ref().map(|previous| {
+            results.iter().filter(|r| previous.get(&key(r)) != current.get(&key(r))).collect::<Vec<_>>()
+        });
+        self.quiet_scans = match &changed {
+            Some(changed) if changed.is_empty() => self.quiet_scans + 1,
+            _ => 0,
+        };
+        self.previous = Some(current);
+        changed
+    }
+
+    pub fn quiet_scans(&self) -> u32 {
+        self.quiet_scans
+    }
+}
+
+/// Exponentially weighted moving average of each target's latency across continuous-mode scans
+#[derive(Debug)]
+pub struct EwmaTracker {
+    alpha: f64,
+    averages: HashMap<TargetKey, f64>,
+}
+
+impl EwmaTracker {
+    pub fn new(alpha: f64) -> Self {
+        EwmaTracker { alpha, averages: HashMap::new() }
+    }
+
+    /// Fold this scan's averages in and store the updated EWMA on each result. The first scan
+    /// seeds the average; scans where a target had no successful samples leave it untouched.
+    pub fn update(&mut self, results: &mut [HostResult]) {
+        for result in results {
+            let Some(avg) = result.avg_response_time_ms else {
+                continue;
+            };
+            let key = (result.host.clone(), result.port, result.test_type.clone(), result.resolved_addr);
+            let ewma = self
+                .averages
+                .entry(key)
+                .and_modify(|prev| *prev = self.alpha * avg + (1.0 - self.alpha) * *prev)
+                .or_insert(avg);
+            result.ewma_response_time_ms = Some(*ewma);
+        }
+    }
+}
+
+pub fn print_transitions(transitions: &[Transition]) {
+    for t in transitions {
+        let mut target = match t.port {
+            Some(port) => format!("{}:{}", t.host.blue(), port.to_string().yellow()),
+            None => format!("{} ({})", t.host.blue(), t.test_type.to_uppercase()),
+        };
+        if let Some(addr) = t.resolved_addr {
+            target.push_str(&format!(" @ {}", addr).dimmed().to_string());
+        }
+        let new_status = match t.new_status.as_str() {
+            "up" => t.new_status.green(),
+            "down" | "filtered" => t.new_status.red(),
+            _ => t.new_status.yellow(),
+        };
+        // Hashes are shortened the way git shortens commits; the full ones are in JSON and webhooks
+        let content = match (&t.old_content_sha256, &t.new_content_sha256) {
+            (Some(old), Some(new)) => format!("content {} {} {}", &old[..12], icons::get("arrow"), &new[..12]),
+            _ => String::new(),
+        };
+        let (icon, arrow) = (icons::get("change"), icons::get("arrow"));
+        if t.old_status == t.new_status {
+            println!("{} {} {}", icon, target, content.yellow());
+        } else if content.is_empty() {
+            println!("{} {} {} {} {}", icon, target, t.old_status, arrow, new_status);
+        } else {
+            println!("{} {} {} {} {}, {}", icon, target, t.old_status, arrow, new_status, content.yellow());
+        }
+    }
+}
+
+/// POST each transition to the webhook in the background; delivery failures are reported but never fatal
+pub fn send_webhooks(client: &reqwest::Client, url: &str, transitions: Vec<Transition>) {
+    for transition in transitions {
+        let client = client.clone();
+        let url = url.to_string();
+        tokio::spawn(async move {
+            let outcome = client.post(&url).json(&transition).send().await.and_then(|r| r.error_for_status());
+            if let Err(e) = outcome {
+                report_warning(&format!("webhook delivery to {} failed: {}", url, e));
+            }
+        });
+    }
+}
+
+/// --alert-command: run the command once for each result, in the background. The result is
+/// also in the environment (RING_HOST, RING_PORT, RING_TEST_TYPE, RING_STATUS, RING_ADDR,
+/// RING_ERROR) for scripts that would rather not have values pasted into them. A command that
+/// can't start or exits non-zero is reported but never stops monitoring.
+pub fn run_alert_commands(command: &Template, results: &[&HostResult]) -> Vec<JoinHandle<()>> {
+    results
+        .iter()
+        .map(|result| {
+            let line = command.render(result);
+            let target = match result.port {
+                Some(port) => format!("{}:{}", result.host, port),
+                None => format!("{} ({})", result.host, result.test_type.to_uppercase()),
+            };
+            let mut cmd = if cfg!(windows) {
+                let mut cmd = tokio::process::Command::new("cmd");
+                cmd.arg("/C").arg(&line);
+                cmd
+            } else {
+                let mut cmd = tokio::process::Command::new("sh");
+                cmd.arg("-c").arg(&line);
+                cmd
+            };
+            cmd.env("RING_HOST", &result.host)
+                .env("RING_PORT", result.port.map(|p| p.to_string()).unwrap_or_default())
+                .env("RING_TEST_TYPE", &result.test_type)
+                .env("RING_STATUS", &result.status)
+                .env("RING_ADDR", result.resolved_addr.map(|a| a.to_string()).unwrap_or_default())
+                .env("RING_ERROR", result.error.as_deref().unwrap_or_default())
+                .stdin(std::process::Stdio::null());
+            tokio::spawn(async move {
+                match cmd.output().await {
+                    Ok(output) if output.status.success() => {}
+                    Ok(output) => {
+                        let stderr = String::from_utf8_lossy(&output.stderr);
+                        let detail = match stderr.lines().next() {
+                            Some(line) if !line.trim().is_empty() => format!(": {}", line.trim()),
+                            _ => String::new(),
+                        };
+                        report_warning(&format!("--alert-command for {} failed ({}){}", target, output.status, detail));
+                    }
+                    Err(e) => report_warning(&format!("--alert-command for {} couldn't start: {}", target, e)),
+                }
+            })
+        })
+        .collect()
+}